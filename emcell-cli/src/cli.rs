@@ -0,0 +1,321 @@
+use std::ffi::OsString;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+
+use emcell::Slot;
+
+use crate::client::Client;
+use crate::elf::CellElf;
+use crate::header::{hex, ParsedHeader};
+use crate::image::FlashImage;
+use crate::patch::{check_image_info, patch};
+use crate::sign::{check_signature, load_key, public_key_hex, sign};
+use crate::verify::{cross_check, verify, CellsDefs};
+
+#[derive(Parser)]
+#[command(name = "emcell", version, about = "Host tools for emcell cell images")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print header, layout and version of the cells in the given images
+    Inspect {
+        /// Linked cell ELF files
+        #[arg(required = true)]
+        elfs: Vec<PathBuf>,
+    },
+    /// Check that images are consistent with each other and with the cells definitions
+    Verify {
+        /// Linked cell ELF files
+        #[arg(required = true)]
+        elfs: Vec<PathBuf>,
+    },
+    /// Merge cells into a single flashable image, gaps are filled with 0xFF
+    Merge {
+        /// Linked cell ELF files
+        #[arg(required = true)]
+        elfs: Vec<PathBuf>,
+        /// Output file
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Output format, detected from the output file extension by default
+        #[arg(short, long)]
+        format: Option<Format>,
+        /// UF2 family ID, the bootloader of the target device expects. AT32F437 has no registered ID, so it is
+        /// defined by the bootloader
+        #[arg(long, value_parser = parse_u32)]
+        family_id: Option<u32>,
+    },
+    /// Fill image length and checksums in the header of a linked cell
+    Patch {
+        /// Linked cell ELF file
+        elf: PathBuf,
+        /// Output ELF file, the input file is patched in place by default
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Also write the patched cell as a binary image
+        #[arg(long)]
+        bin: Option<PathBuf>,
+    },
+    /// Fill image length and checksums, then sign the cell with an Ed25519 key
+    Sign {
+        /// Linked cell ELF file
+        elf: PathBuf,
+        /// Signing key: 32-byte seed, raw or as 64 hex digits
+        #[arg(short, long)]
+        key: PathBuf,
+        /// Output ELF file, the input file is signed in place by default
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Also write the signed cell as a binary image
+        #[arg(long)]
+        bin: Option<PathBuf>,
+    },
+    /// Print public key for `#[signed(public_key = "...")]`
+    PublicKey {
+        /// Signing key: 32-byte seed, raw or as 64 hex digits
+        #[arg(short, long)]
+        key: PathBuf,
+    },
+    /// Write a patched or signed cell into the device through `emcell::protocol::UpdateServer`
+    Update {
+        /// Linked cell ELF file
+        elf: PathBuf,
+        /// Serial port or any other file connected to the device, opened non-blocking. Configure baud rate beforehand,
+        /// e.g. with `stty`
+        #[arg(short, long)]
+        port: PathBuf,
+    },
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum Format {
+    Bin,
+    Ihex,
+    Srec,
+    Uf2,
+}
+
+impl Format {
+    fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "bin" => Some(Format::Bin),
+            "hex" | "ihex" => Some(Format::Ihex),
+            "srec" | "s19" | "s28" | "s37" | "mot" => Some(Format::Srec),
+            "uf2" => Some(Format::Uf2),
+            _ => None,
+        }
+    }
+}
+
+/// Entry point of the `emcell` tool.
+///
+/// `defs` are required to check images against cells definitions. The standalone `emcell` binary does not know them,
+/// pass `Some(CellsDefs::of::<cells_defs::PrimaryCell>())` from your own host binary to enable these checks.
+pub fn main(defs: Option<CellsDefs>) {
+    if let Err(e) = run(std::env::args_os(), defs.as_ref()) {
+        eprintln!("error: {:#}", e);
+        std::process::exit(1);
+    }
+}
+
+pub fn run(args: impl IntoIterator<Item = impl Into<OsString> + Clone>, defs: Option<&CellsDefs>) -> Result<()> {
+    let cli = Cli::parse_from(args);
+
+    match cli.command {
+        Command::Inspect { elfs } => {
+            for path in elfs {
+                let elf = CellElf::load(&path)?;
+                print_cell(&elf, defs);
+            }
+            Ok(())
+        }
+        Command::Verify { elfs } => {
+            let elfs = elfs.iter().map(CellElf::load).collect::<Result<Vec<_>>>()?;
+
+            let mut failed = false;
+            for elf in &elfs {
+                let mut problems = match defs {
+                    Some(defs) => verify(elf, defs),
+                    None => Vec::new(),
+                };
+                problems.extend(check_image_info(elf, defs));
+                problems.extend(check_signature(elf, defs));
+                failed |= report(&elf.display_name(), &problems);
+            }
+            failed |= report("images", &cross_check(&elfs));
+
+            if defs.is_none() {
+                println!("note: cells definitions are not available, only consistency between images was checked");
+            }
+            if failed {
+                bail!("verification failed");
+            }
+            Ok(())
+        }
+        Command::Merge { elfs, output, format, family_id } => {
+            let Some(format) = format.or_else(|| Format::from_path(&output)) else {
+                bail!("cannot detect output format of {}, use --format", output.display());
+            };
+            let elfs = elfs.iter().map(CellElf::load).collect::<Result<Vec<_>>>()?;
+            if defs.is_none() {
+                println!("note: cells definitions are not available, flash regions of the cells are not checked");
+            }
+
+            let image = FlashImage::merge(&elfs, defs)?;
+            let contents = match format {
+                Format::Bin => image.data.clone(),
+                Format::Ihex => image.to_ihex().into_bytes(),
+                Format::Srec => image.to_srec().into_bytes(),
+                Format::Uf2 => image.to_uf2(family_id),
+            };
+            std::fs::write(&output, contents).with_context(|| format!("Failed to write {}", output.display()))?;
+            println!("{}: 0x{:08X}..0x{:08X}", output.display(), image.start, image.end());
+            Ok(())
+        }
+        Command::Patch { elf, output, bin } => {
+            let (patched, info) = patch(&CellElf::load(&elf)?, defs)?;
+            println!("image length {} bytes, crc32 0x{:08X}", info.length, info.crc32);
+            write_cell(&patched, output.as_ref().unwrap_or(&elf), bin.as_ref(), defs)
+        }
+        Command::Sign { elf, key, output, bin } => {
+            let key = load_key(key)?;
+            let signed = sign(&CellElf::load(&elf)?, defs, &key)?;
+            println!("signed with public key {}", public_key_hex(&key));
+            write_cell(&signed, output.as_ref().unwrap_or(&elf), bin.as_ref(), defs)
+        }
+        Command::PublicKey { key } => {
+            println!("{}", public_key_hex(&load_key(key)?));
+            Ok(())
+        }
+        Command::Update { elf, port } => {
+            let elf = CellElf::load(&elf)?;
+            let (cell, slot) = update_target(&elf, defs)?;
+            let image = FlashImage::merge(std::slice::from_ref(&elf), defs)?;
+
+            let transport = open_port(&port).with_context(|| format!("Failed to open {}", port.display()))?;
+            let mut client = Client::new(transport);
+            println!("writing {} bytes of {} into slot {:?}", image.data.len(), cell, slot);
+            client.upload(&cell, slot, &image.data, |sent| {
+                print!("\r{}/{} bytes", sent, image.data.len());
+                let _ = std::io::stdout().flush();
+            })?;
+            println!("\n{}: updated", cell);
+            Ok(())
+        }
+    }
+}
+
+/// Open the port for [`Client`], reads must not block forever
+fn open_port(port: &Path) -> std::io::Result<std::fs::File> {
+    let mut options = std::fs::OpenOptions::new();
+    options.read(true).write(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.custom_flags(libc::O_NONBLOCK | libc::O_NOCTTY);
+    }
+    options.open(port)
+}
+
+/// Cell name and slot of a linked cell image
+fn update_target(elf: &CellElf, defs: Option<&CellsDefs>) -> Result<(String, Slot)> {
+    let Some(location) = &elf.header else {
+        bail!("{}: no cell header found", elf.display_name());
+    };
+    let slot = match defs.and_then(|defs| defs.cell(&location.cell_name).map(|meta| (defs, meta))) {
+        Some((defs, meta)) => defs.slot_flash(meta, location.addr).map(|(slot, _)| slot),
+        None => elf.header_bytes()
+            .and_then(|bytes| ParsedHeader::parse(bytes).ok())
+            .and_then(|header| Slot::from_u32(header.linked_slot)),
+    };
+    let Some(slot) = slot else {
+        bail!("{}: cannot determine the slot of {}", elf.display_name(), location.cell_name);
+    };
+    Ok((location.cell_name.clone(), slot))
+}
+
+fn write_cell(elf: &CellElf, output: &Path, bin: Option<&PathBuf>, defs: Option<&CellsDefs>) -> Result<()> {
+    std::fs::write(output, &elf.data).with_context(|| format!("Failed to write {}", output.display()))?;
+    println!("{}: written", output.display());
+
+    if let Some(bin) = bin {
+        let image = FlashImage::merge(std::slice::from_ref(elf), defs)?;
+        std::fs::write(bin, &image.data).with_context(|| format!("Failed to write {}", bin.display()))?;
+        println!("{}: 0x{:08X}..0x{:08X}", bin.display(), image.start, image.end());
+    }
+    Ok(())
+}
+
+fn parse_u32(s: &str) -> Result<u32, std::num::ParseIntError> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+}
+
+fn report(name: &str, problems: &[String]) -> bool {
+    if problems.is_empty() {
+        println!("{}: OK", name);
+        return false;
+    }
+    println!("{}: FAILED", name);
+    for problem in problems {
+        println!("  - {}", problem);
+    }
+    true
+}
+
+fn print_cell(elf: &CellElf, defs: Option<&CellsDefs>) {
+    println!("{}", elf.display_name());
+
+    let Some(location) = &elf.header else {
+        println!("  no cell header found");
+        return;
+    };
+    println!("  cell        {}", location.cell_name);
+    println!("  header      0x{:08X}..0x{:08X}", location.addr, location.addr + location.size);
+
+    if let Some(meta) = defs.and_then(|defs| defs.cell(&location.cell_name).map(|meta| (defs, meta))) {
+        let (defs, meta) = meta;
+        match defs.slot_flash(meta, location.addr) {
+            Some((slot, region)) => println!("  flash       0x{:08X}..0x{:08X} (slot {:?})", region.start_flash, region.end_flash, slot),
+            None => println!("  flash       header is not at the start of any slot"),
+        }
+        println!("  ram         0x{:08X}..0x{:08X}", meta.absolute_ram_start(&defs.device), meta.absolute_ram_end(&defs.device));
+    }
+    let used_flash = elf.segments.iter().map(|segment| segment.data.len()).sum::<usize>();
+    println!("  flash used  {} bytes", used_flash);
+
+    match elf.header_bytes().map(ParsedHeader::parse) {
+        Some(Ok(header)) => {
+            println!("  signature   0x{:08X}", header.signature);
+            println!("  hash        {}", hex(&header.struct_sha256));
+            println!("  abi         version {}, {} fields", header.abi_minor, header.field_count);
+            println!("  version     {}", header.version);
+            println!("  security    counter {}", header.security_counter);
+            println!("  linked for  slot {}", match header.linked_slot { 0 => "A", 1 => "B", _ => "?" });
+            if header.version.build_timestamp != 0 {
+                println!("  built at    {} (unix time)", header.version.build_timestamp);
+            }
+            if header.image.is_patched() {
+                println!("  image       {} bytes, crc32 0x{:08X}, sha256 {}", header.image.length, header.image.crc32, hex(&header.image.sha256));
+            }
+            else {
+                println!("  image       length and checksum are not filled");
+            }
+        }
+        Some(Err(e)) => println!("  invalid header: {}", e),
+        None => println!("  header data is missing"),
+    }
+
+    for (name, addr) in &elf.extern_headers {
+        println!("  uses        {} (header at 0x{:08X})", name, addr);
+    }
+}
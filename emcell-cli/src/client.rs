@@ -0,0 +1,140 @@
+use std::io::{ErrorKind, Read, Write};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use emcell::protocol::{encode_frame, FrameDecoder, Kind, ResultCode, Status, MAX_DATA, MAX_FRAME};
+use emcell::Slot;
+
+/// Number of attempts for a request, which frame was broken or lost on the way
+const ATTEMPTS: usize = 3;
+
+/// Time to wait for a response, before the request is resent. Erasing a slot on `Begin` takes the longest
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Delay between reads of a transport, which has no data yet
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Host side of the `emcell::protocol` update protocol, over any byte transport.
+///
+/// Reads of the transport must not block forever: use a non-blocking transport (reads fail with
+/// [`ErrorKind::WouldBlock`]) or one with a read timeout ([`ErrorKind::TimedOut`]). A request without a response within
+/// the timeout is resent, like one with a broken frame.
+pub struct Client<T: Read + Write> {
+    transport: T,
+    decoder: FrameDecoder,
+    timeout: Duration,
+}
+
+impl<T: Read + Write> Client<T> {
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            decoder: FrameDecoder::new(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Time to wait for a response, [`DEFAULT_TIMEOUT`] by default
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    /// Current state of the update on the device
+    pub fn query(&mut self) -> Result<Status> {
+        self.request(Kind::Query, &[])
+    }
+
+    /// Write `image` (slot contents, as written by `emcell patch --bin`) into `slot` of cell `cell`.
+    /// `progress` is called with the number of bytes sent
+    pub fn upload(&mut self, cell: &str, slot: Slot, image: &[u8], mut progress: impl FnMut(usize)) -> Result<()> {
+        let mut begin = vec![slot as u8];
+        begin.extend_from_slice(&(image.len() as u32).to_le_bytes());
+        begin.extend_from_slice(cell.as_bytes());
+        self.request(Kind::Begin, &begin).context("Failed to start update")?;
+
+        for (i, chunk) in image.chunks(MAX_DATA).enumerate() {
+            let offset = i * MAX_DATA;
+            let mut data = (offset as u32).to_le_bytes().to_vec();
+            data.extend_from_slice(chunk);
+            self.request(Kind::Data, &data).with_context(|| format!("Failed to write image at offset {}", offset))?;
+            progress(offset + chunk.len());
+        }
+
+        self.request(Kind::End, &[]).context("Image was rejected")?;
+        Ok(())
+    }
+
+    /// Send a request and wait for its status. Requests with broken or lost frames are resent
+    fn request(&mut self, kind: Kind, payload: &[u8]) -> Result<Status> {
+        let mut frame = [0; MAX_FRAME];
+        let len = encode_frame(kind as u8, payload, &mut frame);
+
+        for _ in 0..ATTEMPTS {
+            self.send(&frame[..len]).context("Failed to send request")?;
+
+            let status = self.receive_status()?;
+            match status {
+                Some(status) if status.result == ResultCode::BadFrame => continue,
+                Some(status) if status.request != kind as u8 => bail!("unexpected response to request {:?}", kind),
+                Some(status) if status.result != ResultCode::Ok => bail!("device: {}", status.result),
+                Some(status) => return Ok(status),
+                None => continue,
+            }
+        }
+        bail!("no valid response to request {:?} after {} attempts", kind, ATTEMPTS)
+    }
+
+    /// Write the whole frame, waiting for a non-blocking transport to accept it
+    fn send(&mut self, mut frame: &[u8]) -> std::io::Result<()> {
+        let deadline = Instant::now() + self.timeout;
+        while !frame.is_empty() {
+            match self.transport.write(frame) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => frame = &frame[n..],
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) if is_timeout(&e) && Instant::now() < deadline => std::thread::sleep(POLL_INTERVAL),
+                Err(e) => return Err(e),
+            }
+        }
+        self.transport.flush()
+    }
+
+    /// Wait for the next status frame. `None` if a broken frame was received or no status arrived within the timeout
+    fn receive_status(&mut self) -> Result<Option<Status>> {
+        let deadline = Instant::now() + self.timeout;
+        let mut byte = [0];
+        loop {
+            if Instant::now() >= deadline {
+                // drop the partial frame, so it does not swallow the response to the resent request
+                self.decoder = FrameDecoder::new();
+                return Ok(None);
+            }
+            match self.transport.read(&mut byte) {
+                Ok(0) => bail!("transport closed"),
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) if is_timeout(&e) => {
+                    std::thread::sleep(POLL_INTERVAL);
+                    continue;
+                }
+                Err(e) => return Err(e).context("Failed to receive response"),
+            }
+            match self.decoder.push(byte[0]) {
+                None => continue,
+                Some(Err(_)) => return Ok(None),
+                Some(Ok(frame)) if frame.kind == Kind::Status as u8 => return Ok(Status::from_bytes(frame.payload())),
+                Some(Ok(_)) => continue,
+            }
+        }
+    }
+}
+
+/// No data yet on a non-blocking transport or a read timeout
+fn is_timeout(e: &std::io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
@@ -0,0 +1,144 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use object::elf::PT_LOAD;
+use object::read::elf::{ElfFile32, ProgramHeader};
+use object::{Endianness, Object, ObjectSection, ObjectSymbol, SectionKind};
+
+/// Loadable part of the image
+pub struct Segment {
+    /// Load address (LMA), where the data is located in flash
+    pub paddr: u64,
+    /// Run address (VMA)
+    pub vaddr: u64,
+    /// Size in memory, may be larger than data for zero-initialized sections
+    pub mem_size: u64,
+    pub data: Vec<u8>,
+}
+
+pub struct Section {
+    pub name: String,
+    pub addr: u64,
+    pub size: u64,
+    /// Location of section data in the ELF file, `None` for sections without data (e.g. `.bss`)
+    pub file_range: Option<(u64, u64)>,
+}
+
+/// Header of the cell, which is provided by this image
+pub struct HeaderLocation {
+    pub cell_name: String,
+    pub addr: u64,
+    pub size: u64,
+    pub file_offset: u64,
+}
+
+/// Linked cell image
+pub struct CellElf {
+    pub path: PathBuf,
+    pub data: Vec<u8>,
+    pub segments: Vec<Segment>,
+    pub sections: Vec<Section>,
+    pub header: Option<HeaderLocation>,
+    /// Headers of other cells, referenced by this image: cell name and header address
+    pub extern_headers: Vec<(String, u64)>,
+}
+
+impl CellElf {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(path, data)
+    }
+
+    pub fn parse(path: impl AsRef<Path>, data: Vec<u8>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let elf = ElfFile32::<Endianness>::parse(&*data)
+            .with_context(|| format!("{} is not a 32-bit ELF file", path.display()))?;
+        let endian = elf.endian();
+
+        let mut segments = Vec::new();
+        for ph in elf.elf_program_headers() {
+            if ph.p_type(endian) != PT_LOAD || ph.p_filesz(endian) == 0 {
+                continue;
+            }
+            let segment_data = ph.data(endian, &*data)
+                .map_err(|_| anyhow::anyhow!("{}: invalid program header", path.display()))?;
+            segments.push(Segment {
+                paddr: ph.p_paddr(endian) as u64,
+                vaddr: ph.p_vaddr(endian) as u64,
+                mem_size: ph.p_memsz(endian) as u64,
+                data: segment_data.to_vec(),
+            });
+        }
+
+        let sections = elf.sections()
+            .filter(|section| section.address() != 0 && section.size() != 0)
+            .map(|section| Section {
+                name: section.name().unwrap_or("").to_string(),
+                addr: section.address(),
+                size: section.size(),
+                file_range: match section.kind() {
+                    SectionKind::UninitializedData | SectionKind::UninitializedTls => None,
+                    _ => section.file_range(),
+                },
+            })
+            .collect::<Vec<_>>();
+
+        let mut header = None;
+        let mut extern_headers = Vec::new();
+        for symbol in elf.symbols() {
+            let Some(cell_name) = symbol.name().ok()
+                .and_then(|name| name.strip_prefix("_emcell_"))
+                .and_then(|name| name.strip_suffix("_internal")) else {
+                continue;
+            };
+
+            // header of the current cell starts a section with data (`.CUR_HEADER` from the linker script generated by
+            // `build_rs`), headers of other cells are only addresses
+            let header_section = sections.iter()
+                .find(|section| section.addr == symbol.address() && section.file_range.is_some());
+            match header_section {
+                Some(section) if !symbol.is_undefined() => {
+                    header = Some(HeaderLocation {
+                        cell_name: cell_name.to_string(),
+                        addr: section.addr,
+                        size: section.size,
+                        file_offset: section.file_range.map(|(offset, _)| offset).unwrap_or(0),
+                    });
+                }
+                _ => extern_headers.push((cell_name.to_string(), symbol.address())),
+            }
+        }
+        extern_headers.sort();
+        extern_headers.dedup();
+
+        Ok(Self {
+            path,
+            data,
+            segments,
+            sections,
+            header,
+            extern_headers,
+        })
+    }
+
+    /// Read `len` bytes at run address `addr` from section data
+    pub fn read(&self, addr: u64, len: usize) -> Option<&[u8]> {
+        self.sections.iter()
+            .filter(|section| addr >= section.addr && addr + len as u64 <= section.addr + section.size)
+            .find_map(|section| {
+                let (offset, _) = section.file_range?;
+                let start = (offset + addr - section.addr) as usize;
+                self.data.get(start..start + len)
+            })
+    }
+
+    pub fn header_bytes(&self) -> Option<&[u8]> {
+        let header = self.header.as_ref()?;
+        self.read(header.addr, header.size as usize)
+    }
+
+    pub fn display_name(&self) -> String {
+        self.path.display().to_string()
+    }
+}
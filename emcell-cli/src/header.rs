@@ -0,0 +1,67 @@
+use anyhow::{bail, Result};
+use emcell::meta::header_layout as layout;
+use emcell::meta::{CellVersion, ImageInfo};
+
+/// Cell header, read from an image. Only the fields inserted by `#[cell]` are parsed
+pub struct ParsedHeader {
+    pub signature: u32,
+    pub init: u32,
+    pub struct_sha256: [u8; 32],
+    pub abi_minor: u32,
+    pub field_count: u32,
+    pub field_fingerprints_addr: u32,
+    pub version: CellVersion,
+    pub image: ImageInfo,
+    pub security_counter: u32,
+    pub linked_slot: u32,
+}
+
+impl ParsedHeader {
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < layout::USER_FIELDS {
+            bail!("header is too short: {} bytes, at least {} expected", bytes.len(), layout::USER_FIELDS);
+        }
+
+        Ok(Self {
+            signature: read_u32(bytes, layout::SIGNATURE),
+            init: read_u32(bytes, layout::INIT),
+            struct_sha256: read_array(bytes, layout::ABI_STRUCT_SHA256),
+            abi_minor: read_u32(bytes, layout::ABI_MINOR),
+            field_count: read_u32(bytes, layout::ABI_FIELD_COUNT),
+            field_fingerprints_addr: read_u32(bytes, layout::ABI_FIELD_FINGERPRINTS),
+            version: CellVersion {
+                crate_name: read_array(bytes, layout::VERSION_CRATE_NAME),
+                major: read_u16(bytes, layout::VERSION_MAJOR),
+                minor: read_u16(bytes, layout::VERSION_MINOR),
+                patch: read_u16(bytes, layout::VERSION_PATCH),
+                pre: read_array(bytes, layout::VERSION_PRE),
+                git_revision: read_array(bytes, layout::VERSION_GIT_REVISION),
+                build_timestamp: read_u32(bytes, layout::VERSION_BUILD_TIMESTAMP),
+                abi_version: read_u32(bytes, layout::VERSION_ABI_VERSION),
+            },
+            image: ImageInfo {
+                length: read_u32(bytes, layout::IMAGE_LENGTH),
+                crc32: read_u32(bytes, layout::IMAGE_CRC32),
+                sha256: read_array(bytes, layout::IMAGE_SHA256),
+            },
+            security_counter: read_u32(bytes, layout::SECURITY_COUNTER),
+            linked_slot: read_u32(bytes, layout::LINKED_SLOT),
+        })
+    }
+}
+
+pub fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(read_array(bytes, offset))
+}
+
+pub fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(read_array(bytes, offset))
+}
+
+pub fn read_array<const N: usize>(bytes: &[u8], offset: usize) -> [u8; N] {
+    bytes[offset..offset + N].try_into().unwrap()
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
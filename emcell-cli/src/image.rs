@@ -0,0 +1,300 @@
+use std::fmt::Write;
+
+use anyhow::{bail, Result};
+
+use crate::elf::CellElf;
+use crate::verify::CellsDefs;
+
+/// Value of erased flash, used to fill gaps between cells
+pub const ERASED: u8 = 0xFF;
+
+/// Data of several cells, merged into one continuous flash image
+pub struct FlashImage {
+    pub start: u64,
+    pub data: Vec<u8>,
+}
+
+struct Chunk<'a> {
+    addr: u64,
+    data: &'a [u8],
+    owner: String,
+}
+
+impl FlashImage {
+    /// Place data of every image at its load address.
+    ///
+    /// With `defs`, every image must provide a known cell and stay inside its flash region, and the merged image
+    /// starts at the lowest flash region of the given cells. Overlapping data is always an error.
+    pub fn merge(elfs: &[CellElf], defs: Option<&CellsDefs>) -> Result<Self> {
+        let mut chunks = Vec::new();
+        let mut start = None;
+        for elf in elfs {
+            let owner = match &elf.header {
+                Some(header) => format!("{} ({})", header.cell_name, elf.display_name()),
+                None => elf.display_name(),
+            };
+
+            if let Some(defs) = defs {
+                let Some(header) = &elf.header else {
+                    bail!("{}: no cell header found", elf.display_name());
+                };
+                let Some(meta) = defs.cell(&header.cell_name) else {
+                    bail!("{}: cell {} is not present in cells definitions", elf.display_name(), header.cell_name);
+                };
+                let Some((flash_start, flash_end)) = meta.slot_at(header.addr as usize, &defs.device)
+                    .and_then(|slot| meta.slot_flash_range(slot, &defs.device)) else {
+                    bail!("{}: header at 0x{:08X} is not at the start of any slot of {}", elf.display_name(), header.addr, header.cell_name);
+                };
+                let flash = flash_start as u64..flash_end as u64;
+                for segment in &elf.segments {
+                    let end = segment.paddr + segment.data.len() as u64;
+                    if segment.paddr < flash.start || end > flash.end {
+                        bail!("{}: data at 0x{:08X}..0x{:08X} is outside of the flash region 0x{:08X}..0x{:08X}",
+                              owner, segment.paddr, end, flash.start, flash.end);
+                    }
+                }
+                start = Some(start.map_or(flash.start, |start: u64| start.min(flash.start)));
+            }
+
+            chunks.extend(elf.segments.iter().map(|segment| Chunk {
+                addr: segment.paddr,
+                data: &segment.data,
+                owner: owner.clone(),
+            }));
+        }
+
+        chunks.sort_by_key(|chunk| chunk.addr);
+        for pair in chunks.windows(2) {
+            let prev_end = pair[0].addr + pair[0].data.len() as u64;
+            if prev_end > pair[1].addr {
+                bail!("{} and {} overlap at 0x{:08X}..0x{:08X}", pair[0].owner, pair[1].owner, pair[1].addr, prev_end);
+            }
+        }
+
+        let Some(first) = chunks.first() else {
+            bail!("no loadable data in the given images");
+        };
+        let start = start.unwrap_or(first.addr).min(first.addr);
+        let end = chunks.iter().map(|chunk| chunk.addr + chunk.data.len() as u64).max().unwrap();
+
+        let mut data = vec![ERASED; (end - start) as usize];
+        for chunk in &chunks {
+            let offset = (chunk.addr - start) as usize;
+            data[offset..offset + chunk.data.len()].copy_from_slice(chunk.data);
+        }
+
+        Ok(Self { start, data })
+    }
+
+    pub fn end(&self) -> u64 {
+        self.start + self.data.len() as u64
+    }
+
+    /// Rows of up to `len` bytes, aligned to `len`, with their addresses. Fully erased rows are skipped
+    fn rows(&self, len: u64) -> impl Iterator<Item = (u64, &[u8])> {
+        self.blocks(len).filter(|(_, row)| row.iter().any(|b| *b != ERASED))
+    }
+
+    /// Blocks of up to `len` bytes, aligned to `len`, with their addresses. Only the first and the last block can be
+    /// shorter
+    fn blocks(&self, len: u64) -> impl Iterator<Item = (u64, &[u8])> {
+        let mut addr = self.start;
+        std::iter::from_fn(move || {
+            if addr >= self.end() {
+                return None;
+            }
+            let row_end = ((addr / len + 1) * len).min(self.end());
+            let row = &self.data[(addr - self.start) as usize..(row_end - self.start) as usize];
+            let row_addr = addr;
+            addr = row_end;
+            Some((row_addr, row))
+        })
+    }
+
+    /// Intel HEX with extended linear address records
+    pub fn to_ihex(&self) -> String {
+        let mut out = String::new();
+        let mut upper = None;
+        for (addr, row) in self.rows(16) {
+            if upper != Some(addr >> 16) {
+                upper = Some(addr >> 16);
+                ihex_record(&mut out, 0, 0x04, &((addr >> 16) as u16).to_be_bytes());
+            }
+            ihex_record(&mut out, addr as u16, 0x00, row);
+        }
+        ihex_record(&mut out, 0, 0x01, &[]);
+        out
+    }
+
+    /// Motorola S-record with 32-bit addresses
+    pub fn to_srec(&self) -> String {
+        let mut out = String::new();
+        srec_record(&mut out, '0', &[0, 0], b"emcell");
+        for (addr, row) in self.rows(16) {
+            srec_record(&mut out, '3', &(addr as u32).to_be_bytes(), row);
+        }
+        srec_record(&mut out, '7', &(self.start as u32).to_be_bytes(), &[]);
+        out
+    }
+
+    /// UF2 file for drag-and-drop flashing. Every block of the image is written, including erased ones, so the whole
+    /// image range is replaced.
+    ///
+    /// Bootloaders expect full blocks at 256-byte aligned addresses, so the image is padded with erased bytes to
+    /// 256-byte boundaries on both ends
+    pub fn to_uf2(&self, family_id: Option<u32>) -> Vec<u8> {
+        let len = UF2_PAYLOAD_SIZE as u64;
+        let start = self.start / len * len;
+        let end = self.end().div_ceil(len) * len;
+        let mut data = vec![ERASED; (end - start) as usize];
+        let offset = (self.start - start) as usize;
+        data[offset..offset + self.data.len()].copy_from_slice(&self.data);
+        let padded = FlashImage { start, data };
+
+        let blocks = padded.blocks(len).collect::<Vec<_>>();
+        let mut out = Vec::with_capacity(blocks.len() * 512);
+        for (i, (addr, data)) in blocks.iter().enumerate() {
+            let flags = if family_id.is_some() { UF2_FLAG_FAMILY_ID } else { 0 };
+            for word in [UF2_MAGIC_START0, UF2_MAGIC_START1, flags, *addr as u32, data.len() as u32, i as u32,
+                         blocks.len() as u32, family_id.unwrap_or(0)] {
+                out.extend_from_slice(&word.to_le_bytes());
+            }
+            out.extend_from_slice(data);
+            out.resize(out.len() + UF2_DATA_SIZE - data.len(), 0);
+            out.extend_from_slice(&UF2_MAGIC_END.to_le_bytes());
+        }
+        out
+    }
+}
+
+const UF2_MAGIC_START0: u32 = 0x0A32_4655;
+const UF2_MAGIC_START1: u32 = 0x9E5D_5157;
+const UF2_MAGIC_END: u32 = 0x0AB1_6F30;
+const UF2_FLAG_FAMILY_ID: u32 = 0x0000_2000;
+const UF2_PAYLOAD_SIZE: usize = 256;
+const UF2_DATA_SIZE: usize = 476;
+
+fn ihex_record(out: &mut String, addr: u16, kind: u8, data: &[u8]) {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend_from_slice(&addr.to_be_bytes());
+    bytes.push(kind);
+    bytes.extend_from_slice(data);
+    let checksum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)).wrapping_neg();
+
+    out.push(':');
+    for b in bytes.iter().chain([&checksum]) {
+        write!(out, "{:02X}", b).unwrap();
+    }
+    out.push('\n');
+}
+
+fn srec_record(out: &mut String, kind: char, addr: &[u8], data: &[u8]) {
+    let mut bytes = vec![(addr.len() + data.len() + 1) as u8];
+    bytes.extend_from_slice(addr);
+    bytes.extend_from_slice(data);
+    let checksum = !bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+
+    out.push('S');
+    out.push(kind);
+    for b in bytes.iter().chain([&checksum]) {
+        write!(out, "{:02X}", b).unwrap();
+    }
+    out.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elf::Segment;
+
+    fn elf(name: &str, paddr: u64, len: usize) -> CellElf {
+        CellElf {
+            path: name.into(),
+            data: Vec::new(),
+            segments: vec![Segment { paddr, vaddr: paddr, mem_size: len as u64, data: vec![0x55; len] }],
+            sections: Vec::new(),
+            header: None,
+            extern_headers: Vec::new(),
+        }
+    }
+
+    fn word(block: &[u8], index: usize) -> u32 {
+        u32::from_le_bytes(block[index * 4..index * 4 + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn ihex_switches_extended_address_at_64k() {
+        let image = FlashImage { start: 0xFFF8, data: (0..16).collect() };
+
+        assert_eq!(image.to_ihex(), "\
+            :020000040000FA\n\
+            :08FFF8000001020304050607E5\n\
+            :020000040001F9\n\
+            :0800000008090A0B0C0D0E0F9C\n\
+            :00000001FF\n");
+    }
+
+    #[test]
+    fn srec_records_have_checksums() {
+        let mut data = vec![1, 2, 3, 4];
+        data.resize(0x20, ERASED);
+        data.push(0xAA);
+        let image = FlashImage { start: 0x0800_0000, data };
+
+        assert_eq!(image.to_srec(), "\
+            S0090000656D63656C6C84\n\
+            S3150800000001020304FFFFFFFFFFFFFFFFFFFFFFFFE4\n\
+            S30608000020AA27\n\
+            S70508000000F2\n");
+    }
+
+    #[test]
+    fn uf2_blocks_are_numbered_and_padded() {
+        let image = FlashImage { start: 0x0800_00F0, data: vec![0xAA; 0x20] };
+        let uf2 = image.to_uf2(Some(0x1234_5678));
+
+        let blocks = uf2.chunks(512).collect::<Vec<_>>();
+        assert_eq!(uf2.len(), 2 * 512);
+        for (i, block) in blocks.iter().enumerate() {
+            assert_eq!(word(block, 0), UF2_MAGIC_START0);
+            assert_eq!(word(block, 1), UF2_MAGIC_START1);
+            assert_eq!(word(block, 2), UF2_FLAG_FAMILY_ID);
+            assert_eq!(word(block, 3), 0x0800_0000 + i as u32 * 256);
+            assert_eq!(word(block, 4), 256);
+            assert_eq!(word(block, 5), i as u32);
+            assert_eq!(word(block, 6), 2);
+            assert_eq!(word(block, 7), 0x1234_5678);
+            assert_eq!(word(block, 127), UF2_MAGIC_END);
+            assert!(block[32 + 256..508].iter().all(|b| *b == 0));
+        }
+
+        let payload = blocks.iter().flat_map(|block| &block[32..32 + 256]).copied().collect::<Vec<_>>();
+        assert!(payload[..0xF0].iter().all(|b| *b == ERASED));
+        assert!(payload[0xF0..0x110].iter().all(|b| *b == 0xAA));
+        assert!(payload[0x110..].iter().all(|b| *b == ERASED));
+    }
+
+    #[test]
+    fn uf2_without_family_id() {
+        let uf2 = FlashImage { start: 0x0800_0000, data: vec![0xAA; 0x100] }.to_uf2(None);
+
+        assert_eq!(uf2.len(), 512);
+        assert_eq!(word(&uf2, 2), 0);
+        assert_eq!(word(&uf2, 7), 0);
+    }
+
+    #[test]
+    fn merge_fills_gaps() {
+        let image = FlashImage::merge(&[elf("b", 0x0800_0010, 4), elf("a", 0x0800_0000, 4)], None).unwrap();
+
+        assert_eq!(image.start, 0x0800_0000);
+        assert_eq!(image.data, [[0x55; 4].as_slice(), &[ERASED; 12], &[0x55; 4]].concat());
+    }
+
+    #[test]
+    fn merge_rejects_overlapping_images() {
+        let error = FlashImage::merge(&[elf("a", 0x0800_0000, 0x20), elf("b", 0x0800_0010, 0x20)], None).err().unwrap();
+
+        assert_eq!(error.to_string(), "a and b overlap at 0x08000010..0x08000020");
+    }
+}
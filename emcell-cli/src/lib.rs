@@ -0,0 +1,13 @@
+//! Host tools for emcell: read cell headers from linked images and check them against cells definitions.
+
+pub mod client;
+pub mod elf;
+pub mod header;
+pub mod image;
+pub mod patch;
+pub mod sign;
+pub mod verify;
+mod cli;
+
+pub use cli::{main, run};
+pub use verify::CellsDefs;
@@ -0,0 +1,3 @@
+fn main() {
+    emcell_cli::main(None);
+}
@@ -0,0 +1,131 @@
+use std::ops::Range;
+
+use anyhow::{bail, Result};
+use emcell::integrity::crc32;
+use emcell::meta::header_layout as layout;
+use emcell::meta::{ImageInfo, HEADER_SIZE};
+use sha2::{Digest, Sha256};
+
+use crate::elf::CellElf;
+use crate::image::ERASED;
+use crate::verify::CellsDefs;
+
+/// Flash range of the cell without its header: `[start_flash, end_flash)`.
+///
+/// Without cells definitions it is derived from the header location: the primary cell puts its header after all the
+/// data, other cells put it before.
+pub fn cell_flash(elf: &CellElf, defs: Option<&CellsDefs>) -> Result<Range<u64>> {
+    let Some(header) = &elf.header else {
+        bail!("{}: no cell header found", elf.display_name());
+    };
+
+    if let Some(defs) = defs {
+        let Some(meta) = defs.cell(&header.cell_name) else {
+            bail!("{}: cell {} is not present in cells definitions", elf.display_name(), header.cell_name);
+        };
+        let Some((_, region)) = defs.slot_flash(meta, header.addr) else {
+            bail!("{}: header of {} at 0x{:08X} is not at the start of any of its slots", elf.display_name(), header.cell_name, header.addr);
+        };
+        return Ok(region.start_flash as u64..region.end_flash as u64);
+    }
+
+    let header_end = header.addr + header.size;
+    let start = elf.segments.iter().map(|segment| segment.paddr).min().unwrap_or(header.addr);
+    if start >= header.addr {
+        Ok(header_end..u64::MAX)
+    }
+    else {
+        Ok(start..header.addr)
+    }
+}
+
+/// Flash contents of the cell image, `[start_flash, start_flash + length)`. Gaps are filled with erased value
+pub fn image_data(elf: &CellElf, flash: &Range<u64>) -> Result<Vec<u8>> {
+    let Some(header) = &elf.header else {
+        bail!("{}: no cell header found", elf.display_name());
+    };
+    let header_range = header.addr..header.addr + header.size;
+
+    // the header may share a load segment with the data, so split segments around it
+    let mut parts = Vec::new();
+    for segment in &elf.segments {
+        let range = segment.paddr..segment.paddr + segment.data.len() as u64;
+        for part in [range.start..range.end.min(header_range.start), range.start.max(header_range.end)..range.end] {
+            if part.is_empty() {
+                continue;
+            }
+            if part.start < flash.start || part.end > flash.end {
+                bail!("{}: data at 0x{:08X}..0x{:08X} is outside of the cell flash 0x{:08X}..0x{:08X}",
+                      elf.display_name(), part.start, part.end, flash.start, flash.end);
+            }
+            let offset = (part.start - segment.paddr) as usize;
+            parts.push((part.start, &segment.data[offset..offset + (part.end - part.start) as usize]));
+        }
+    }
+
+    let end = parts.iter().map(|(addr, data)| addr + data.len() as u64).max().unwrap_or(flash.start);
+    let mut data = vec![ERASED; (end - flash.start) as usize];
+    for (addr, part) in parts {
+        let offset = (addr - flash.start) as usize;
+        data[offset..offset + part.len()].copy_from_slice(part);
+    }
+    Ok(data)
+}
+
+/// Length and checksums of the cell image, as they should be stored in its header
+pub fn image_info(elf: &CellElf, defs: Option<&CellsDefs>) -> Result<ImageInfo> {
+    let flash = cell_flash(elf, defs)?;
+    let data = image_data(elf, &flash)?;
+
+    Ok(ImageInfo {
+        length: data.len() as u32,
+        crc32: crc32(&data),
+        sha256: Sha256::digest(&data).into(),
+    })
+}
+
+/// Write image length and checksums into the header of the cell. Returns patched image
+pub fn patch(elf: &CellElf, defs: Option<&CellsDefs>) -> Result<(CellElf, ImageInfo)> {
+    let info = image_info(elf, defs)?;
+    let Some(header) = &elf.header else {
+        bail!("{}: no cell header found", elf.display_name());
+    };
+    if elf.header_bytes().is_none() {
+        bail!("{}: header data is missing in the image", elf.display_name());
+    }
+    if header.size < HEADER_SIZE as u64 {
+        bail!("{}: header region is {} bytes, {} expected, build the cell with emcell::build_rs",
+              elf.display_name(), header.size, HEADER_SIZE);
+    }
+
+    let mut data = elf.data.clone();
+    let offset = header.file_offset as usize;
+    data[offset + layout::IMAGE_LENGTH..][..4].copy_from_slice(&info.length.to_le_bytes());
+    data[offset + layout::IMAGE_CRC32..][..4].copy_from_slice(&info.crc32.to_le_bytes());
+    data[offset + layout::IMAGE_SHA256..][..32].copy_from_slice(&info.sha256);
+
+    Ok((CellElf::parse(&elf.path, data)?, info))
+}
+
+/// Compare length and checksums stored in the header with the actual image. Unpatched images are not checked
+pub fn check_image_info(elf: &CellElf, defs: Option<&CellsDefs>) -> Vec<String> {
+    let Some(Ok(header)) = elf.header_bytes().map(crate::header::ParsedHeader::parse) else {
+        return Vec::new();
+    };
+    if !header.image.is_patched() {
+        return Vec::new();
+    }
+
+    let actual = match image_info(elf, defs) {
+        Ok(info) => info,
+        Err(e) => return vec![e.to_string()],
+    };
+    let mut problems = Vec::new();
+    if header.image.length != actual.length {
+        problems.push(format!("image length {} in the header, actual length is {}", header.image.length, actual.length));
+    }
+    else if header.image.crc32 != actual.crc32 || header.image.sha256 != actual.sha256 {
+        problems.push("image checksum in the header does not match the image".to_string());
+    }
+    problems
+}
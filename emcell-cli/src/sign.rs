@@ -0,0 +1,115 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use emcell::meta::header_layout as layout;
+use emcell::meta::{SignatureTrailer, SIGNATURE_SIZE};
+use sha2::{Digest, Sha256};
+
+use crate::elf::CellElf;
+use crate::header::{hex, ParsedHeader};
+use crate::patch::{cell_flash, image_data, patch};
+use crate::verify::CellsDefs;
+
+/// Load Ed25519 signing key: 32-byte seed, either raw or as 64 hex digits
+pub fn load_key(path: impl AsRef<Path>) -> Result<SigningKey> {
+    let path = path.as_ref();
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+
+    let text = String::from_utf8_lossy(&data);
+    let seed = if let Some(seed) = parse_hex(text.trim()) {
+        seed
+    }
+    else if let Ok(seed) = <[u8; 32]>::try_from(data.as_slice()) {
+        seed
+    }
+    else {
+        bail!("{}: expected Ed25519 key as 32 raw bytes or 64 hex digits", path.display());
+    };
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+fn parse_hex(text: &str) -> Option<[u8; 32]> {
+    if text.len() != 64 || !text.is_ascii() {
+        return None;
+    }
+    let mut res = [0; 32];
+    for (i, byte) in res.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(res)
+}
+
+/// Public key in the form expected by `#[signed(public_key = "...")]`
+pub fn public_key_hex(key: &SigningKey) -> String {
+    hex(key.verifying_key().as_bytes())
+}
+
+/// Digest, which is signed: `sha256(header_region[..SIGNATURE_TRAILER] || image)`
+pub fn signed_digest(elf: &CellElf, defs: Option<&CellsDefs>) -> Result<[u8; 32]> {
+    let Some(header) = &elf.header else {
+        bail!("{}: no cell header found", elf.display_name());
+    };
+    let Some(header_region) = elf.read(header.addr, layout::SIGNATURE_TRAILER) else {
+        bail!("{}: header region has no signature trailer, build the cell with emcell::build_rs", elf.display_name());
+    };
+    let image = image_data(elf, &cell_flash(elf, defs)?)?;
+
+    Ok(Sha256::new().chain_update(header_region).chain_update(&image).finalize().into())
+}
+
+fn trailer_offset(elf: &CellElf) -> Result<usize> {
+    let header = elf.header.as_ref().unwrap();
+    if header.size < (layout::SIGNATURE_TRAILER + SIGNATURE_SIZE) as u64 {
+        bail!("{}: header region has no signature trailer, build the cell with emcell::build_rs", elf.display_name());
+    }
+    Ok(header.file_offset as usize + layout::SIGNATURE_TRAILER)
+}
+
+/// Fill image length and checksums, then sign the cell. Returns signed image
+pub fn sign(elf: &CellElf, defs: Option<&CellsDefs>, key: &SigningKey) -> Result<CellElf> {
+    let (patched, _) = patch(elf, defs)?;
+    let digest = signed_digest(&patched, defs)?;
+    let signature = key.sign(&digest);
+
+    let mut data = patched.data;
+    let offset = trailer_offset(elf)?;
+    data[offset..offset + SIGNATURE_SIZE].copy_from_slice(&signature.to_bytes());
+
+    CellElf::parse(&elf.path, data)
+}
+
+/// Check the signature of the cell, if its definition requires one or the image is signed
+pub fn check_signature(elf: &CellElf, defs: Option<&CellsDefs>) -> Vec<String> {
+    let Some(header) = &elf.header else {
+        return Vec::new();
+    };
+    let public_key = defs.and_then(|defs| defs.cell(&header.cell_name)).and_then(|meta| meta.public_key);
+    let Some(public_key) = public_key else {
+        return Vec::new();
+    };
+
+    let trailer = match trailer_offset(elf) {
+        Ok(offset) => SignatureTrailer { signature: elf.data[offset..offset + SIGNATURE_SIZE].try_into().unwrap() },
+        Err(e) => return vec![e.to_string()],
+    };
+    if !trailer.is_signed() {
+        return vec!["cell must be signed, but the image has no signature".to_string()];
+    }
+    if !elf.header_bytes().map(ParsedHeader::parse).is_some_and(|header| header.is_ok_and(|header| header.image.is_patched())) {
+        return vec!["image is signed, but its length is not filled in the header".to_string()];
+    }
+
+    let digest = match signed_digest(elf, defs) {
+        Ok(digest) => digest,
+        Err(e) => return vec![e.to_string()],
+    };
+    let valid = VerifyingKey::from_bytes(&public_key)
+        .is_ok_and(|key| key.verify_strict(&digest, &Signature::from_bytes(&trailer.signature)).is_ok());
+    if valid {
+        Vec::new()
+    }
+    else {
+        vec![format!("image signature does not match public key {}", hex(&public_key))]
+    }
+}
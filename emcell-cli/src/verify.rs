@@ -0,0 +1,185 @@
+use emcell::meta::{CellDefMeta, DeviceConfigMeta, PartitionedFlashRegion};
+use emcell::{Cell, Slot};
+
+use crate::elf::CellElf;
+use crate::header::{hex, ParsedHeader};
+
+/// Cells definitions, the images were built against. Usually `META` from the cells definitions crate
+#[derive(Copy, Clone)]
+pub struct CellsDefs {
+    pub device: DeviceConfigMeta,
+    pub cells: &'static [CellDefMeta],
+}
+
+impl CellsDefs {
+    /// Definitions of all cells from the configuration of cell `T`
+    pub fn of<T: Cell>() -> Self {
+        Self {
+            device: T::DEVICE_CONFIG,
+            cells: T::CELLS_META,
+        }
+    }
+
+    pub fn cell(&self, name: &str) -> Option<&CellDefMeta> {
+        self.cells.iter().find(|cell| cell.name == name)
+    }
+
+    /// Flash region of the slot, which has its header at `header_addr`
+    pub fn slot_flash(&self, meta: &CellDefMeta, header_addr: u64) -> Option<(Slot, PartitionedFlashRegion)> {
+        let slot = meta.slot_at(header_addr as usize, &self.device)?;
+        Some((slot, meta.partitioned_flash_in(slot, &self.device)?))
+    }
+}
+
+/// Check a single image against cells definitions. Returns list of problems, empty if image is valid
+pub fn verify(elf: &CellElf, defs: &CellsDefs) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let Some(location) = &elf.header else {
+        problems.push("no cell header found: _emcell_<Cell>_internal symbol is missing".to_string());
+        return problems;
+    };
+    let Some(meta) = defs.cell(&location.cell_name) else {
+        problems.push(format!("cell {} is not present in cells definitions", location.cell_name));
+        return problems;
+    };
+
+    let Some(slot) = meta.slot_at(location.addr as usize, &defs.device) else {
+        let expected = [Slot::A, Slot::B].into_iter()
+            .filter_map(|slot| meta.partitioned_flash_in(slot, &defs.device))
+            .map(|region| format!("0x{:08X}", region.start_header))
+            .collect::<Vec<_>>();
+        problems.push(format!("header is located at 0x{:08X}, but {} expected", location.addr, expected.join(" or ")));
+        return problems;
+    };
+
+    match elf.header_bytes().map(ParsedHeader::parse) {
+        None => problems.push("header data is missing in the image".to_string()),
+        Some(Err(e)) => problems.push(e.to_string()),
+        Some(Ok(header)) => {
+            verify_header(elf, meta, &header, &mut problems);
+            if header.linked_slot != slot as u32 {
+                problems.push(format!("header is located in slot {:?}, but linked for slot {}", slot, header.linked_slot));
+            }
+        }
+    }
+
+    verify_placement(elf, meta, slot, &defs.device, &mut problems);
+
+    for (name, addr) in &elf.extern_headers {
+        match defs.cell(name) {
+            Some(other) => {
+                let expected = other.partitioned_flash(&defs.device).start_header as u64;
+                if *addr != expected {
+                    problems.push(format!("header of {} is expected at 0x{:08X}, but 0x{:08X} is used", name, expected, addr));
+                }
+            }
+            None => problems.push(format!("references header of cell {}, which is not present in cells definitions", name)),
+        }
+    }
+
+    problems
+}
+
+fn verify_header(elf: &CellElf, meta: &CellDefMeta, header: &ParsedHeader, problems: &mut Vec<String>) {
+    if header.signature != meta.signature {
+        problems.push(format!("signature 0x{:08X} does not match 0x{:08X} from cells definitions", header.signature, meta.signature));
+    }
+
+    let fingerprints = read_fingerprints(elf, header);
+    if header.struct_sha256 != meta.struct_sha256 {
+        let first_differing_field = fingerprints.as_ref().and_then(|provided| {
+            meta.field_fingerprints.iter()
+                .zip(meta.field_names)
+                .enumerate()
+                .take(meta.base_field_count)
+                .find(|(i, (fingerprint, _))| provided.get(*i) != Some(*fingerprint))
+                .map(|(_, (_, name))| *name)
+        });
+        match first_differing_field {
+            Some(field) => problems.push(format!("header hash {} does not match cells definitions, first differing field: {}",
+                                                 hex(&header.struct_sha256), field)),
+            None => problems.push(format!("header hash {} does not match cells definitions (layout or device configuration differs)",
+                                          hex(&header.struct_sha256))),
+        }
+    }
+    // optional fields may be missing on either side, like in `emcell::check_header`: compare the common prefix only
+    else if meta.field_fingerprints.len() != meta.base_field_count {
+        match fingerprints {
+            Some(provided) => {
+                let differing = meta.field_fingerprints.iter()
+                    .zip(meta.field_names)
+                    .zip(&provided)
+                    .skip(meta.base_field_count)
+                    .find(|((fingerprint, _), provided)| fingerprint != provided);
+                if let Some(((_, name), _)) = differing {
+                    problems.push(format!("optional header field {} differs from cells definitions", name));
+                }
+            }
+            None => problems.push("field fingerprints of the header are not readable".to_string()),
+        }
+    }
+}
+
+fn read_fingerprints(elf: &CellElf, header: &ParsedHeader) -> Option<Vec<u32>> {
+    let bytes = elf.read(header.field_fingerprints_addr as u64, header.field_count as usize * 4)?;
+    Some(bytes.chunks_exact(4).map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap())).collect())
+}
+
+fn verify_placement(elf: &CellElf, meta: &CellDefMeta, slot: Slot, device: &DeviceConfigMeta, problems: &mut Vec<String>) {
+    let (start, end) = meta.slot_flash_range(slot, device).unwrap();
+    let flash = start as u64..end as u64;
+    // own stack of the cell is not available for its sections
+    let ram = meta.absolute_data_ram_start(device) as u64..meta.absolute_data_ram_end(device) as u64;
+
+    for segment in &elf.segments {
+        let end = segment.paddr + segment.data.len() as u64;
+        if segment.paddr < flash.start || end > flash.end {
+            problems.push(format!("data at 0x{:08X}..0x{:08X} is outside of the flash region 0x{:08X}..0x{:08X}",
+                                  segment.paddr, end, flash.start, flash.end));
+        }
+    }
+
+    // sections, which are placed into RAM at runtime. Retained statics live in the no-init region instead
+    let device_ram = device.ram_range_start as u64..device.ram_range_end as u64;
+    let noinit = device.absolute_noinit_range().map(|(start, end)| start as u64..end as u64);
+    for section in &elf.sections {
+        let end = section.addr + section.size;
+        if noinit.as_ref().is_some_and(|noinit| section.addr >= noinit.start && end <= noinit.end) {
+            continue;
+        }
+        if device_ram.contains(&section.addr) && (section.addr < ram.start || end > ram.end) {
+            problems.push(format!("section {} at 0x{:08X}..0x{:08X} is outside of the RAM region 0x{:08X}..0x{:08X}",
+                                  section.name, section.addr, end, ram.start, ram.end));
+        }
+    }
+}
+
+/// Check that images agree with each other: every referenced header is located where its cell placed it
+pub fn cross_check(elfs: &[CellElf]) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let providers = elfs.iter()
+        .filter_map(|elf| elf.header.as_ref().map(|header| (elf, header)))
+        .collect::<Vec<_>>();
+
+    for (i, (elf, header)) in providers.iter().enumerate() {
+        if let Some((other, _)) = providers[..i].iter().find(|(_, other)| other.cell_name == header.cell_name) {
+            problems.push(format!("cell {} is provided by both {} and {}", header.cell_name, other.display_name(), elf.display_name()));
+        }
+    }
+
+    for elf in elfs {
+        for (name, addr) in &elf.extern_headers {
+            let Some((provider, header)) = providers.iter().find(|(_, header)| header.cell_name == *name) else {
+                continue;
+            };
+            if header.addr != *addr {
+                problems.push(format!("{} expects header of {} at 0x{:08X}, but {} places it at 0x{:08X}",
+                                      elf.display_name(), name, addr, provider.display_name(), header.addr));
+            }
+        }
+    }
+
+    problems
+}
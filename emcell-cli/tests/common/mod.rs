@@ -0,0 +1,278 @@
+//! Cells definitions of the tests and linked images of their cells, built without a cross toolchain: minimal ELF files
+//! with the cell header, code and field fingerprints, and a host mapping of the device flash to check them with
+//! `emcell::integrity`
+#![allow(dead_code)]
+
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+use emcell::meta::{header_layout, HEADER_SIZE};
+use emcell::{Cell, Slot};
+use emcell_cli::elf::CellElf;
+use emcell_cli::CellsDefs;
+
+emcell_configuration! {
+    device!{
+        initial_stack_ptr: 0x2000_1000,
+        ram_range_start: 0x2000_0000,
+        ram_range_end: 0x2000_8000,
+        flash_range_start: 0x0800_0000,
+        flash_range_end: 0x0801_0000,
+    }
+
+    #[cell(primary)]
+    #[ram_region(0x1000, 0x2000)]
+    #[flash_region(0x0, 0x4000)]
+    pub struct Cell1 {
+    }
+
+    #[cell]
+    #[ram_region(0x2000, 0x3000)]
+    #[flash_region(0x4000, 0x8000)]
+    #[flash_region(0xC000, 0x1_0000)]
+    pub struct Cell2 {
+        pub run: fn(u32) -> u32,
+    }
+
+    #[cell]
+    #[signed(public_key = "2152f8d19b791d24453242e15f2eab6cb7cffa7b6a5ed30097960e069881db12")]
+    #[ram_region(0x3000, 0x4000)]
+    #[flash_region(0x8000, 0xC000)]
+    pub struct Cell3 {
+    }
+}
+
+/// Seed of the key, which `Cell3` requires a signature from
+pub const KEY_SEED: [u8; 32] = [0x42; 32];
+
+pub fn defs() -> CellsDefs {
+    CellsDefs::of::<Cell1>()
+}
+
+/// Section of a linked image. Sections without data are zero-initialized, like `.bss`
+pub struct Section {
+    pub name: String,
+    pub addr: u32,
+    pub size: u32,
+    pub data: Option<Vec<u8>>,
+}
+
+/// Linked ARM image: every section with data is loaded by its own segment at its run address
+#[derive(Default)]
+pub struct Elf {
+    pub sections: Vec<Section>,
+    pub symbols: Vec<(String, u32)>,
+}
+
+impl Elf {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn section(mut self, name: &str, addr: u32, data: Vec<u8>) -> Self {
+        self.sections.push(Section { name: name.to_string(), addr, size: data.len() as u32, data: Some(data) });
+        self
+    }
+
+    pub fn bss(mut self, name: &str, addr: u32, size: u32) -> Self {
+        self.sections.push(Section { name: name.to_string(), addr, size, data: None });
+        self
+    }
+
+    /// Global symbol, defined in the section containing `addr`, or absolute if there is none
+    pub fn symbol(mut self, name: &str, addr: u32) -> Self {
+        self.symbols.push((name.to_string(), addr));
+        self
+    }
+
+    pub fn section_mut(&mut self, name: &str) -> &mut Section {
+        self.sections.iter_mut().find(|section| section.name == name).unwrap()
+    }
+
+    /// Overwrite bytes of the cell header at `offset`
+    pub fn with_header(mut self, offset: usize, bytes: &[u8]) -> Self {
+        let header = self.section_mut(".CUR_HEADER").data.as_mut().unwrap();
+        header[offset..offset + bytes.len()].copy_from_slice(bytes);
+        self
+    }
+
+    pub fn parse(&self) -> CellElf {
+        CellElf::parse("cell.elf", self.build()).unwrap()
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        const EHDR_SIZE: usize = 52;
+        const PHDR_SIZE: usize = 32;
+        const SHDR_SIZE: u16 = 40;
+        const SHF_WRITE: u32 = 0x1;
+        const SHF_ALLOC: u32 = 0x2;
+        const SHF_EXECINSTR: u32 = 0x4;
+        const SHT_PROGBITS: u32 = 1;
+        const SHT_SYMTAB: u32 = 2;
+        const SHT_STRTAB: u32 = 3;
+        const SHT_NOBITS: u32 = 8;
+        const SHN_ABS: u16 = 0xFFF1;
+
+        fn align(out: &mut Vec<u8>) {
+            out.resize(out.len().next_multiple_of(4), 0);
+        }
+        fn add_name(table: &mut Vec<u8>, name: &str) -> u32 {
+            let offset = table.len() as u32;
+            table.extend_from_slice(name.as_bytes());
+            table.push(0);
+            offset
+        }
+        fn put(out: &mut Vec<u8>, words: &[u32]) {
+            for word in words {
+                out.extend_from_slice(&word.to_le_bytes());
+            }
+        }
+
+        let loaded = self.sections.iter().filter(|section| section.data.is_some()).count();
+        let mut out = vec![0; EHDR_SIZE + PHDR_SIZE * loaded];
+
+        let mut offsets = Vec::new();
+        for section in &self.sections {
+            align(&mut out);
+            offsets.push(out.len() as u32);
+            out.extend_from_slice(section.data.as_deref().unwrap_or_default());
+        }
+
+        let mut strtab = vec![0];
+        let mut symtab = vec![0; 16];
+        for (name, addr) in &self.symbols {
+            let shndx = self.sections.iter()
+                .position(|section| (section.addr..section.addr + section.size).contains(addr))
+                .map_or(SHN_ABS, |i| i as u16 + 1);
+            put(&mut symtab, &[add_name(&mut strtab, name), *addr, 0]);
+            // STB_GLOBAL, STT_NOTYPE
+            symtab.extend_from_slice(&[0x10, 0]);
+            symtab.extend_from_slice(&shndx.to_le_bytes());
+        }
+        align(&mut out);
+        let symtab_offset = out.len() as u32;
+        out.extend_from_slice(&symtab);
+        let strtab_offset = out.len() as u32;
+        out.extend_from_slice(&strtab);
+
+        let mut shstrtab = vec![0];
+        let names = self.sections.iter().map(|section| add_name(&mut shstrtab, &section.name)).collect::<Vec<_>>();
+        let symtab_name = add_name(&mut shstrtab, ".symtab");
+        let strtab_name = add_name(&mut shstrtab, ".strtab");
+        let shstrtab_name = add_name(&mut shstrtab, ".shstrtab");
+        let shstrtab_offset = out.len() as u32;
+        out.extend_from_slice(&shstrtab);
+
+        align(&mut out);
+        let shoff = out.len() as u32;
+        let user_sections = self.sections.len() as u32;
+        out.extend_from_slice(&[0; SHDR_SIZE as usize]);
+        for ((section, name), offset) in self.sections.iter().zip(names).zip(&offsets) {
+            let (kind, flags) = match &section.data {
+                None => (SHT_NOBITS, SHF_ALLOC | SHF_WRITE),
+                Some(_) if section.name == ".text" => (SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR),
+                Some(_) => (SHT_PROGBITS, SHF_ALLOC),
+            };
+            put(&mut out, &[name, kind, flags, section.addr, *offset, section.size, 0, 0, 4, 0]);
+        }
+        put(&mut out, &[symtab_name, SHT_SYMTAB, 0, 0, symtab_offset, symtab.len() as u32, user_sections + 2, 1, 4, 16]);
+        put(&mut out, &[strtab_name, SHT_STRTAB, 0, 0, strtab_offset, strtab.len() as u32, 0, 0, 1, 0]);
+        put(&mut out, &[shstrtab_name, SHT_STRTAB, 0, 0, shstrtab_offset, shstrtab.len() as u32, 0, 0, 1, 0]);
+
+        let mut header = b"\x7FELF\x01\x01\x01".to_vec();
+        header.resize(16, 0);
+        // ET_EXEC, EM_ARM
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&40u16.to_le_bytes());
+        put(&mut header, &[1, 0, EHDR_SIZE as u32, shoff, 0x0500_0400]);
+        for half in [EHDR_SIZE as u16, PHDR_SIZE as u16, loaded as u16, SHDR_SIZE, user_sections as u16 + 4, user_sections as u16 + 3] {
+            header.extend_from_slice(&half.to_le_bytes());
+        }
+        // PT_LOAD, readable and executable
+        for (section, offset) in self.sections.iter().zip(&offsets).filter(|(section, _)| section.data.is_some()) {
+            put(&mut header, &[1, *offset, section.addr, section.addr, section.size, section.size, 5, 4]);
+        }
+        out[..header.len()].copy_from_slice(&header);
+        out
+    }
+}
+
+/// Length of the code of test images
+pub const CODE_SIZE: usize = 3000;
+
+/// Linked, not patched image of cell `T` for `slot`
+pub fn cell_image<T: Cell>(slot: Slot) -> Elf {
+    cell_image_at::<T>(slot, 0)
+}
+
+/// Image of cell `T` for `slot`, moved by `shift` bytes from the start of the slot: header region, code and field
+/// fingerprints of the header after the code, `.bss` in the RAM region of the cell and headers of other cells
+pub fn cell_image_at<T: Cell>(slot: Slot, shift: u32) -> Elf {
+    use header_layout::*;
+
+    let meta = T::CUR_META;
+    let region = meta.partitioned_flash_in(slot, &T::DEVICE_CONFIG).unwrap();
+    let header_addr = region.start_header as u32 + shift;
+    let code_addr = region.start_flash as u32 + shift;
+
+    let code: Vec<u8> = (0..CODE_SIZE).map(|i| (i * 7 + 3) as u8).collect();
+    let fingerprints_addr = code_addr + code.len() as u32;
+    let fingerprints: Vec<u8> = meta.field_fingerprints.iter().flat_map(|fingerprint| fingerprint.to_le_bytes()).collect();
+
+    let mut header = vec![0xFF; HEADER_SIZE];
+    let mut put = |offset: usize, bytes: &[u8]| header[offset..offset + bytes.len()].copy_from_slice(bytes);
+    put(SIGNATURE, &meta.signature.to_le_bytes());
+    put(ABI_STRUCT_SHA256, &meta.struct_sha256);
+    put(ABI_FIELD_COUNT, &(meta.field_fingerprints.len() as u32).to_le_bytes());
+    put(ABI_FIELD_FINGERPRINTS, &fingerprints_addr.to_le_bytes());
+    put(LINKED_SLOT, &(slot as u32).to_le_bytes());
+
+    let mut elf = Elf::new()
+        .section(".CUR_HEADER", header_addr, header)
+        .section(".text", code_addr, code)
+        .bss(".bss", meta.absolute_data_ram_start(&T::DEVICE_CONFIG) as u32, 0x100)
+        .symbol(&format!("_emcell_{}_internal", meta.name), header_addr);
+    if !fingerprints.is_empty() {
+        elf = elf.section(".rodata", fingerprints_addr, fingerprints);
+    }
+    for other in T::CELLS_META.iter().filter(|other| other.name != meta.name) {
+        elf = elf.symbol(&format!("_emcell_{}_internal", other.name), other.partitioned_flash(&T::DEVICE_CONFIG).start_header as u32);
+    }
+    elf
+}
+
+/// Flash of the test device, mapped at its address in the test process
+#[cfg(target_os = "linux")]
+pub struct Flash(&'static mut [u8]);
+
+#[cfg(target_os = "linux")]
+impl Flash {
+    /// Erase the whole flash, then write loadable data of `elf`
+    pub fn program(&mut self, elf: &CellElf) {
+        self.0.fill(0xFF);
+        for segment in &elf.segments {
+            self.write(segment.paddr as usize, &segment.data);
+        }
+    }
+
+    pub fn write(&mut self, addr: usize, data: &[u8]) {
+        let offset = addr - Cell1::DEVICE_CONFIG.flash_range_start;
+        self.0[offset..offset + data.len()].copy_from_slice(data);
+    }
+}
+
+/// Exclusive access to the device flash. The first call maps it, tests fail if the address range is already taken
+#[cfg(target_os = "linux")]
+pub fn flash() -> MutexGuard<'static, Flash> {
+    static FLASH: OnceLock<Mutex<Flash>> = OnceLock::new();
+
+    FLASH.get_or_init(|| {
+        let device = Cell1::DEVICE_CONFIG;
+        let len = device.flash_range_end - device.flash_range_start;
+        let addr = unsafe {
+            libc::mmap(device.flash_range_start as *mut libc::c_void, len, libc::PROT_READ | libc::PROT_WRITE,
+                       libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED_NOREPLACE, -1, 0)
+        };
+        assert_eq!(addr as usize, device.flash_range_start, "Failed to map flash at 0x{:08X}", device.flash_range_start);
+        Mutex::new(Flash(unsafe { std::slice::from_raw_parts_mut(addr as *mut u8, len) }))
+    }).lock().unwrap_or_else(|e| e.into_inner())
+}
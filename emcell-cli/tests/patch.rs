@@ -0,0 +1,75 @@
+//! `emcell patch` output, checked by `emcell::integrity` the same way as on the device
+#![cfg(target_os = "linux")]
+
+#[macro_use]
+extern crate emcell_macro;
+
+mod common;
+
+use common::{cell_image, defs, flash, Cell1, Cell2, CODE_SIZE};
+use emcell::integrity::{verify_image, IntegrityCheck};
+use emcell::meta::HEADER_SIZE;
+use emcell::{Cell, CellError, Slot};
+use emcell_cli::header::ParsedHeader;
+use emcell_cli::patch::{check_image_info, patch};
+
+#[test]
+fn patched_image_passes_verify_image() {
+    for slot in [Slot::A, Slot::B] {
+        let (patched, info) = patch(&cell_image::<Cell2>(slot).parse(), Some(&defs())).unwrap();
+        let header = ParsedHeader::parse(patched.header_bytes().unwrap()).unwrap();
+        assert_eq!((header.image.length, header.image.crc32, header.image.sha256), (info.length, info.crc32, info.sha256));
+        assert_eq!(info.length as usize, CODE_SIZE + 4);
+        assert_eq!(check_image_info(&patched, Some(&defs())), Vec::<String>::new());
+
+        let mut flash = flash();
+        flash.program(&patched);
+        assert_eq!(verify_image::<Cell2>(slot, &header.image, IntegrityCheck::Crc32), Ok(()));
+        assert_eq!(verify_image::<Cell2>(slot, &header.image, IntegrityCheck::Sha256), Ok(()));
+    }
+}
+
+#[test]
+fn primary_cell_is_patched_without_defs() {
+    let (patched, info) = patch(&cell_image::<Cell1>(Slot::A).parse(), None).unwrap();
+    assert_eq!(info.length as usize, CODE_SIZE);
+
+    let mut flash = flash();
+    flash.program(&patched);
+    assert_eq!(verify_image::<Cell1>(Slot::A, &info, IntegrityCheck::Sha256), Ok(()));
+}
+
+#[test]
+fn corrupted_image_fails_verify_image() {
+    let (patched, info) = patch(&cell_image::<Cell2>(Slot::A).parse(), Some(&defs())).unwrap();
+    let start = Cell2::CUR_META.partitioned_flash(&Cell2::DEVICE_CONFIG).start_flash;
+
+    let mut flash = flash();
+    flash.program(&patched);
+    flash.write(start + 100, &[0]);
+    assert_eq!(verify_image::<Cell2>(Slot::A, &info, IntegrityCheck::Crc32), Err(CellError::ImageCorrupted));
+    assert_eq!(verify_image::<Cell2>(Slot::A, &info, IntegrityCheck::Sha256), Err(CellError::ImageCorrupted));
+}
+
+#[test]
+fn changed_image_is_reported() {
+    let (patched, _) = patch(&cell_image::<Cell2>(Slot::A).parse(), Some(&defs())).unwrap();
+    let mut data = patched.data.clone();
+    let code_offset = patched.sections.iter().find(|section| section.name == ".text").unwrap().file_range.unwrap().0;
+    data[code_offset as usize] ^= 0xFF;
+
+    let changed = emcell_cli::elf::CellElf::parse("cell.elf", data).unwrap();
+    assert_eq!(check_image_info(&changed, Some(&defs())), vec!["image checksum in the header does not match the image".to_string()]);
+}
+
+#[test]
+fn truncated_header_is_rejected() {
+    let mut image = cell_image::<Cell2>(Slot::A);
+    let header = image.section_mut(".CUR_HEADER");
+    header.data.as_mut().unwrap().truncate(0x80);
+    header.size = 0x80;
+
+    let error = patch(&image.parse(), Some(&defs())).err().unwrap();
+    assert_eq!(error.to_string(), format!(
+        "cell.elf: header region is 128 bytes, {} expected, build the cell with emcell::build_rs", HEADER_SIZE));
+}
@@ -0,0 +1,337 @@
+//! `Client` against `UpdateServer` on a RAM flash, connected by an in-memory transport, which can break or lose frames
+
+#[macro_use]
+extern crate emcell_macro;
+
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Write};
+use std::time::Duration;
+
+use emcell::integrity::crc32;
+use emcell::meta::{header_layout, HEADER_SIZE};
+use emcell::protocol::{encode_frame, FrameDecoder, Kind, ResultCode, State, Status, UpdateServer, MAX_DATA, MAX_FRAME};
+use emcell::update::MemoryFlash;
+use emcell::{Cell, Slot};
+use emcell_cli::client::Client;
+use sha2::Digest;
+
+emcell_configuration! {
+    device!{
+        initial_stack_ptr: 0x2000_1000,
+        ram_range_start: 0x2000_0000,
+        ram_range_end: 0x2000_8000,
+        flash_range_start: 0x0800_0000,
+        flash_range_end: 0x0801_0000,
+    }
+
+    #[cell(primary)]
+    #[ram_region(0x1000, 0x2000)]
+    #[flash_region(0x0, 0x4000)]
+    pub struct Cell1 {
+    }
+
+    #[cell]
+    #[ram_region(0x2000, 0x3000)]
+    #[flash_region(0x4000, 0x8000)]
+    pub struct Cell2 {
+    }
+}
+
+type Flash = MemoryFlash<0x1_0000>;
+
+/// Driver offset of slot A of Cell2
+const SLOT_OFFSET: usize = 0x4000;
+
+/// Patched image of Cell2 for slot A: header region followed by the code
+fn image() -> Vec<u8> {
+    use header_layout::*;
+
+    let code: Vec<u8> = (0..3000u32).map(|i| (i * 7 + 3) as u8).collect();
+    let mut image = vec![0; HEADER_SIZE];
+    let mut put = |offset: usize, bytes: &[u8]| image[offset..offset + bytes.len()].copy_from_slice(bytes);
+    put(SIGNATURE, &Cell2::CUR_META.signature.to_le_bytes());
+    put(ABI_STRUCT_SHA256, &Cell2::CUR_META.struct_sha256);
+    put(LINKED_SLOT, &(Slot::A as u32).to_le_bytes());
+    put(IMAGE_LENGTH, &(code.len() as u32).to_le_bytes());
+    put(IMAGE_CRC32, &crc32(&code).to_le_bytes());
+    put(IMAGE_SHA256, sha2::Sha256::digest(&code).as_slice());
+    image.extend_from_slice(&code);
+    image
+}
+
+fn begin_payload(length: usize) -> Vec<u8> {
+    let mut payload = vec![Slot::A as u8];
+    payload.extend_from_slice(&(length as u32).to_le_bytes());
+    payload.extend_from_slice(b"Cell2");
+    payload
+}
+
+fn data_payload(offset: usize, chunk: &[u8]) -> Vec<u8> {
+    let mut payload = (offset as u32).to_le_bytes().to_vec();
+    payload.extend_from_slice(chunk);
+    payload
+}
+
+/// Flip a bit of the CRC at the end of a frame
+fn corrupt(frame: &mut [u8]) {
+    *frame.last_mut().unwrap() ^= 0x01;
+}
+
+/// Byte transport to the server: every write is one request frame, responses are queued for reading. Reads do not
+/// block, like the serial port of `emcell update`
+struct Loopback<'a> {
+    server: UpdateServer<'a, Flash>,
+    responses: VecDeque<u8>,
+    requests: usize,
+    sent_responses: usize,
+    /// Indices of the requests and responses, which are broken on the way
+    corrupt_requests: Vec<usize>,
+    corrupt_responses: Vec<usize>,
+    /// Indices of the requests and responses, which are lost on the way
+    lost_requests: Vec<usize>,
+    lost_responses: Vec<usize>,
+    /// Line noise, received by the server before every request
+    noise: Vec<u8>,
+}
+
+impl<'a> Loopback<'a> {
+    fn new(flash: &'a mut Flash) -> Self {
+        Self {
+            server: UpdateServer::for_cells_of::<Cell2>(flash),
+            responses: VecDeque::new(),
+            requests: 0,
+            sent_responses: 0,
+            corrupt_requests: Vec::new(),
+            corrupt_responses: Vec::new(),
+            lost_requests: Vec::new(),
+            lost_responses: Vec::new(),
+            noise: Vec::new(),
+        }
+    }
+
+    /// Send a request without the client and decode its response
+    fn request(&mut self, kind: Kind, payload: &[u8]) -> Status {
+        let mut frame = [0; MAX_FRAME];
+        let len = encode_frame(kind as u8, payload, &mut frame);
+        self.write_all(&frame[..len]).unwrap();
+
+        let mut decoder = FrameDecoder::new();
+        while let Some(byte) = self.responses.pop_front() {
+            if let Some(frame) = decoder.push(byte) {
+                return Status::from_bytes(frame.unwrap().payload()).unwrap();
+            }
+        }
+        panic!("no response to {:?}", kind);
+    }
+}
+
+impl Write for Loopback<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut request = self.noise.clone();
+        request.extend_from_slice(buf);
+        if self.corrupt_requests.contains(&self.requests) {
+            corrupt(&mut request);
+        }
+        let lost = self.lost_requests.contains(&self.requests);
+        self.requests += 1;
+        if lost {
+            return Ok(buf.len());
+        }
+
+        let Self { server, responses, sent_responses, corrupt_responses, lost_responses, .. } = self;
+        server.receive(&request, |response| {
+            let mut response = response.to_vec();
+            if corrupt_responses.contains(sent_responses) {
+                corrupt(&mut response);
+            }
+            if !lost_responses.contains(sent_responses) {
+                responses.extend(response);
+            }
+            *sent_responses += 1;
+        });
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for Loopback<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.responses.is_empty() {
+            return Err(ErrorKind::WouldBlock.into());
+        }
+        let len = buf.len().min(self.responses.len());
+        for (out, byte) in buf.iter_mut().zip(self.responses.drain(..len)) {
+            *out = byte;
+        }
+        Ok(len)
+    }
+}
+
+/// Number of requests for an upload without errors: begin, data chunks, end
+fn request_count(image: &[u8]) -> usize {
+    image.len().div_ceil(MAX_DATA) + 2
+}
+
+fn client(transport: Loopback) -> Client<Loopback> {
+    Client::new(transport).with_timeout(Duration::from_millis(20))
+}
+
+fn assert_flashed(flash: &Flash, image: &[u8]) {
+    assert_eq!(&flash.data[SLOT_OFFSET..SLOT_OFFSET + image.len()], image);
+}
+
+#[test]
+fn upload_writes_image() {
+    let image = image();
+    let mut flash = Flash::new();
+    let mut client = client(Loopback::new(&mut flash));
+
+    let mut sent = 0;
+    client.upload("Cell2", Slot::A, &image, |n| sent = n).unwrap();
+    assert_eq!(sent, image.len());
+    let status = client.query().unwrap();
+    assert_eq!((status.state, status.written as usize), (State::Done, image.len()));
+
+    let transport = client.into_inner();
+    assert_eq!(transport.requests, request_count(&image) + 1);
+    drop(transport);
+    assert_flashed(&flash, &image);
+}
+
+#[test]
+fn chunk_is_resent_after_broken_response() {
+    let image = image();
+    let mut flash = Flash::new();
+    let mut transport = Loopback::new(&mut flash);
+    // response to the last data chunk is broken, the server receives the chunk twice
+    transport.corrupt_responses.push(request_count(&image) - 2);
+    let mut client = client(transport);
+
+    client.upload("Cell2", Slot::A, &image, |_| {}).unwrap();
+
+    let transport = client.into_inner();
+    assert_eq!(transport.requests, request_count(&image) + 1);
+    assert_eq!(transport.server.state(), State::Done);
+    drop(transport);
+    assert_flashed(&flash, &image);
+}
+
+#[test]
+fn broken_request_is_resent_after_resync() {
+    let image = image();
+    let mut flash = Flash::new();
+    let mut transport = Loopback::new(&mut flash);
+    transport.corrupt_requests.extend([0, 2, request_count(&image) - 1]);
+    // garbage without a frame start is skipped by the decoder
+    transport.noise = vec![0x00, 0x55, 0xFF];
+    let mut client = client(transport);
+
+    client.upload("Cell2", Slot::A, &image, |_| {}).unwrap();
+
+    let transport = client.into_inner();
+    assert_eq!(transport.requests, request_count(&image) + 3);
+    assert_eq!(transport.server.state(), State::Done);
+    drop(transport);
+    assert_flashed(&flash, &image);
+}
+
+#[test]
+fn lost_frames_are_resent_after_timeout() {
+    let image = image();
+    let mut flash = Flash::new();
+    let mut transport = Loopback::new(&mut flash);
+    // lost begin request, lost response to a data chunk (the chunk is received twice) and to the end request
+    transport.lost_requests.push(0);
+    transport.lost_responses.extend([2, request_count(&image) - 1]);
+    let mut client = client(transport);
+
+    client.upload("Cell2", Slot::A, &image, |_| {}).unwrap();
+
+    let transport = client.into_inner();
+    assert_eq!(transport.requests, request_count(&image) + 3);
+    assert_eq!(transport.server.state(), State::Done);
+    drop(transport);
+    assert_flashed(&flash, &image);
+}
+
+#[test]
+fn silent_device_fails_upload() {
+    let image = image();
+    let mut flash = Flash::new();
+    let mut transport = Loopback::new(&mut flash);
+    transport.lost_requests.extend(0..3);
+    let mut client = client(transport);
+
+    let err = client.upload("Cell2", Slot::A, &image, |_| {}).unwrap_err();
+    assert_eq!(format!("{:#}", err), "Failed to start update: no valid response to request Begin after 3 attempts");
+    assert_eq!(client.into_inner().server.state(), State::Idle);
+}
+
+#[test]
+fn broken_frame_is_reported() {
+    let mut flash = Flash::new();
+    let mut transport = Loopback::new(&mut flash);
+    transport.corrupt_requests.push(0);
+
+    let status = transport.request(Kind::Query, &[]);
+    assert_eq!((status.request, status.result), (0, ResultCode::BadFrame));
+    let status = transport.request(Kind::Query, &[]);
+    assert_eq!((status.request, status.result), (Kind::Query as u8, ResultCode::Ok));
+}
+
+#[test]
+fn out_of_order_chunk_is_refused() {
+    let image = image();
+    let mut flash = Flash::new();
+    let mut transport = Loopback::new(&mut flash);
+
+    assert_eq!(transport.request(Kind::Begin, &begin_payload(image.len())).result, ResultCode::Ok);
+    let status = transport.request(Kind::Data, &data_payload(MAX_DATA, &image[MAX_DATA..2 * MAX_DATA]));
+    assert_eq!((status.result, status.written), (ResultCode::OutOfOrder, 0));
+
+    assert_eq!(transport.request(Kind::Data, &data_payload(0, &image[..MAX_DATA])).result, ResultCode::Ok);
+    // chunk overlapping with the written part, but not the last one
+    let status = transport.request(Kind::Data, &data_payload(MAX_DATA / 2, &image[MAX_DATA / 2..MAX_DATA * 3 / 2]));
+    assert_eq!((status.result, status.written as usize), (ResultCode::OutOfOrder, MAX_DATA));
+    assert_eq!(transport.server.state(), State::Receiving);
+}
+
+#[test]
+fn end_before_complete_image_is_refused() {
+    let image = image();
+    let mut flash = Flash::new();
+    let mut transport = Loopback::new(&mut flash);
+
+    assert_eq!(transport.request(Kind::Begin, &begin_payload(image.len())).result, ResultCode::Ok);
+    for (i, chunk) in image[..image.len() - 1].chunks(MAX_DATA).enumerate() {
+        assert_eq!(transport.request(Kind::Data, &data_payload(i * MAX_DATA, chunk)).result, ResultCode::Ok);
+    }
+    let status = transport.request(Kind::End, &[]);
+    assert_eq!((status.result, status.state), (ResultCode::Incomplete, State::Receiving));
+
+    // the update goes on with the missing byte
+    let status = transport.request(Kind::Data, &data_payload(image.len() - 1, &image[image.len() - 1..]));
+    assert_eq!(status.result, ResultCode::Ok);
+    let status = transport.request(Kind::End, &[]);
+    assert_eq!((status.result, status.state), (ResultCode::Ok, State::Done));
+    drop(transport);
+    assert_flashed(&flash, &image);
+}
+
+#[test]
+fn rejected_image_fails_upload() {
+    let mut image = image();
+    image[HEADER_SIZE + 10] ^= 0x01;
+    let mut flash = Flash::new();
+    let mut client = client(Loopback::new(&mut flash));
+
+    let err = client.upload("Cell2", Slot::A, &image, |_| {}).unwrap_err();
+    assert_eq!(format!("{:#}", err), "Image was rejected: device: image checksum mismatch");
+    assert_eq!(client.query().unwrap().state, State::Failed);
+    drop(client);
+    // header signature is not written
+    assert_eq!(&flash.data[SLOT_OFFSET..SLOT_OFFSET + 4], &[0xFF; 4]);
+}
@@ -0,0 +1,85 @@
+//! `emcell sign` output, checked by `emcell::integrity::verify_signature` the same way as on the device
+#![cfg(target_os = "linux")]
+
+#[macro_use]
+extern crate emcell_macro;
+
+mod common;
+
+use common::{cell_image, defs, flash, Cell3, KEY_SEED};
+use ed25519_dalek::SigningKey;
+use emcell::integrity::verify_signature;
+use emcell::meta::{header_layout, ImageInfo};
+use emcell::{Cell, CellError, Slot};
+use emcell_cli::elf::CellElf;
+use emcell_cli::header::{hex, ParsedHeader};
+use emcell_cli::patch::patch;
+use emcell_cli::sign::{check_signature, public_key_hex, sign};
+
+fn key() -> SigningKey {
+    SigningKey::from_bytes(&KEY_SEED)
+}
+
+fn image_info(elf: &CellElf) -> ImageInfo {
+    ParsedHeader::parse(elf.header_bytes().unwrap()).unwrap().image
+}
+
+/// Signed image with the byte at `addr` changed after signing
+fn tampered(signed: &CellElf, addr: u64) -> CellElf {
+    let section = signed.sections.iter().find(|section| (section.addr..section.addr + section.size).contains(&addr)).unwrap();
+    let mut data = signed.data.clone();
+    data[(section.file_range.unwrap().0 + addr - section.addr) as usize] ^= 0xFF;
+    CellElf::parse("cell.elf", data).unwrap()
+}
+
+#[test]
+fn public_key_matches_definitions() {
+    assert_eq!(public_key_hex(&key()), hex(&Cell3::CUR_META.public_key.unwrap()));
+}
+
+#[test]
+fn signed_image_passes_verify_signature() {
+    let signed = sign(&cell_image::<Cell3>(Slot::A).parse(), Some(&defs()), &key()).unwrap();
+    assert_eq!(check_signature(&signed, Some(&defs())), Vec::<String>::new());
+
+    let mut flash = flash();
+    flash.program(&signed);
+    assert_eq!(verify_signature::<Cell3>(Slot::A, &image_info(&signed)), Ok(()));
+}
+
+#[test]
+fn unsigned_image_is_rejected() {
+    let (patched, info) = patch(&cell_image::<Cell3>(Slot::A).parse(), Some(&defs())).unwrap();
+    assert_eq!(check_signature(&patched, Some(&defs())), vec!["cell must be signed, but the image has no signature".to_string()]);
+
+    let mut flash = flash();
+    flash.program(&patched);
+    assert_eq!(verify_signature::<Cell3>(Slot::A, &info), Err(CellError::ImageNotSigned));
+}
+
+#[test]
+fn tampered_image_is_rejected() {
+    let signed = sign(&cell_image::<Cell3>(Slot::A).parse(), Some(&defs()), &key()).unwrap();
+    let region = Cell3::CUR_META.partitioned_flash(&Cell3::DEVICE_CONFIG);
+    let expected = vec![format!("image signature does not match public key {}", public_key_hex(&key()))];
+
+    for addr in [region.start_flash + 100, region.start_header + header_layout::SECURITY_COUNTER] {
+        let tampered = tampered(&signed, addr as u64);
+        assert_eq!(check_signature(&tampered, Some(&defs())), expected);
+
+        let mut flash = flash();
+        flash.program(&tampered);
+        assert_eq!(verify_signature::<Cell3>(Slot::A, &image_info(&tampered)), Err(CellError::ImageSignatureInvalid));
+    }
+}
+
+#[test]
+fn wrong_key_is_rejected() {
+    let signed = sign(&cell_image::<Cell3>(Slot::A).parse(), Some(&defs()), &SigningKey::from_bytes(&[0x24; 32])).unwrap();
+    assert_eq!(check_signature(&signed, Some(&defs())),
+               vec![format!("image signature does not match public key {}", public_key_hex(&key()))]);
+
+    let mut flash = flash();
+    flash.program(&signed);
+    assert_eq!(verify_signature::<Cell3>(Slot::A, &image_info(&signed)), Err(CellError::ImageSignatureInvalid));
+}
@@ -0,0 +1,114 @@
+//! `emcell verify` checks of linked images against the cells definitions
+
+#[macro_use]
+extern crate emcell_macro;
+
+mod common;
+
+use common::{cell_image, cell_image_at, defs, Cell1, Cell2, Cell3};
+use emcell::meta::{header_layout, HEADER_SIZE};
+use emcell::{Cell, Slot};
+use emcell_cli::header::ParsedHeader;
+use emcell_cli::verify::{cross_check, verify};
+
+#[test]
+fn elf_provides_header_and_references_other_cells() {
+    let elf = cell_image::<Cell2>(Slot::A).parse();
+    let region = Cell2::CUR_META.partitioned_flash(&Cell2::DEVICE_CONFIG);
+
+    let header = elf.header.as_ref().unwrap();
+    assert_eq!(header.cell_name, "Cell2");
+    assert_eq!(header.addr, region.start_header as u64);
+    assert_eq!(header.size, HEADER_SIZE as u64);
+    let cell1_header = Cell1::CUR_META.partitioned_flash(&Cell1::DEVICE_CONFIG).start_header as u64;
+    let cell3_header = Cell3::CUR_META.partitioned_flash(&Cell3::DEVICE_CONFIG).start_header as u64;
+    assert_eq!(elf.extern_headers, vec![("Cell1".to_string(), cell1_header), ("Cell3".to_string(), cell3_header)]);
+    assert_eq!(elf.segments.iter().map(|segment| segment.paddr).min(), Some(region.start_header as u64));
+}
+
+#[test]
+fn header_is_parsed_from_image() {
+    let elf = cell_image::<Cell2>(Slot::B).parse();
+    let header = ParsedHeader::parse(elf.header_bytes().unwrap()).unwrap();
+
+    assert_eq!(header.signature, Cell2::CUR_META.signature);
+    assert_eq!(header.struct_sha256, Cell2::CUR_META.struct_sha256);
+    assert_eq!(header.field_count, 1);
+    assert_eq!(header.linked_slot, Slot::B as u32);
+    assert!(!header.image.is_patched());
+}
+
+#[test]
+fn short_header_is_rejected() {
+    let error = ParsedHeader::parse(&[0; header_layout::USER_FIELDS - 1]).err().unwrap();
+    assert_eq!(error.to_string(), format!("header is too short: {} bytes, at least {} expected",
+                                          header_layout::USER_FIELDS - 1, header_layout::USER_FIELDS));
+}
+
+#[test]
+fn good_images_have_no_problems() {
+    for elf in [cell_image::<Cell1>(Slot::A), cell_image::<Cell2>(Slot::A), cell_image::<Cell2>(Slot::B)] {
+        assert_eq!(verify(&elf.parse(), &defs()), Vec::<String>::new());
+    }
+    let elfs = [cell_image::<Cell1>(Slot::A).parse(), cell_image::<Cell2>(Slot::A).parse()];
+    assert_eq!(cross_check(&elfs), Vec::<String>::new());
+}
+
+#[test]
+fn signature_mismatch_is_reported() {
+    let elf = cell_image::<Cell2>(Slot::A)
+        .with_header(header_layout::SIGNATURE, &0x1234_5678u32.to_le_bytes())
+        .parse();
+
+    assert_eq!(verify(&elf, &defs()), vec![
+        format!("signature 0x12345678 does not match 0x{:08X} from cells definitions", Cell2::CUR_META.signature),
+    ]);
+}
+
+#[test]
+fn struct_sha_mismatch_names_differing_field() {
+    let mut image = cell_image::<Cell2>(Slot::A).with_header(header_layout::ABI_STRUCT_SHA256, &[0xAB; 32]);
+    let problems = verify(&image.parse(), &defs());
+    assert_eq!(problems, vec![
+        format!("header hash {} does not match cells definitions (layout or device configuration differs)", "ab".repeat(32)),
+    ]);
+
+    image.section_mut(".rodata").data.as_mut().unwrap()[0] ^= 0xFF;
+    let problems = verify(&image.parse(), &defs());
+    assert_eq!(problems, vec![
+        format!("header hash {} does not match cells definitions, first differing field: run", "ab".repeat(32)),
+    ]);
+}
+
+#[test]
+fn header_outside_of_slots_is_reported() {
+    let elf = cell_image_at::<Cell2>(Slot::A, 0x100).parse();
+
+    assert_eq!(verify(&elf, &defs()), vec![
+        "header is located at 0x08004100, but 0x08004000 or 0x0800C000 expected".to_string(),
+    ]);
+}
+
+#[test]
+fn header_of_other_slot_is_reported() {
+    let elf = cell_image::<Cell2>(Slot::B)
+        .with_header(header_layout::LINKED_SLOT, &(Slot::A as u32).to_le_bytes())
+        .parse();
+
+    assert_eq!(verify(&elf, &defs()), vec!["header is located in slot B, but linked for slot 0".to_string()]);
+}
+
+#[test]
+fn misplaced_extern_header_is_reported() {
+    let cell2 = cell_image::<Cell2>(Slot::A);
+    let mut cell1 = cell_image_at::<Cell1>(Slot::A, 0);
+    cell1.symbols.retain(|(name, _)| name != "_emcell_Cell2_internal");
+    let cell1 = cell1.symbol("_emcell_Cell2_internal", 0x0800_4100);
+
+    assert_eq!(verify(&cell1.parse(), &defs()), vec![
+        "header of Cell2 is expected at 0x08004000, but 0x08004100 is used".to_string(),
+    ]);
+    assert_eq!(cross_check(&[cell1.parse(), cell2.parse()]), vec![
+        "cell.elf expects header of Cell2 at 0x08004100, but cell.elf places it at 0x08004000".to_string(),
+    ]);
+}
@@ -0,0 +1,262 @@
+//! Canonical ABI representation of cell header fields.
+//!
+//! Header hash must not depend on how proc-macro2 prints tokens or on cosmetic changes in the
+//! cells definitions crate (formatting, trailing commas, doc comments, argument names, lifetimes),
+//! but must change on every real ABI change: field name, order, type or function signature.
+
+use syn::{Expr, Field, Fields, GenericArgument, Lit, LitInt, PathArguments, ReturnType, Type, TypePath};
+use syn::spanned::Spanned;
+
+/// Field attributes which change the generated header layout and therefore are part of the ABI
+const ABI_ATTRIBUTES: &[&str] = &["switch_vectors"];
+
+/// Canonical representation of the whole field list
+pub fn canonical_fields(fields: &Fields) -> syn::Result<Vec<String>> {
+    fields.iter()
+        .enumerate()
+        .map(|(i, field)| canonical_field(i, field))
+        .collect()
+}
+
+/// Canonical representation of a single field: position, ABI-relevant attributes, name and type
+pub fn canonical_field(index: usize, field: &Field) -> syn::Result<String> {
+    let mut res = index.to_string();
+    res.push(':');
+
+    for attr in &field.attrs {
+        if let Some(name) = ABI_ATTRIBUTES.iter().find(|name| attr.path().is_ident(name)) {
+            res.push('#');
+            res.push_str(name);
+            res.push(':');
+        }
+    }
+
+    if let Some(since) = field_since(field)? {
+        res.push_str(&format!("#since({}):", since));
+    }
+
+    if let Some(ident) = &field.ident {
+        res.push_str(&ident.to_string());
+    }
+    res.push(':');
+    res.push_str(&canonical_type(&field.ty)?);
+    Ok(res)
+}
+
+/// ABI minor version, in which an optional field was added: `#[since(2)]`.
+///
+/// Fields without this attribute belong to ABI version 1 and are required.
+pub fn field_since(field: &Field) -> syn::Result<Option<u32>> {
+    let Some(attr) = field.attrs.iter().find(|attr| attr.path().is_ident("since")) else {
+        return Ok(None);
+    };
+    let version: LitInt = attr.parse_args()?;
+    let version = version.base10_parse::<u32>()?;
+    if version < 2 {
+        return Err(syn::Error::new(attr.span(), "Expected #[since(N)] with N >= 2. Fields without #[since] belong to ABI version 1"));
+    }
+    Ok(Some(version))
+}
+
+pub fn canonical_type(ty: &Type) -> syn::Result<String> {
+    let res = match ty {
+        Type::Path(path) => canonical_type_path(path)?,
+        Type::Reference(reference) => {
+            let mutability = if reference.mutability.is_some() { "mut " } else { "" };
+            format!("&{}{}", mutability, canonical_type(&reference.elem)?)
+        }
+        Type::Ptr(ptr) => {
+            let mutability = if ptr.mutability.is_some() { "mut" } else { "const" };
+            format!("*{} {}", mutability, canonical_type(&ptr.elem)?)
+        }
+        Type::Array(array) => format!("[{};{}]", canonical_type(&array.elem)?, canonical_expr(&array.len)),
+        Type::Slice(slice) => format!("[{}]", canonical_type(&slice.elem)?),
+        Type::Tuple(tuple) => {
+            let elems = tuple.elems.iter().map(canonical_type).collect::<syn::Result<Vec<_>>>()?;
+            format!("({})", elems.join(","))
+        }
+        Type::BareFn(bare_fn) => {
+            let mut res = String::new();
+            if bare_fn.unsafety.is_some() {
+                res.push_str("unsafe ");
+            }
+            if let Some(abi) = &bare_fn.abi {
+                // `extern fn` defaults to "C", `extern "Rust" fn` is the same as plain `fn`
+                let name = abi.name.as_ref().map(|name| name.value()).unwrap_or_else(|| "C".to_string());
+                if name != "Rust" {
+                    res.push_str(&format!("extern \"{}\" ", name));
+                }
+            }
+            let mut inputs = bare_fn.inputs.iter().map(|arg| canonical_type(&arg.ty)).collect::<syn::Result<Vec<_>>>()?;
+            if bare_fn.variadic.is_some() {
+                inputs.push("...".to_string());
+            }
+            let output = match &bare_fn.output {
+                ReturnType::Default => "()".to_string(),
+                ReturnType::Type(_, ty) => canonical_type(ty)?,
+            };
+            res.push_str(&format!("fn({})->{}", inputs.join(","), output));
+            res
+        }
+        Type::Never(_) => "!".to_string(),
+        Type::Paren(paren) => canonical_type(&paren.elem)?,
+        Type::Group(group) => canonical_type(&group.elem)?,
+        Type::TraitObject(object) => {
+            let bounds = object.bounds.iter().filter_map(|bound| match bound {
+                syn::TypeParamBound::Trait(bound) => Some(canonical_path(&bound.path)),
+                _ => None,
+            }).collect::<syn::Result<Vec<_>>>()?;
+            format!("dyn {}", bounds.join("+"))
+        }
+        _ => return Err(syn::Error::new(ty.span(), "This type is not supported in cell header")),
+    };
+    Ok(res)
+}
+
+fn canonical_type_path(path: &TypePath) -> syn::Result<String> {
+    match &path.qself {
+        Some(qself) => {
+            // <T as Trait>::Assoc
+            let trait_segments = path.path.segments.iter().take(qself.position).collect::<Vec<_>>();
+            let rest = path.path.segments.iter().skip(qself.position).collect::<Vec<_>>();
+            let trait_path = trait_segments.into_iter().map(canonical_segment).collect::<syn::Result<Vec<_>>>()?.join("::");
+            let rest = rest.into_iter().map(canonical_segment).collect::<syn::Result<Vec<_>>>()?.join("::");
+            Ok(format!("<{} as {}>::{}", canonical_type(&qself.ty)?, trait_path, rest))
+        }
+        None => canonical_path(&path.path),
+    }
+}
+
+fn canonical_path(path: &syn::Path) -> syn::Result<String> {
+    // leading `::` is dropped: `::core::x` and `core::x` are the same type
+    Ok(path.segments.iter().map(canonical_segment).collect::<syn::Result<Vec<_>>>()?.join("::"))
+}
+
+fn canonical_segment(segment: &syn::PathSegment) -> syn::Result<String> {
+    let mut res = segment.ident.to_string();
+    match &segment.arguments {
+        PathArguments::None => {}
+        PathArguments::AngleBracketed(args) => {
+            // lifetimes do not affect ABI
+            let args = args.args.iter().filter_map(|arg| match arg {
+                GenericArgument::Lifetime(_) => None,
+                GenericArgument::Type(ty) => Some(canonical_type(ty)),
+                GenericArgument::Const(expr) => Some(Ok(canonical_expr(expr))),
+                GenericArgument::AssocType(assoc) => Some(canonical_type(&assoc.ty).map(|ty| format!("{}={}", assoc.ident, ty))),
+                arg => Some(Err(syn::Error::new(arg.span(), "This generic argument is not supported in cell header"))),
+            }).collect::<syn::Result<Vec<_>>>()?;
+            if !args.is_empty() {
+                res.push_str(&format!("<{}>", args.join(",")));
+            }
+        }
+        PathArguments::Parenthesized(args) => {
+            let inputs = args.inputs.iter().map(canonical_type).collect::<syn::Result<Vec<_>>>()?;
+            let output = match &args.output {
+                ReturnType::Default => "()".to_string(),
+                ReturnType::Type(_, ty) => canonical_type(ty)?,
+            };
+            res.push_str(&format!("({})->{}", inputs.join(","), output));
+        }
+    }
+    Ok(res)
+}
+
+/// Integer literals are compared by value (`0x10` == `16` == `16usize`), everything else by tokens without whitespace
+fn canonical_expr(expr: &Expr) -> String {
+    match expr {
+        Expr::Lit(lit) => match &lit.lit {
+            Lit::Int(int) => int.base10_digits().to_string(),
+            lit => quote::ToTokens::to_token_stream(lit).to_string(),
+        },
+        Expr::Paren(paren) => canonical_expr(&paren.expr),
+        Expr::Group(group) => canonical_expr(&group.expr),
+        expr => quote::ToTokens::to_token_stream(expr).to_string().chars().filter(|c| !c.is_whitespace()).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use syn::{parse_quote, Fields, FieldsNamed, Type};
+
+    use super::{canonical_fields, canonical_type};
+
+    fn ty(ty: Type) -> String {
+        canonical_type(&ty).unwrap()
+    }
+
+    fn fields(fields: FieldsNamed) -> Vec<String> {
+        canonical_fields(&Fields::Named(fields)).unwrap()
+    }
+
+    #[test]
+    fn trailing_comma_is_ignored() {
+        assert_eq!(ty(parse_quote!(fn(u32))), ty(parse_quote!(fn(u32,))));
+        assert_eq!(ty(parse_quote!((u8, u16))), ty(parse_quote!((u8, u16,))));
+        assert_eq!(ty(parse_quote!(Option<u32>)), ty(parse_quote!(Option<u32,>)));
+    }
+
+    #[test]
+    fn leading_path_separator_is_ignored() {
+        assert_eq!(ty(parse_quote!(::core::option::Option<u32>)), ty(parse_quote!(core::option::Option<u32>)));
+        assert_eq!(ty(parse_quote!(fn() -> ::core::primitive::u8)), ty(parse_quote!(fn() -> core::primitive::u8)));
+    }
+
+    #[test]
+    fn attributes_and_doc_comments_are_ignored() {
+        let plain = fields(parse_quote!({ run: fn(u32) -> u32, value: u8 }));
+        let documented = fields(parse_quote!({
+            /// Run the cell
+            #[allow(dead_code)]
+            run: fn(u32) -> u32,
+            #[doc = "value"]
+            value: u8,
+        }));
+        assert_eq!(plain, documented);
+    }
+
+    #[test]
+    fn lifetimes_and_argument_names_are_ignored() {
+        assert_eq!(ty(parse_quote!(&'static str)), ty(parse_quote!(&str)));
+        assert_eq!(ty(parse_quote!(Ref<'static, u32>)), ty(parse_quote!(Ref<u32>)));
+        assert_eq!(ty(parse_quote!(for<'a> fn(&'a [u8]) -> &'a u8)), ty(parse_quote!(fn(&[u8]) -> &u8)));
+        assert_eq!(ty(parse_quote!(fn(value: u32))), ty(parse_quote!(fn(u32))));
+    }
+
+    #[test]
+    fn rust_abi_is_default() {
+        assert_eq!(ty(parse_quote!(extern "Rust" fn(u32))), ty(parse_quote!(fn(u32))));
+        assert_eq!(ty(parse_quote!(extern fn(u32))), ty(parse_quote!(extern "C" fn(u32))));
+        assert_ne!(ty(parse_quote!(extern "C" fn(u32))), ty(parse_quote!(fn(u32))));
+    }
+
+    #[test]
+    fn integer_literals_are_compared_by_value() {
+        assert_eq!(ty(parse_quote!([u8; 0x10])), ty(parse_quote!([u8; 16usize])));
+    }
+
+    #[test]
+    fn type_changes_are_detected() {
+        let pairs: [(Type, Type); 8] = [
+            (parse_quote!(fn(u32)), parse_quote!(fn(u64))),
+            (parse_quote!(fn(u32)), parse_quote!(fn(u32) -> u32)),
+            (parse_quote!(fn(u32, u8)), parse_quote!(fn(u8, u32))),
+            (parse_quote!(fn(u32)), parse_quote!(unsafe fn(u32))),
+            (parse_quote!(&u32), parse_quote!(&mut u32)),
+            (parse_quote!(*const u8), parse_quote!(*mut u8)),
+            (parse_quote!([u8; 16]), parse_quote!([u8; 32])),
+            (parse_quote!(core::option::Option<u32>), parse_quote!(core::option::Option<i32>)),
+        ];
+        for (a, b) in pairs {
+            assert_ne!(ty(a.clone()), ty(b.clone()), "{} and {}", quote::quote!(#a), quote::quote!(#b));
+        }
+    }
+
+    #[test]
+    fn field_changes_are_detected() {
+        let base = fields(parse_quote!({ run: fn(u32), value: u8 }));
+        assert_ne!(base, fields(parse_quote!({ run: fn(u32), value: u16 })));
+        assert_ne!(base, fields(parse_quote!({ start: fn(u32), value: u8 })));
+        assert_ne!(base, fields(parse_quote!({ value: u8, run: fn(u32) })));
+        assert_ne!(base, fields(parse_quote!({ #[switch_vectors] run: fn(u32), value: u8 })));
+    }
+}
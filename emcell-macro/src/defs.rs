@@ -1,8 +1,9 @@
 use proc_macro::TokenStream;
+use crate::abi;
 use proc_macro2::{Ident, Span};
 use quote::{quote, ToTokens};
 use sha2::{Digest, Sha256};
-use syn::{Data, DataStruct, DeriveInput, ExprMacro, Field, Fields, FieldValue, ItemStruct, LitInt, Member, Meta, parse2, parse_macro_input, parse_quote, Type, Visibility};
+use syn::{Attribute, Data, DataStruct, DeriveInput, ExprMacro, Field, Fields, FieldValue, ItemStruct, LitInt, LitStr, Member, Meta, parse2, parse_macro_input, parse_quote, Token, Type};
 use syn::parse::{Parse, Parser, ParseStream};
 use syn::punctuated::Punctuated;
 use syn::spanned::Spanned;
@@ -14,7 +15,46 @@ struct EmcellDef {
 
     ram_region: RamRegion,
     flash_region: FlashRegion,
+    // second #[flash_region], see emcell::Slot
+    flash_slot_b: Option<FlashRegion>,
     struct_sha256: [u8; 32],
+    field_fingerprints: Vec<u32>,
+    signature: u32,
+    // fields without #[since], covered by struct_sha256
+    base_field_count: usize,
+    abi_minor: u32,
+    public_key: Option<[u8; 32]>,
+    // #[stack(size[, guarded])], at the top or bottom of the RAM region, see StackStrategy
+    stack: Option<CellStack>,
+}
+
+struct CellStack {
+    size: usize,
+    // RAM below the region is guarded by the MPU, so a cell_bottom stack may overflow into it
+    guarded: bool,
+    span: Span,
+}
+
+impl Parse for CellStack {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let size: LitInt = input.parse()?;
+        let mut guarded = false;
+        if !input.is_empty() {
+            let _: Comma = input.parse()?;
+            let key: Ident = input.parse()?;
+            if key != "guarded" {
+                return Err(syn::Error::new(key.span(), "Unknown stack parameter! Expected guarded"));
+            }
+            guarded = true;
+        }
+        Ok(CellStack { size: parse_integer_lit(&size)?, guarded, span: size.span() })
+    }
+}
+
+impl EmcellDef {
+    fn flash_slots(&self) -> impl Iterator<Item = &FlashRegion> {
+        core::iter::once(&self.flash_region).chain(&self.flash_slot_b)
+    }
 }
 
 impl ToTokens for EmcellDef {
@@ -32,17 +72,49 @@ impl ToTokens for EmcellDef {
         };
 
         let hash = self.struct_sha256;
-
+        let field_names = self.strukt.fields.iter()
+            .map(|field| field.ident.as_ref().map(|ident| ident.to_string()).unwrap_or_default());
+        let field_fingerprints = &self.field_fingerprints;
+        let signature = self.signature;
+        let base_field_count = self.base_field_count;
+        let abi_minor = self.abi_minor;
+        let slot_b = match &self.flash_slot_b {
+            Some(FlashRegion { start, end, .. }) => quote! { Some((#start, #end)) },
+            None => quote! { None },
+        };
+        let public_key = match self.public_key {
+            Some(key) => quote! { Some([#(#key),*]) },
+            None => quote! { None },
+        };
+        let stack_size = match &self.stack {
+            Some(CellStack { size, .. }) => quote! { Some(#size) },
+            None => quote! { None },
+        };
 
         tokens.extend(quote! {
             emcell::meta::CellDefMeta {
                 name: #name,
                 cell_type: #cell_type,
+                signature: #signature,
                 ram_range_start_offs: #ram_region_start,
                 ram_range_end_offs: #ram_region_end,
                 flash_range_start_offs: #flash_region_start,
                 flash_range_end_offs: #flash_region_end,
                 struct_sha256: [#(#hash),*],
+                field_names: &[#(#field_names),*],
+                field_fingerprints: &[#(#field_fingerprints),*],
+                base_field_count: #base_field_count,
+                abi_minor: #abi_minor,
+                public_key: #public_key,
+                slot_b_flash_range_offs: #slot_b,
+                stack_size: #stack_size,
+                version: emcell::meta::CellVersion::new(
+                    env!("CARGO_PKG_NAME"),
+                    env!("CARGO_PKG_VERSION"),
+                    option_env!("EMCELL_GIT_REVISION"),
+                    option_env!("EMCELL_BUILD_TIMESTAMP"),
+                    #abi_minor,
+                ),
             }
         });
     }
@@ -52,6 +124,14 @@ struct EmcellDeviceConfiguration {
     initial_stack_pointer: usize,
     ram_region: RamRegion,
     flash_region: FlashRegion,
+    /// `#[noinit_region(start, end)]`, offsets into device RAM like `#[ram_region]`. Starts with `emcell::boot::BootMailbox`
+    noinit_region: Option<RamRegion>,
+    stack_strategy: StackStrategy,
+    /// Shared stack `[end, start)`, offsets into device RAM. Computed by `shared_stack` after cells are parsed
+    stack: (usize, usize),
+
+    span: Span,
+    initial_stack_pointer_span: Span,
 }
 
 impl ToTokens for EmcellDeviceConfiguration {
@@ -61,6 +141,16 @@ impl ToTokens for EmcellDeviceConfiguration {
         let ram_region_end = self.ram_region.end;
         let flash_region_start = self.flash_region.start;
         let flash_region_end = self.flash_region.end;
+        let noinit_region = match &self.noinit_region {
+            Some(RamRegion { start, end, .. }) => quote! { Some((#start, #end)) },
+            None => quote! { None },
+        };
+        let stack_strategy = match self.stack_strategy {
+            StackStrategy::GlobalBottom => quote! { emcell::meta::StackStrategy::GlobalBottom },
+            StackStrategy::GlobalTop => quote! { emcell::meta::StackStrategy::GlobalTop },
+            StackStrategy::CellBottom => quote! { emcell::meta::StackStrategy::CellBottom },
+        };
+        let (stack_end, stack_start) = (self.ram_region.start + self.stack.0, self.ram_region.start + self.stack.1);
 
         tokens.extend(quote! {
             emcell::meta::DeviceConfigMeta {
@@ -69,6 +159,9 @@ impl ToTokens for EmcellDeviceConfiguration {
                 ram_range_end: #ram_region_end,
                 flash_range_start: #flash_region_start,
                 flash_range_end: #flash_region_end,
+                noinit_range_offs: #noinit_region,
+                stack_strategy: #stack_strategy,
+                stack_range: (#stack_end, #stack_start),
             }
         });
     }
@@ -87,20 +180,38 @@ impl Parse for DeviceMacroParams {
 
 impl Parse for EmcellDeviceConfiguration {
     fn parse(input: ParseStream) -> syn::Result<Self> {
+        let attrs = input.call(Attribute::parse_outer)?;
         let device_config_macro: ExprMacro = input.parse()?;
         if !device_config_macro.mac.path.is_ident("device") {
             return Err(syn::Error::new(device_config_macro.span(), "Expected device! macro"));
         }
 
+        let span = device_config_macro.span();
+
+        let mut noinit_region = None;
+        for attr in &attrs {
+            if !attr.path().is_ident("noinit_region") {
+                return Err(syn::Error::new(attr.span(), "Unknown device! attribute! Expected #[noinit_region(start, end)]"));
+            }
+            let mut region = syn::parse2::<RamRegion>(attr.meta.require_list()?.tokens.clone())?;
+            if let Some(cell) = region.shared_with.first() {
+                return Err(syn::Error::new(cell.span(), "noinit_region is shared by all cells, shared_with is not allowed"));
+            }
+            region.span = attr.span();
+            noinit_region = Some(region);
+        }
+
         //parse macro content as struct fields list
         let device_config: DeviceMacroParams = parse2(device_config_macro.mac.tokens)?;
         let device_config = device_config.0;
 
         let mut initial_stack_pointer = None;
+        let mut initial_stack_pointer_span = span;
         let mut ram_region_start = None;
         let mut ram_region_end = None;
         let mut flash_region_start = None;
         let mut flash_region_end = None;
+        let mut stack_strategy = StackStrategy::GlobalBottom;
 
         for field in device_config.iter() {
             match &field.member {
@@ -122,6 +233,7 @@ impl Parse for EmcellDeviceConfiguration {
                     match ident.to_string().as_str() {
                         "initial_stack_ptr" => {
                             initial_stack_pointer = Some(expr_into_lit_int(&field.expr)?);
+                            initial_stack_pointer_span = field.span();
                         }
                         "ram_range_start" => {
                             ram_region_start = Some(expr_into_lit_int(&field.expr)?);
@@ -135,6 +247,9 @@ impl Parse for EmcellDeviceConfiguration {
                         "flash_range_end" => {
                             flash_region_end = Some(expr_into_lit_int(&field.expr)?);
                         }
+                        "stack_strategy" => {
+                            stack_strategy = StackStrategy::parse(&field.expr)?;
+                        }
                         _ => {}
                     }
 
@@ -161,13 +276,59 @@ impl Parse for EmcellDeviceConfiguration {
         };
 
         Ok(EmcellDeviceConfiguration {
-            ram_region: RamRegion { start: ram_region_start, end: ram_region_end },
-            flash_region: FlashRegion { start: flash_region_start, end: flash_region_end },
-            initial_stack_pointer
+            ram_region: RamRegion { start: ram_region_start, end: ram_region_end, shared_with: Vec::new(), span },
+            flash_region: FlashRegion { start: flash_region_start, end: flash_region_end, span },
+            initial_stack_pointer,
+            noinit_region,
+            stack_strategy,
+            stack: (0, 0),
+            span,
+            initial_stack_pointer_span,
         })
     }
 }
 
+/// `stack_strategy` of `device!`, see `emcell::meta::StackStrategy`
+#[derive(Copy, Clone, PartialEq)]
+enum StackStrategy {
+    GlobalBottom,
+    GlobalTop,
+    CellBottom,
+}
+
+impl StackStrategy {
+    fn parse(expr: &syn::Expr) -> syn::Result<Self> {
+        let err = || syn::Error::new(expr.span(), "Expected stack strategy: global_bottom, global_top or cell_bottom");
+        let syn::Expr::Path(path) = expr else {
+            return Err(err());
+        };
+        match path.path.get_ident().map(|ident| ident.to_string()).as_deref() {
+            Some("global_bottom") => Ok(StackStrategy::GlobalBottom),
+            Some("global_top") => Ok(StackStrategy::GlobalTop),
+            Some("cell_bottom") => Ok(StackStrategy::CellBottom),
+            _ => Err(err()),
+        }
+    }
+}
+
+/// Shared stack `[end, start)` as offsets into device RAM.
+///
+/// It grows down from `initial_stack_ptr`: to the device RAM start, or with `global_top` to the end of the highest
+/// region below it (cell RAM or no-init region).
+fn shared_stack(device: &EmcellDeviceConfiguration, cells: &[EmcellDef]) -> (usize, usize) {
+    let start = device.initial_stack_pointer.saturating_sub(device.ram_region.start);
+    if device.stack_strategy != StackStrategy::GlobalTop {
+        return (0, start);
+    }
+
+    let end = cells.iter().map(|cell| cell.ram_region.end)
+        .chain(device.noinit_region.as_ref().map(|region| region.end))
+        .filter(|end| *end <= start)
+        .max()
+        .unwrap_or(0);
+    (end, start)
+}
+
 struct EmcellConfiguration {
     device: EmcellDeviceConfiguration,
     cells: Vec<EmcellDef>
@@ -186,7 +347,9 @@ impl Parse for EmcellConfiguration {
 
             let mut is_primary = None;
             let mut ram_region = None;
-            let mut flash_region = None;
+            let mut flash_regions = Vec::new();
+            let mut public_key = None;
+            let mut stack = None;
 
             for attr in &strukt.attrs {
                 let meta = &attr.meta;
@@ -195,11 +358,24 @@ impl Parse for EmcellConfiguration {
                 match name {
                     _ if name.is_ident("ram_region") => {
                         let meta = meta.require_list()?;
-                        ram_region = Some(syn::parse2::<RamRegion>(meta.tokens.clone())?);
+                        let mut region = syn::parse2::<RamRegion>(meta.tokens.clone())?;
+                        region.span = attr.span();
+                        ram_region = Some(region);
                     }
                     _ if name.is_ident("flash_region") => {
                         let meta = meta.require_list()?;
-                        flash_region = Some(syn::parse2::<FlashRegion>(meta.tokens.clone())?);
+                        let mut region = syn::parse2::<FlashRegion>(meta.tokens.clone())?;
+                        region.span = attr.span();
+                        flash_regions.push(region);
+                    }
+                    _ if name.is_ident("stack") => {
+                        let mut cell_stack: CellStack = attr.parse_args()?;
+                        cell_stack.span = attr.span();
+                        stack = Some(cell_stack);
+                    }
+                    _ if name.is_ident("signed") => {
+                        let meta = meta.require_list()?;
+                        public_key = Some(syn::parse2::<SignedParams>(meta.tokens.clone())?.public_key);
                     }
                     _ if name.is_ident("cell") => {
                         match meta {
@@ -225,26 +401,34 @@ impl Parse for EmcellConfiguration {
             let Some(ram_region) = ram_region else {
                 return Err(syn::Error::new(strukt.span(), "Attribute #[ram_region(start, end)] required for each struct definition"));
             };
-            let Some(flash_region) = flash_region else {
+            let mut flash_regions = flash_regions.into_iter();
+            let Some(flash_region) = flash_regions.next() else {
                 return Err(syn::Error::new(strukt.span(), "Attribute #[flash_region(start, end)] required for each struct definition"));
             };
+            let flash_slot_b = flash_regions.next();
+            if let Some(extra) = flash_regions.next() {
+                return Err(syn::Error::new(extra.span, "At most two #[flash_region] slots (A and B) are allowed"));
+            }
             let Some(is_primary) = is_primary else {
                 return Err(syn::Error::new(strukt.span(), "Required attribute #[cell] or #[cell(primary)] missing for struct definition"));
             };
-
-
-            let mut hasher = Sha256::new();
-            let fields = &strukt.fields;
-            hasher.update(fields.to_token_stream().to_string().as_bytes());
-            let hash = hasher.finalize();
-            let struct_sha256 = hash.as_slice().try_into().unwrap();
+            if let (true, Some(slot_b)) = (is_primary, &flash_slot_b) {
+                return Err(syn::Error::new(slot_b.span, "Primary cell cannot have a second flash slot"));
+            }
 
             cells.push(EmcellDef {
                 strukt,
                 is_primary,
                 ram_region,
                 flash_region,
-                struct_sha256
+                flash_slot_b,
+                struct_sha256: [0; 32],
+                field_fingerprints: Vec::new(),
+                signature: 0,
+                base_field_count: 0,
+                abi_minor: 1,
+                public_key,
+                stack,
             });
         }
 
@@ -254,6 +438,18 @@ impl Parse for EmcellConfiguration {
         if primary_count == 0 {
             return Err(syn::Error::new(Span::call_site(), "No primary cell found. At least one cell must be marked as #[cell(primary)]"));
         }
+
+        let mut device = device;
+        device.stack = shared_stack(&device, &cells);
+        validate_layout(&device, &cells)?;
+
+        for cell in &mut cells {
+            (cell.base_field_count, cell.abi_minor) = validate_optional_fields(cell)?;
+            cell.struct_sha256 = header_sha256(cell, &device)?;
+            cell.field_fingerprints = field_fingerprints(cell)?;
+            cell.signature = cell_signature(cell);
+        }
+
         Ok(EmcellConfiguration{
             cells,
             device
@@ -269,8 +465,8 @@ pub fn emcell_configuration(input: TokenStream) -> TokenStream {
     let mut cell_idents = Vec::new();
     let mut cell_indices = Vec::new();
 
-    let mut non_primary_cell_idents = Vec::new();
     let mut primary_cell = None;
+    let mut boot_entries = Vec::new();
 
     for (i, cell) in emcell_configuration.cells.iter().enumerate() {
         let cell_name = cell.strukt.ident.to_string();
@@ -278,17 +474,26 @@ pub fn emcell_configuration(input: TokenStream) -> TokenStream {
         cell_idents.push(cell.strukt.ident.clone());
         cell_indices.push(i);
 
+        // only a diverging #[switch_vectors] entry can boot the cell
+        let switch_vectors_field = cell.strukt.fields.iter()
+            .find(|field| field.attrs.iter().any(|attr| attr.path().is_ident("switch_vectors")))
+            .filter(|field| matches!(switch_vectors_output(&field.ty), Some(None)))
+            .and_then(|field| field.ident.as_ref());
+        boot_entries.push(match switch_vectors_field {
+            Some(ident) => quote! { Some(self.#ident) },
+            None => quote! { None },
+        });
+
         if cell.is_primary {
             primary_cell = Some(cell);
         }
-        else {
-            non_primary_cell_idents.push(cell.strukt.ident.clone());
-        }
     }
     let primary_cell = primary_cell.unwrap();
     let primary_cell_ident = &primary_cell.strukt.ident;
 
     let cell_count = cell_names.len();
+    let header_size = HEADER_SIZE;
+    let boot_mailbox_size = BOOT_MAILBOX_SIZE;
 
     let emcell_defs = &emcell_configuration.cells;
     let emcell_device = emcell_configuration.device;
@@ -302,17 +507,46 @@ pub fn emcell_configuration(input: TokenStream) -> TokenStream {
             const CUR_META: emcell::meta::CellDefMeta = META.cell_defs[#cell_indices];
             const DEVICE_CONFIG: emcell::meta::DeviceConfigMeta = META.device_configuration;
             const CELLS_META: &'static [emcell::meta::CellDefMeta] = &META.cell_defs;
-            fn check_signature(&self, init_memory: bool) -> bool {
-                if self.signature != <Self as emcell::WithSignature>::VALID_SIGNATURE { // a little silly :3
-                    return false;
-                }
-
+            fn version(&self) -> &emcell::meta::CellVersion {
+                &self.version
+            }
+            fn image_info(&self) -> &emcell::meta::ImageInfo {
+                &self.image
+            }
+            fn security_counter(&self) -> u32 {
+                self.security_counter
+            }
+            fn linked_slot(&self) -> u32 {
+                self.linked_slot
+            }
+            fn boot_entry(&self) -> Option<fn() -> !> {
+                #boot_entries
+            }
+            fn check_header(&self) -> Result<(), emcell::CellError> {
+                emcell::check_header::<Self>(self.signature, &self.abi)
+            }
+            unsafe fn call_init(&self, init_memory: bool) -> Result<(), emcell::CellError> {
                 let known_sha256 = Self::CUR_META.struct_sha256;
-                let sha_ok = unsafe {(self.init)(known_sha256, init_memory)};
-                return sha_ok;
+                let init_ok = unsafe {(self.init)(known_sha256, init_memory)};
+                if !init_ok {
+                    return Err(emcell::CellError::InitFailed);
+                }
+                Ok(())
             }
         }
 
+        // host tools rely on emcell::meta::header_layout
+        #[cfg(target_pointer_width = "32")]
+        const _: () = {
+            assert!(core::mem::offset_of!(#cell_idents, abi) == emcell::meta::header_layout::ABI);
+            assert!(core::mem::offset_of!(#cell_idents, version) == emcell::meta::header_layout::VERSION);
+            assert!(core::mem::offset_of!(#cell_idents, image) == emcell::meta::header_layout::IMAGE);
+            assert!(core::mem::offset_of!(#cell_idents, security_counter) == emcell::meta::header_layout::SECURITY_COUNTER);
+            assert!(core::mem::offset_of!(#cell_idents, linked_slot) == emcell::meta::header_layout::LINKED_SLOT);
+        };
+        const _: () = assert!(core::mem::size_of::<#cell_idents>() <= emcell::meta::header_layout::SIGNATURE_TRAILER,
+                              "Cell header does not fit into the header region");
+
         impl #cell_idents {
             pub const fn get_cell_start_flash_addr() -> usize {
                 <Self as emcell::Cell>::CUR_META.absolute_flash_start(&META.device_configuration)
@@ -320,22 +554,29 @@ pub fn emcell_configuration(input: TokenStream) -> TokenStream {
             pub const fn get_cell_end_flash_addr() -> usize {
                 <Self as emcell::Cell>::CUR_META.absolute_flash_end(&META.device_configuration)
             }
+            /// Own stack of the cell `[start, end)`, see `#[stack(size)]`
+            pub const fn stack_range() -> Option<(usize, usize)> {
+                <Self as emcell::Cell>::CUR_META.absolute_stack_range(&META.device_configuration)
+            }
+            /// Call `f` on the own stack of the cell, if it has one, see `emcell::call_on_stack`
+            pub fn call_on_stack<R>(&self, f: impl FnOnce(&Self) -> R) -> R {
+                unsafe { emcell::call_on_stack(Self::stack_range(), || f(self)) }
+            }
         })*
 
-        #(unsafe impl emcell::WithSignature for #non_primary_cell_idents {
-            const VALID_SIGNATURE: u32 = 0xdeadbeef;
+        #(unsafe impl emcell::WithSignature for #cell_idents {
+            const VALID_SIGNATURE: u32 = <Self as emcell::Cell>::CUR_META.signature;
         })*
 
-        unsafe impl emcell::WithSignature for #primary_cell_ident {
-            const VALID_SIGNATURE: u32 = 0xbeefdead;
-        }
-
         pub const META: emcell::meta::CellDefsMeta::<#cell_count> = emcell::meta::CellDefsMeta {
             cell_defs: [#(#emcell_defs),*],
             device_configuration: #emcell_device
         };
 
         pub const CELL_COUNT: usize = #cell_count;
+
+        const _: () = assert!(emcell::meta::HEADER_SIZE == #header_size, "emcell and emcell-macro versions do not match");
+        const _: () = assert!(emcell::meta::BOOT_MAILBOX_SIZE == #boot_mailbox_size, "emcell and emcell-macro versions do not match");
     };
 
     TokenStream::from(output)
@@ -367,6 +608,37 @@ impl Parse for CellAttribParams {
         })
     }
 }
+// params for #[signed(public_key = "hex")] attribute
+struct SignedParams {
+    public_key: [u8; 32],
+}
+
+impl Parse for SignedParams {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let key: Ident = input.parse()?;
+        if key != "public_key" {
+            return Err(syn::Error::new(key.span(), "Unknown signed parameter! Expected public_key = \"hex\""));
+        }
+        let _: Token![=] = input.parse()?;
+        let value: LitStr = input.parse()?;
+        if !input.is_empty() {
+            let _: Comma = input.parse()?;
+        }
+
+        let err = || syn::Error::new(value.span(), "Expected Ed25519 public key as 64 hex digits");
+        let hex = value.value();
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err(err());
+        }
+        let mut public_key = [0; 32];
+        for (i, byte) in public_key.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| err())?;
+        }
+
+        Ok(SignedParams { public_key })
+    }
+}
+
 fn parse_integer_lit(input: &LitInt) -> syn::Result<usize> {
     match input.suffix() {
         "" => input.base10_parse::<usize>(),
@@ -374,30 +646,39 @@ fn parse_integer_lit(input: &LitInt) -> syn::Result<usize> {
         _ => Err(syn::Error::new(input.span(), "Invalid integer literal")),
     }
 }
-// ram_region(start,end) attribute parsing
+// ram_region(start, end[, shared_with = Cell]) attribute parsing
 struct RamRegion {
     start: usize,
     end: usize,
+    /// Cells which are explicitly allowed to overlap this RAM region
+    shared_with: Vec<Ident>,
+    span: Span,
 }
 
 impl Parse for RamRegion {
     fn parse(input: ParseStream) -> syn::Result<Self> {
-        let literals = input.parse_terminated(LitInt::parse, Comma)?;
-        let literals = literals.into_iter().collect::<Vec<_>>();
-
-        if literals.len() != 2 {
-            return Err(syn::Error::new(input.span(), "Expected two integer literals, separated by comma"));
-        }
+        let span = input.span();
+        let (start, end) = parse_region_bounds(input)?;
 
-        let start = &literals[0];
-        let end = &literals[1];
+        let mut shared_with = Vec::new();
+        while !input.is_empty() {
+            let key: Ident = input.parse()?;
+            if key != "shared_with" {
+                return Err(syn::Error::new(key.span(), "Unknown ram_region parameter! Expected shared_with = CellName"));
+            }
+            let _: Token![=] = input.parse()?;
+            shared_with.push(input.parse::<Ident>()?);
 
-        let start = parse_integer_lit(start)?;
-        let end = parse_integer_lit(end)?;
+            if !input.is_empty() {
+                let _: Comma = input.parse()?;
+            }
+        }
 
         Ok(RamRegion {
             start,
-            end
+            end,
+            shared_with,
+            span,
         })
     }
 }
@@ -406,30 +687,306 @@ impl Parse for RamRegion {
 struct FlashRegion {
     start: usize,
     end: usize,
+    span: Span,
 }
 
 impl Parse for FlashRegion {
     fn parse(input: ParseStream) -> syn::Result<Self> {
-        let literals = input.parse_terminated(LitInt::parse, Comma)?;
-        let literals = literals.into_iter().collect::<Vec<_>>();
-
-        if literals.len() != 2 {
+        let span = input.span();
+        let (start, end) = parse_region_bounds(input)?;
+        if !input.is_empty() {
             return Err(syn::Error::new(input.span(), "Expected two integer literals, separated by comma"));
         }
 
-        let start = &literals[0];
-        let end = &literals[1];
-
-        let start = parse_integer_lit(start)?;
-        let end = parse_integer_lit(end)?;
-
         Ok(FlashRegion {
             start,
             end,
+            span,
         })
     }
 }
 
+/// Parse leading `start, end` integer literals of a region attribute, consuming trailing comma if present
+fn parse_region_bounds(input: ParseStream) -> syn::Result<(usize, usize)> {
+    let err = |span| syn::Error::new(span, "Expected two integer literals, separated by comma");
+
+    let start: LitInt = input.parse().map_err(|e| err(e.span()))?;
+    let _: Comma = input.parse().map_err(|e| err(e.span()))?;
+    let end: LitInt = input.parse().map_err(|e| err(e.span()))?;
+    if !input.is_empty() {
+        let _: Comma = input.parse()?;
+    }
+
+    Ok((parse_integer_lit(&start)?, parse_integer_lit(&end)?))
+}
+
+// Must be kept in sync with emcell::meta::HEADER_SIZE, checked in generated code
+const HEADER_SIZE: usize = 1024;
+// Must be kept in sync with emcell::meta::BOOT_MAILBOX_SIZE, checked in generated code
+const BOOT_MAILBOX_SIZE: usize = 64;
+
+/// Digest of everything two cells must agree on to talk to each other: header fields,
+/// cell kind, absolute placement of the cell, header size and device configuration.
+fn header_sha256(cell: &EmcellDef, device: &EmcellDeviceConfiguration) -> syn::Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    // optional fields are checked one by one with field fingerprints
+    for field in abi::canonical_fields(&cell.strukt.fields)?.iter().take(cell.base_field_count) {
+        hasher.update((field.len() as u64).to_le_bytes());
+        hasher.update(field.as_bytes());
+    }
+
+    hasher.update([cell.is_primary as u8]);
+    let layout = [
+        device.ram_region.start + cell.ram_region.start,
+        device.ram_region.start + cell.ram_region.end,
+        device.flash_region.start + cell.flash_region.start,
+        device.flash_region.start + cell.flash_region.end,
+        HEADER_SIZE,
+        device.initial_stack_pointer,
+        device.ram_region.start,
+        device.ram_region.end,
+        device.flash_region.start,
+        device.flash_region.end,
+    ];
+    for value in layout {
+        hasher.update((value as u64).to_le_bytes());
+    }
+    // cells without slot B keep their hash
+    if let Some(slot_b) = &cell.flash_slot_b {
+        for value in [device.flash_region.start + slot_b.start, device.flash_region.start + slot_b.end] {
+            hasher.update((value as u64).to_le_bytes());
+        }
+    }
+    if let Some(stack) = &cell.stack {
+        hasher.update(b"stack");
+        hasher.update((stack.size as u64).to_le_bytes());
+    }
+    // placement of the own stacks of cells
+    if device.stack_strategy == StackStrategy::CellBottom {
+        hasher.update(b"cell_bottom");
+    }
+    if let Some(noinit) = &device.noinit_region {
+        hasher.update(b"noinit");
+        for value in [device.ram_region.start + noinit.start, device.ram_region.start + noinit.end] {
+            hasher.update((value as u64).to_le_bytes());
+        }
+    }
+
+    Ok(hasher.finalize().into())
+}
+
+/// Optional `#[since(N)]` fields must go after all required fields, in the order of ABI versions.
+///
+/// Returns number of required fields and ABI minor version of the header.
+fn validate_optional_fields(cell: &EmcellDef) -> syn::Result<(usize, u32)> {
+    let mut base_field_count = 0;
+    let mut abi_minor = 1;
+    for field in cell.strukt.fields.iter() {
+        match abi::field_since(field)? {
+            None if abi_minor > 1 => {
+                return Err(syn::Error::new(field.span(), "Fields without #[since] must be declared before all #[since(N)] fields"));
+            }
+            None => base_field_count += 1,
+            Some(_) if field.attrs.iter().any(|attr| attr.path().is_ident("switch_vectors")) => {
+                return Err(syn::Error::new(field.span(), "#[switch_vectors] field cannot be optional"));
+            }
+            Some(since) if since < abi_minor => {
+                return Err(syn::Error::new(field.span(), format!("Optional fields must be ordered by ABI version: #[since({})] declared after #[since({})]", since, abi_minor)));
+            }
+            Some(since) => abi_minor = since,
+        }
+    }
+    Ok((base_field_count, abi_minor))
+}
+
+/// Magic word at the start of the cell header, unique for every cell.
+///
+/// Derived from the cell name only, so a header built from other cells definitions still passes the signature check
+/// and is reported as `HashMismatch` with the first differing field.
+fn cell_signature(cell: &EmcellDef) -> u32 {
+    let mut hasher = Sha256::new();
+    hasher.update(b"emcell-signature");
+    hasher.update(cell.strukt.ident.to_string().as_bytes());
+    let hash = hasher.finalize();
+
+    let signature = u32::from_le_bytes([hash[0], hash[1], hash[2], hash[3]]);
+    // erased and zeroed flash must never look like a valid header
+    match signature {
+        0x0000_0000 | 0xFFFF_FFFF => signature ^ 0x5A5A_A5A5,
+        signature => signature,
+    }
+}
+
+/// Short per-field digests, used to point at the first incompatible field when header hashes differ
+fn field_fingerprints(cell: &EmcellDef) -> syn::Result<Vec<u32>> {
+    Ok(abi::canonical_fields(&cell.strukt.fields)?.iter().map(|field| {
+        let hash = Sha256::digest(field.as_bytes());
+        u32::from_le_bytes([hash[0], hash[1], hash[2], hash[3]])
+    }).collect())
+}
+
+fn ranges_overlap(a: (usize, usize), b: (usize, usize)) -> bool {
+    a.0 < b.1 && b.0 < a.1
+}
+
+/// Check the whole memory layout: region bounds, device limits, overlaps between cells and stack placement.
+///
+/// All problems are reported at once, each one pointing at the offending attribute.
+fn validate_layout(device: &EmcellDeviceConfiguration, cells: &[EmcellDef]) -> syn::Result<()> {
+    let mut errors: Vec<syn::Error> = Vec::new();
+
+    let ram_size = device.ram_region.end.saturating_sub(device.ram_region.start);
+    let flash_size = device.flash_region.end.saturating_sub(device.flash_region.start);
+
+    if device.ram_region.start >= device.ram_region.end {
+        errors.push(syn::Error::new(device.span, "ram_range_start must be less than ram_range_end"));
+    }
+    if device.flash_region.start >= device.flash_region.end {
+        errors.push(syn::Error::new(device.span, "flash_range_start must be less than flash_range_end"));
+    }
+    if device.initial_stack_pointer <= device.ram_region.start || device.initial_stack_pointer > device.ram_region.end {
+        errors.push(syn::Error::new(device.initial_stack_pointer_span, format!(
+            "initial_stack_ptr 0x{:X} is outside of device RAM (0x{:X}..=0x{:X})",
+            device.initial_stack_pointer, device.ram_region.start + 1, device.ram_region.end)));
+    }
+    // stack occupies RAM below the initial stack pointer, see shared_stack
+    let stack = device.stack;
+    if stack.0 >= stack.1 {
+        errors.push(syn::Error::new(device.initial_stack_pointer_span, format!(
+            "no RAM is left for the stack below initial_stack_ptr 0x{:X}", device.initial_stack_pointer)));
+    }
+
+    // no-init region is not touched on startup, so it keeps the boot mailbox and #[emcell::retained] statics across
+    // resets
+    let noinit = device.noinit_region.as_ref().map(|region| (region.start, region.end));
+    if let Some(region) = &device.noinit_region {
+        if region.end < region.start.saturating_add(BOOT_MAILBOX_SIZE) {
+            errors.push(syn::Error::new(region.span, format!(
+                "noinit_region (0x{:X}..0x{:X}) must hold at least {} bytes for the boot mailbox at its start",
+                region.start, region.end, BOOT_MAILBOX_SIZE)));
+        }
+        else if region.end > ram_size {
+            errors.push(syn::Error::new(region.span, format!(
+                "noinit_region (0x{:X}..0x{:X}) exceeds device RAM size 0x{:X}", region.start, region.end, ram_size)));
+        }
+        else if !region.start.is_multiple_of(4) {
+            errors.push(syn::Error::new(region.span, format!("noinit_region start 0x{:X} must be 4-byte aligned", region.start)));
+        }
+        else if ranges_overlap((region.start, region.end), stack) {
+            errors.push(syn::Error::new(region.span, format!(
+                "noinit_region (0x{:X}..0x{:X}) overlaps with the stack (0x{:X}..0x{:X})", region.start, region.end, stack.0, stack.1)));
+        }
+    }
+
+    for cell in cells {
+        let name = &cell.strukt.ident;
+        let ram = &cell.ram_region;
+        let flash = &cell.flash_region;
+
+        if ram.start >= ram.end {
+            errors.push(syn::Error::new(ram.span, format!(
+                "RAM region of {} is empty: start 0x{:X} must be less than end 0x{:X}", name, ram.start, ram.end)));
+        }
+        else if ram.end > ram_size {
+            errors.push(syn::Error::new(ram.span, format!(
+                "RAM region of {} (0x{:X}..0x{:X}) exceeds device RAM size 0x{:X}", name, ram.start, ram.end, ram_size)));
+        }
+        else if ranges_overlap((ram.start, ram.end), stack) {
+            errors.push(syn::Error::new(ram.span, format!(
+                "RAM region of {} (0x{:X}..0x{:X}) overlaps with the stack (0x{:X}..0x{:X})", name, ram.start, ram.end, stack.0, stack.1)));
+        }
+        else if noinit.is_some_and(|noinit| ranges_overlap((ram.start, ram.end), noinit)) {
+            errors.push(syn::Error::new(ram.span, format!(
+                "RAM region of {} (0x{:X}..0x{:X}) overlaps with noinit_region", name, ram.start, ram.end)));
+        }
+
+        // stack occupies the top of the RAM region (the bottom with cell_bottom), its top must be 8-byte aligned
+        let stack_top = match device.stack_strategy {
+            StackStrategy::CellBottom => cell.stack.as_ref().map_or(ram.start, |stack| ram.start + stack.size),
+            _ => ram.end,
+        };
+        if let Some(stack) = &cell.stack {
+            if stack.size == 0 || !stack.size.is_multiple_of(8) {
+                errors.push(syn::Error::new(stack.span, format!(
+                    "stack size 0x{:X} of {} must be a non-zero multiple of 8", stack.size, name)));
+            }
+            else if stack.size > ram.end.saturating_sub(ram.start) {
+                errors.push(syn::Error::new(stack.span, format!(
+                    "stack of {} (0x{:X} bytes) does not fit into its RAM region (0x{:X}..0x{:X})", name, stack.size, ram.start, ram.end)));
+            }
+            else if !(device.ram_region.start + stack_top).is_multiple_of(8) {
+                errors.push(syn::Error::new(stack.span, format!(
+                    "top of the stack of {} at 0x{:X} must be 8-byte aligned", name, device.ram_region.start + stack_top)));
+            }
+            // an overflow at the bottom of the region only faults below the start of device RAM, elsewhere it runs
+            // into RAM of the shared stack or another cell
+            else if device.stack_strategy == StackStrategy::CellBottom && ram.start != 0 && !stack.guarded {
+                errors.push(syn::Error::new(stack.span, format!(
+                    "stack of {} at the bottom of its RAM region overflows into RAM below 0x{:X}. Guard it with the MPU \
+                    and declare #[stack(0x{:X}, guarded)], or use another stack_strategy",
+                    name, device.ram_region.start + ram.start, stack.size)));
+            }
+        }
+
+        for flash in cell.flash_slots() {
+            if flash.start >= flash.end {
+                errors.push(syn::Error::new(flash.span, format!(
+                    "FLASH region of {} is empty: start 0x{:X} must be less than end 0x{:X}", name, flash.start, flash.end)));
+            }
+            else if flash.end > flash_size {
+                errors.push(syn::Error::new(flash.span, format!(
+                    "FLASH region of {} (0x{:X}..0x{:X}) exceeds device flash size 0x{:X}", name, flash.start, flash.end, flash_size)));
+            }
+            else if flash.end - flash.start <= HEADER_SIZE {
+                errors.push(syn::Error::new(flash.span, format!(
+                    "FLASH region of {} is too small: at least {} bytes are reserved for the cell header", name, HEADER_SIZE)));
+            }
+        }
+        if let Some(slot_b) = &cell.flash_slot_b {
+            if ranges_overlap((flash.start, flash.end), (slot_b.start, slot_b.end)) {
+                errors.push(syn::Error::new(slot_b.span, format!("FLASH slots A and B of {} overlap", name)));
+            }
+        }
+
+        for shared in &ram.shared_with {
+            if shared == name {
+                errors.push(syn::Error::new(shared.span(), "Cell cannot share RAM region with itself"));
+            }
+            else if !cells.iter().any(|c| c.strukt.ident == *shared) {
+                errors.push(syn::Error::new(shared.span(), format!("Unknown cell {} in shared_with", shared)));
+            }
+        }
+    }
+
+    for (i, cell) in cells.iter().enumerate() {
+        for other in &cells[..i] {
+            let name = &cell.strukt.ident;
+            let other_name = &other.strukt.ident;
+
+            for flash in cell.flash_slots() {
+                if other.flash_slots().any(|other_flash| ranges_overlap((flash.start, flash.end), (other_flash.start, other_flash.end))) {
+                    errors.push(syn::Error::new(flash.span, format!(
+                        "FLASH region of {} overlaps with FLASH region of {}", name, other_name)));
+                }
+            }
+
+            let shared = cell.ram_region.shared_with.contains(other_name) || other.ram_region.shared_with.contains(name);
+            if !shared && ranges_overlap((cell.ram_region.start, cell.ram_region.end), (other.ram_region.start, other.ram_region.end)) {
+                errors.push(syn::Error::new(cell.ram_region.span, format!(
+                    "RAM region of {} overlaps with RAM region of {}. Add `shared_with = {}` if this is intended", name, other_name, other_name)));
+            }
+        }
+    }
+
+    match errors.into_iter().reduce(|mut acc, e| {
+        acc.combine(e);
+        acc
+    }) {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
 pub fn cell(_cell_attr: TokenStream, item: TokenStream) -> TokenStream {
     let mut header_struct = parse_macro_input!(item as DeriveInput);
 
@@ -437,7 +994,7 @@ pub fn cell(_cell_attr: TokenStream, item: TokenStream) -> TokenStream {
     header_struct.attrs.push(parse_quote! { #[repr(C)] });
 
     // Extract the struct fields
-    let mut fields = match &mut header_struct.data {
+    let fields = match &mut header_struct.data {
         Data::Struct(DataStruct { fields: Fields::Named(fields), .. }) => fields,
         _ => {
             return TokenStream::from(
@@ -447,13 +1004,40 @@ pub fn cell(_cell_attr: TokenStream, item: TokenStream) -> TokenStream {
         }
     };
 
-    let mut switch_vectors_fn_ident = None;
+    // optional fields are accessed through generated getters, which check field count of the provided header
+    let mut optional_getters = Vec::new();
+    for (i, field) in fields.named.iter_mut().enumerate() {
+        let since = match abi::field_since(field) {
+            Ok(since) => since,
+            Err(e) => return TokenStream::from(e.to_compile_error()),
+        };
+        let Some(since) = since else {
+            continue;
+        };
+        field.attrs.retain(|attr| !attr.path().is_ident("since"));
+
+        let ident = &field.ident;
+        let ty = &field.ty;
+        let doc = format!("Value of optional field `{}`, added in ABI version {}.\n\nReturns `None` if the cell was built with older definitions and does not provide it.",
+                          ident.as_ref().unwrap(), since);
+        optional_getters.push(quote! {
+            #[doc = #doc]
+            pub fn #ident(&self) -> Option<#ty> {
+                if self.abi.field_count as usize > #i {
+                    Some(self.#ident)
+                } else {
+                    None
+                }
+            }
+        });
+    }
+
+    let mut switch_vectors_fn = None;
     for field in fields.named.iter_mut() {
         for (i, attr) in field.attrs.iter().enumerate() {
             if attr.meta.path().is_ident("switch_vectors") {
                 field.attrs.remove(i);
-                //check signature to be fn() -> !
-                let sig = &mut field.ty;
+                //check signature to be fn() -> ! or fn() -> R
                 let Some(ident) = field.ident.as_mut() else {
                     return TokenStream::from(
                         syn::Error::new(field.span(), "Expected named field")
@@ -461,15 +1045,14 @@ pub fn cell(_cell_attr: TokenStream, item: TokenStream) -> TokenStream {
                     );
                 };
 
-                let true_sig: Type = parse_quote! { fn() -> ! };
-                if sig.to_token_stream().to_string() != true_sig.to_token_stream().to_string() {
+                let Some(output) = switch_vectors_output(&field.ty) else {
                     return TokenStream::from(
-                        syn::Error::new(ident.span(), "Expected function signature fn() -> !")
+                        syn::Error::new(ident.span(), "Expected function signature fn() -> ! or fn() -> R")
                             .to_compile_error(),
                     );
-                }
+                };
 
-                switch_vectors_fn_ident = Some(ident.clone());
+                switch_vectors_fn = Some((ident.clone(), output));
                 break;
             }
         }
@@ -489,48 +1072,150 @@ pub fn cell(_cell_attr: TokenStream, item: TokenStream) -> TokenStream {
         .unwrap();
     fields.named.insert(1, init_field);
 
+    // per-field fingerprints for diagnostics, see emcell::check_header
+    let abi_field = Field::parse_named
+        .parse2(quote! { pub abi: emcell::meta::HeaderAbi })
+        .unwrap();
+    fields.named.insert(2, abi_field);
+
+    let version_field = Field::parse_named
+        .parse2(quote! { pub version: emcell::meta::CellVersion })
+        .unwrap();
+    fields.named.insert(3, version_field);
+
+    // filled after linking, see emcell::integrity
+    let image_field = Field::parse_named
+        .parse2(quote! { pub image: emcell::meta::ImageInfo })
+        .unwrap();
+    fields.named.insert(4, image_field);
+
+    // anti-rollback, see emcell::rollback
+    let security_counter_field = Field::parse_named
+        .parse2(quote! { pub security_counter: u32 })
+        .unwrap();
+    fields.named.insert(5, security_counter_field);
+
+    // slot, the image was linked for, see emcell::Slot
+    let linked_slot_field = Field::parse_named
+        .parse2(quote! { pub linked_slot: u32 })
+        .unwrap();
+    fields.named.insert(6, linked_slot_field);
+
 
     let header_ident = &header_struct.ident;
 
-    let impl_decl = if let Some(switch_vectors_fn_ident) = switch_vectors_fn_ident {
+    let impl_decl = if let Some((switch_vectors_fn_ident, output)) = switch_vectors_fn {
         let switch_vectors = Field::parse_named
             .parse2(quote! { pub _emcell_internal_switch_vectors: unsafe fn() })
             .unwrap();
-        fields.named.insert(2, switch_vectors);
-
-        quote! {
+        fields.named.insert(7, switch_vectors);
 
+        let switch_vectors_and_call = quote! {
             impl #header_ident {
-                pub fn switch_vectors_and_run(&self) -> ! {
-                    unsafe {(self._emcell_internal_switch_vectors)()};
-                    (self.#switch_vectors_fn_ident)()
+                /// Run `#[pre_switch]` of the current cell, switch interrupt vectors to this cell and call `f`.
+                /// Interrupt vectors, interrupt configuration and MSP of the caller are restored after `f` returns, see
+                /// `emcell::switch_vectors_and_call`
+                pub fn switch_vectors_and_call<R>(&self, f: impl FnOnce(&Self) -> R) -> R {
+                    unsafe { emcell::switch_vectors_and_call(self._emcell_internal_switch_vectors, Self::stack_range(), || f(self)) }
                 }
             }
+        };
+
+        match output {
+            Some(output) => quote! {
+                impl #header_ident {
+                    /// Call the `#[switch_vectors]` function of this cell with its interrupt vectors and return to
+                    /// the caller, see `switch_vectors_and_call`
+                    pub fn switch_vectors_and_run(&self) -> #output {
+                        self.switch_vectors_and_call(|cell| (cell.#switch_vectors_fn_ident)())
+                    }
+                }
+
+                #switch_vectors_and_call
+            },
+            None => quote! {
+                impl #header_ident {
+                    /// Run `#[pre_switch]` of the current cell, switch interrupt vectors to this cell and call its
+                    /// `#[switch_vectors]` function, on the own stack of the cell if it has one
+                    pub fn switch_vectors_and_run(&self) -> ! {
+                        unsafe { emcell::pre_switch() };
+                        unsafe {(self._emcell_internal_switch_vectors)()};
+                        match Self::stack_range() {
+                            Some(stack) => unsafe { emcell::run_on_stack(stack, self.#switch_vectors_fn_ident) },
+                            None => (self.#switch_vectors_fn_ident)(),
+                        }
+                    }
+                }
+
+                #switch_vectors_and_call
+
+                impl emcell::boot::Bootable for #header_ident {
+                    fn boot(&self) -> ! {
+                        self.switch_vectors_and_run()
+                    }
+                }
+            },
         }
     } else {
         quote! {}
     };
 
 
+    let getters_decl = if optional_getters.is_empty() {
+        quote! {}
+    } else {
+        quote! {
+            impl #header_ident {
+                #(#optional_getters)*
+            }
+        }
+    };
+
     let output = quote! {
         #header_struct
 
         #impl_decl
+
+        #getters_decl
     };
 
     TokenStream::from(output)
 }
 
-/// switch_vectors macro attribute is a way of declaring function in a cell header with a signature () -> !
+/// Return type of a `#[switch_vectors]` field: `Some(None)` for `fn() -> !`, `Some(Some(R))` for `fn() -> R`, `None` if
+/// the field is not such a function pointer
+fn switch_vectors_output(ty: &Type) -> Option<Option<Type>> {
+    let Type::BareFn(bare_fn) = ty else {
+        return None;
+    };
+    if !bare_fn.inputs.is_empty() || bare_fn.abi.is_some() || bare_fn.unsafety.is_some() || bare_fn.variadic.is_some()
+        || bare_fn.lifetimes.is_some() {
+        return None;
+    }
+    match &bare_fn.output {
+        syn::ReturnType::Default => Some(Some(parse_quote! { () })),
+        syn::ReturnType::Type(_, ty) if matches!(**ty, Type::Never(_)) => Some(None),
+        syn::ReturnType::Type(_, ty) => Some(Some((**ty).clone())),
+    }
+}
+
+/// switch_vectors macro attribute is a way of declaring function in a cell header with a signature () -> ! or () -> R
+///
+/// A diverging `fn() -> !` entry hands the core over to the cell: `switch_vectors_and_run(&self) -> !` never returns and
+/// the cell becomes `emcell::boot::Bootable`. A returning `fn() -> R` entry is called through
+/// `switch_vectors_and_call`, `switch_vectors_and_run(&self) -> R` restores interrupt vectors, interrupt configuration
+/// and MSP of the caller after it returns. Such a cell cannot be booted.
 ///
 /// This function provides additional code generation for interrupt vector switching to ones declared in other cell
 ///
 /// # Warning
-/// Do not forget to deinitialize everything you don't need before calling this function!
-/// E.g. disabling unused interrupts, resetting peripheral to default state, etc.
+/// Interrupts and SysTick are quiesced automatically by `emcell::device::quiesce`, but peripherals keep running.
+/// Deinitialize them in a `#[pre_switch(hook)]` of `define_header!`/`define_primary_header!`, which is called
+/// right before switching.
 ///
 /// If run is the only function to be called in other cell, it is allowed to overlap ram regions, considering full
 /// deinitialization and resetting all the peripherals before calling run().
+/// Such overlap must be declared explicitly with `#[ram_region(start, end, shared_with = OtherCell)]`.
 pub fn switch_vectors(_attr: TokenStream, item: TokenStream) -> TokenStream {
     item
 }
@@ -545,6 +1230,21 @@ pub fn flash_region(_attr: TokenStream, item: TokenStream) -> TokenStream {
     item
 }
 
+//dummy signed
+pub fn signed(_attr: TokenStream, item: TokenStream) -> TokenStream {
+    item
+}
+
+//dummy stack
+pub fn stack(_attr: TokenStream, item: TokenStream) -> TokenStream {
+    item
+}
+
+//dummy noinit_region
+pub fn noinit_region(_attr: TokenStream, item: TokenStream) -> TokenStream {
+    item
+}
+
 pub fn device(_item: TokenStream) -> TokenStream {
     TokenStream::new()
 }
\ No newline at end of file
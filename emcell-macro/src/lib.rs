@@ -1,44 +1,71 @@
+mod abi;
 mod defs;
+mod retained;
 
 use proc_macro::{TokenStream};
 use proc_macro2::Ident;
 use quote::{format_ident, quote, ToTokens};
-use syn::{ExprStruct, parse_macro_input, parse_quote, Path};
+use syn::{Attribute, ExprStruct, FieldValue, LitBool, Member, parse_macro_input, parse_quote, Path, Token};
+use syn::spanned::Spanned;
+use syn::punctuated::Punctuated;
 use syn::parse::{Parse, ParseStream};
-use syn::token::{Colon};
+use syn::token::{Colon, Comma};
 
 
 #[proc_macro]
 pub fn define_header(item: TokenStream) -> TokenStream {
-    let input = parse_macro_input!(item as ExprStruct);
+    let HeaderDefinition { pre_switch, header: input } = parse_macro_input!(item as HeaderDefinition);
     let ident = input.path;
     let ident_str = ident.to_token_stream().to_string().trim_matches('"').to_string();
 
     let fields = input.fields;
+    let security_counter = default_security_counter(&fields);
 
     let link_section = ".emcell.cur_header";
     let static_ident = format_ident!("_emcell_{}_internal", ident_str);
+    let trailer_ident = format_ident!("_emcell_{}_signature", ident_str);
+    let pre_switch = pre_switch.to_token_stream();
 
 
     let output: proc_macro2::TokenStream = {
         quote!(
             #[cortex_m_rt::entry]
             fn _emcell_internal_main() -> ! {
-                let reset_vec_addr = unsafe { (<#ident as emcell::Cell>::DEVICE_CONFIG.flash_range_start as *const u32)
-                .offset(1)} ;
-                let reset_vec = unsafe { reset_vec_addr.read_volatile() } as *const u32;
-                let reset_vec = unsafe { core::mem::transmute::<_, fn() -> !>(reset_vec) };
-                unsafe {reset_vec()}
+                // started by emcell::device::boot_cell: run the cell itself
+                if emcell::device::started_by_boot_cell() {
+                    if let Some(entry) = emcell::Cell::boot_entry(&#static_ident) {
+                        entry()
+                    }
+                }
+                // otherwise the device was reset, run the primary cell
+                unsafe { emcell::device::boot_primary::<#ident>() }
             }
 
             #[no_mangle]
             #[link_section = #link_section]
             pub static #static_ident : #ident = #ident {
-                signature: 0xdeadbeef,
+                signature: <#ident as emcell::WithSignature>::VALID_SIGNATURE,
                 init: unsafe { __emcell_init },
+                abi: emcell::meta::HeaderAbi::new(&<#ident as emcell::Cell>::CUR_META),
+                version: emcell::meta::CellVersion::new(
+                    env!("CARGO_PKG_NAME"),
+                    env!("CARGO_PKG_VERSION"),
+                    option_env!("EMCELL_GIT_REVISION"),
+                    option_env!("EMCELL_BUILD_TIMESTAMP"),
+                    <#ident as emcell::Cell>::CUR_META.abi_minor,
+                ),
+                image: emcell::meta::ImageInfo::UNPATCHED,
+                linked_slot: emcell::meta::Slot::from_env(option_env!("EMCELL_SLOT")) as u32,
+                #security_counter
                 #fields
             };
 
+            #[used]
+            #[link_section = ".emcell.signature"]
+            static #trailer_ident: emcell::meta::SignatureTrailer = emcell::meta::SignatureTrailer::UNSIGNED;
+
+            #pre_switch
+
             unsafe fn __emcell_init(known_sha: [u8; 32], init_memory: bool) -> bool {
                 if known_sha != <#ident as emcell::Cell>::CUR_META.struct_sha256 {
                     return false;
@@ -59,25 +86,45 @@ pub fn define_header(item: TokenStream) -> TokenStream {
 
 #[proc_macro]
 pub fn define_primary_header(item: TokenStream) -> TokenStream {
-    let input = parse_macro_input!(item as ExprStruct);
+    let HeaderDefinition { pre_switch, header: input } = parse_macro_input!(item as HeaderDefinition);
     let ident = input.path;
     let ident_str = ident.to_token_stream().to_string().trim_matches('"').to_string();
 
     let fields = input.fields;
+    let security_counter = default_security_counter(&fields);
 
     let link_section = ".emcell.cur_header";
     let static_ident = format_ident!("_emcell_{}_internal", ident_str);
+    let trailer_ident = format_ident!("_emcell_{}_signature", ident_str);
+    let pre_switch = pre_switch.to_token_stream();
 
     let output: proc_macro2::TokenStream = {
         quote!(
             #[no_mangle]
             #[link_section = #link_section]
             pub static #static_ident : #ident = #ident {
-                signature: 0xbeef_dead,
+                signature: <#ident as emcell::WithSignature>::VALID_SIGNATURE,
                 init: unsafe { __emcell_init_primary },
+                abi: emcell::meta::HeaderAbi::new(&<#ident as emcell::Cell>::CUR_META),
+                version: emcell::meta::CellVersion::new(
+                    env!("CARGO_PKG_NAME"),
+                    env!("CARGO_PKG_VERSION"),
+                    option_env!("EMCELL_GIT_REVISION"),
+                    option_env!("EMCELL_BUILD_TIMESTAMP"),
+                    <#ident as emcell::Cell>::CUR_META.abi_minor,
+                ),
+                image: emcell::meta::ImageInfo::UNPATCHED,
+                linked_slot: emcell::meta::Slot::from_env(option_env!("EMCELL_SLOT")) as u32,
+                #security_counter
                 #fields
             };
 
+            #[used]
+            #[link_section = ".emcell.signature"]
+            static #trailer_ident: emcell::meta::SignatureTrailer = emcell::meta::SignatureTrailer::UNSIGNED;
+
+            #pre_switch
+
             unsafe fn __emcell_init_primary(known_sha: [u8; 32], _init_memory: bool) -> bool {
                 if known_sha != <#ident as emcell::Cell>::CUR_META.struct_sha256 {
                     return false;
@@ -90,6 +137,86 @@ pub fn define_primary_header(item: TokenStream) -> TokenStream {
     proc_macro::TokenStream::from(output)
 }
 
+/// Header definition of `define_header!` and `define_primary_header!`, optionally with
+/// `#[pre_switch(hook, quiesce = false)]` in front of it
+struct HeaderDefinition {
+    pre_switch: PreSwitch,
+    header: ExprStruct,
+}
+
+impl Parse for HeaderDefinition {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut pre_switch = PreSwitch::default();
+        for attr in input.call(Attribute::parse_outer)? {
+            if !attr.path().is_ident("pre_switch") {
+                return Err(syn::Error::new(attr.span(), "Unknown header attribute! Expected #[pre_switch(hook)]"));
+            }
+            pre_switch = attr.parse_args()?;
+        }
+        Ok(HeaderDefinition { pre_switch, header: input.parse()? })
+    }
+}
+
+/// Code, which runs in the current cell before it is left, see `emcell::pre_switch`
+struct PreSwitch {
+    hook: Option<Path>,
+    quiesce: bool,
+}
+
+impl Default for PreSwitch {
+    fn default() -> Self {
+        PreSwitch { hook: None, quiesce: true }
+    }
+}
+
+impl Parse for PreSwitch {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut pre_switch = PreSwitch::default();
+        while !input.is_empty() {
+            if input.peek(syn::Ident) && input.peek2(Token![=]) {
+                let key: Ident = input.parse()?;
+                if key != "quiesce" {
+                    return Err(syn::Error::new(key.span(), "Unknown pre_switch parameter! Expected quiesce = true/false"));
+                }
+                let _: Token![=] = input.parse()?;
+                pre_switch.quiesce = input.parse::<LitBool>()?.value;
+            }
+            else {
+                pre_switch.hook = Some(input.parse()?);
+            }
+
+            if !input.is_empty() {
+                let _: Comma = input.parse()?;
+            }
+        }
+        Ok(pre_switch)
+    }
+}
+
+impl ToTokens for PreSwitch {
+    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
+        let hook = self.hook.iter();
+        let quiesce = self.quiesce.then(|| quote! { emcell::device::quiesce(); });
+        tokens.extend(quote! {
+            #[no_mangle]
+            unsafe fn _emcell_pre_switch() {
+                #(#hook();)*
+                #quiesce
+            }
+        });
+    }
+}
+
+/// `security_counter: 0,` unless the counter is set explicitly in the header definition
+fn default_security_counter(fields: &Punctuated<FieldValue, Comma>) -> proc_macro2::TokenStream {
+    let is_set = fields.iter().any(|field| matches!(&field.member, Member::Named(ident) if ident == "security_counter"));
+    if is_set {
+        quote!()
+    } else {
+        quote!(security_counter: 0,)
+    }
+}
+
 struct ExternHeader {
     name: Ident,
     typez: Ident,
@@ -128,8 +255,55 @@ fn generate_extern_header(cell_name: Ident, cell_type: Ident, forward_or_backwar
                 /// #Safety
                 /// CellWrapper can be constructed ONLY if this cell is used by exactly one other cell project
                 pub fn new() -> Option<Self> {
+                    Self::try_new().ok()
+                }
+
+                /// Same as `new`, but reports why the cell header was rejected
+                ///
+                /// #Safety
+                /// CellWrapper can be constructed ONLY if this cell is used by exactly one other cell project
+                pub fn try_new() -> Result<Self, emcell::CellError> {
+                    let cell = unsafe { & #internal_ident };
+                    unsafe { emcell::CellWrapper::<#cell_type, #forward_or_backward>::_try_new_init(cell)}.map(|inner| Self { inner })
+                }
+
+                /// Same as `try_new`, with additional checks from `policy` (e.g. image integrity),
+                /// performed before the first call into the cell
+                ///
+                /// #Safety
+                /// CellWrapper can be constructed ONLY if this cell is used by exactly one other cell project
+                pub fn try_new_with(policy: &emcell::CheckPolicy) -> Result<Self, emcell::CellError> {
                     let cell = unsafe { & #internal_ident };
-                    unsafe { emcell::CellWrapper::<#cell_type, #forward_or_backward>::_new_init(cell)}.map(|inner| Self { inner })
+                    unsafe { emcell::CellWrapper::<#cell_type, #forward_or_backward>::_try_new_init_with(cell, policy)}.map(|inner| Self { inner })
+                }
+
+                /// Same as `try_new_with`, for the cell image in `slot`
+                ///
+                /// #Safety
+                /// CellWrapper can be constructed ONLY if this cell is used by exactly one other cell project
+                pub fn try_new_in_slot(slot: emcell::Slot, policy: &emcell::CheckPolicy) -> Result<Self, emcell::CellError> {
+                    let cell = unsafe { emcell::slot_header::<#cell_type>(slot)? };
+                    unsafe { emcell::CellWrapper::<#cell_type, #forward_or_backward>::_try_new_init_with(cell, policy)}.map(|inner| Self { inner })
+                }
+
+                /// Construct CellWrapper for the cell image in `slot`, see `try_new_in_slot`
+                pub fn new_in_slot(slot: emcell::Slot) -> Option<Self> {
+                    Self::try_new_in_slot(slot, &emcell::CheckPolicy::new()).ok()
+                }
+
+                /// Pick the valid slot with the newest cell image (security counter, version, build time) and
+                /// construct CellWrapper for it. Returns the error of the last rejected slot if none is valid
+                ///
+                /// #Safety
+                /// CellWrapper can be constructed ONLY if this cell is used by exactly one other cell project
+                pub fn try_newest(policy: &emcell::CheckPolicy) -> Result<(Self, emcell::Slot), emcell::CellError> {
+                    unsafe { emcell::CellWrapper::<#cell_type, #forward_or_backward>::_try_new_newest_with(policy)}
+                        .map(|(inner, slot)| (Self { inner }, slot))
+                }
+
+                /// Construct CellWrapper for the newest valid slot, see `try_newest`
+                pub fn newest() -> Option<Self> {
+                    Self::try_newest(&emcell::CheckPolicy::new()).ok().map(|(cell, _)| cell)
                 }
 
                 /// Construct constant CellWrapper for this cell without signature check and memory initialization
@@ -159,6 +333,16 @@ fn generate_extern_header(cell_name: Ident, cell_type: Ident, forward_or_backwar
                     self.inner.ensure_init()
                 }
 
+                /// Same as `ensure_init`, but reports why the cell header was rejected
+                pub fn try_ensure_init(&self) -> Result<(), emcell::CellError> {
+                    self.inner.try_ensure_init()
+                }
+
+                /// Version block of the cell, as found in its header
+                pub fn version(&self) -> &emcell::meta::CellVersion {
+                    self.inner.version()
+                }
+
                 /// Check if this cell wrapper was created with `new_dummy` method
                 pub fn is_dummy(&self) -> bool {
                     self.inner.is_dummy()
@@ -183,13 +367,12 @@ fn generate_extern_header(cell_name: Ident, cell_type: Ident, forward_or_backwar
 /// Cell **should not** be a primary cell.
 ///
 /// # Example
-/// ```
+/// ```ignore
 /// use emcell_macro::extern_header_forward;
 /// extern_header_forward! {
-///    Cell1Wrapper: Cell1
+///    Cell2Wrapper: Cell2
 /// }
-///
-///
+/// ```
 #[proc_macro]
 pub fn extern_header_forward(item: TokenStream) -> TokenStream {
     let ExternHeader { name: cell_name, typez: cell_type } = parse_macro_input!(item as ExternHeader);
@@ -203,13 +386,13 @@ pub fn extern_header_forward(item: TokenStream) -> TokenStream {
 /// It is only allowed to extern header, which use extern_header_forward with this cell. This cell is called parent cell.
 ///
 /// # Example
-/// ```
+/// ```ignore
 /// use emcell_macro::extern_header_backward;
 ///
 /// extern_header_backward! {
 ///    Cell1Wrapper: Cell1
 /// }
-///
+/// ```
 #[proc_macro]
 pub fn extern_header_backward(item: TokenStream) -> TokenStream {
     let ExternHeader { name: cell_name, typez: cell_type } = parse_macro_input!(item as ExternHeader);
@@ -234,13 +417,37 @@ pub fn flash_region(attr: TokenStream, item: TokenStream) -> TokenStream {
     defs::flash_region(attr, item)
 }
 
-/// Declare header function with signature fn() -> !, which use additional generated code for
+/// Require the cell image to be signed: `#[signed(public_key = "<64 hex digits>")]`.
+/// Other cells check the Ed25519 signature before the first call into it (`ed25519` feature of emcell)
+#[proc_macro_attribute]
+pub fn signed(attr: TokenStream, item: TokenStream) -> TokenStream {
+    defs::signed(attr, item)
+}
+
+/// Declare header function with signature fn() -> ! or fn() -> R, which use additional generated code for
 /// switching interrupt vectors to the ones from the cell
 #[proc_macro_attribute]
 pub fn switch_vectors(attr: TokenStream, item: TokenStream) -> TokenStream {
     defs::switch_vectors(attr, item)
 }
 
+/// Own stack of the cell at the top of its RAM region: `#[stack(size)]`. Used by the runtime of the cell,
+/// `switch_vectors_and_run`, `switch_vectors_and_call` and `call_on_stack` of its header.
+///
+/// With `stack_strategy: cell_bottom` the stack is at the bottom of the region, `#[stack(size, guarded)]` declares that
+/// RAM below it is guarded by the MPU, see `emcell::meta::StackStrategy::CellBottom`
+#[proc_macro_attribute]
+pub fn stack(attr: TokenStream, item: TokenStream) -> TokenStream {
+    defs::stack(attr, item)
+}
+
+/// RAM region of `device!`, which is not initialized on startup and keeps `#[emcell::retained]` statics
+/// across resets: `#[noinit_region(start, end)] device! { ... }`, offsets into device RAM like `#[ram_region]`
+#[proc_macro_attribute]
+pub fn noinit_region(attr: TokenStream, item: TokenStream) -> TokenStream {
+    defs::noinit_region(attr, item)
+}
+
 #[proc_macro]
 pub fn device(item: TokenStream) -> TokenStream {
     defs::device(item)
@@ -249,4 +456,11 @@ pub fn device(item: TokenStream) -> TokenStream {
 #[proc_macro]
 pub fn emcell_configuration(input: TokenStream) -> TokenStream {
     defs::emcell_configuration(input)
-}
\ No newline at end of file
+}
+
+/// Keep the static in the no-init RAM region of the device across resets: `#[emcell::retained] static NAME: T = init;`.
+/// The static becomes `emcell::retained::Retained<T>`, see `emcell::retained`
+#[proc_macro_attribute]
+pub fn retained(attr: TokenStream, item: TokenStream) -> TokenStream {
+    retained::retained(attr, item)
+}
@@ -0,0 +1,45 @@
+use proc_macro::TokenStream;
+use crate::abi;
+use quote::{format_ident, quote};
+use sha2::{Digest, Sha256};
+use syn::{parse_macro_input, ItemStatic, StaticMutability};
+use syn::spanned::Spanned;
+
+/// `static NAME: T = init;` -> `static NAME: emcell::retained::Retained<T>` with storage in `.emcell.noinit`
+pub fn retained(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return syn::Error::new(proc_macro2::TokenStream::from(attr).span(), "#[retained] takes no arguments")
+            .to_compile_error().into();
+    }
+    let ItemStatic { attrs, vis, ident, mutability, ty, expr, .. } = parse_macro_input!(item as ItemStatic);
+    if let StaticMutability::Mut(token) = mutability {
+        return syn::Error::new(token.span(), "#[retained] static must not be mutable, use Retained::set")
+            .to_compile_error().into();
+    }
+
+    let link_section = format!(".emcell.noinit.{}", ident);
+    let storage_ident = format_ident!("_EMCELL_RETAINED_{}", ident);
+
+    // value stored by another build with different name or type is not taken for this one. Type is hashed in the
+    // same canonical form as header fields, so spelling of the type does not matter
+    let ty_string = match abi::canonical_type(&ty) {
+        Ok(ty_string) => ty_string,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let mut hasher = Sha256::new();
+    hasher.update(ident.to_string().as_bytes());
+    hasher.update(ty_string.as_bytes());
+    let digest = hasher.finalize();
+    let tag = u32::from_le_bytes([digest[0], digest[1], digest[2], digest[3]]) | 1;
+
+    let output = quote! {
+        #(#attrs)*
+        #vis static #ident: emcell::retained::Retained<#ty> = {
+            #[link_section = #link_section]
+            static mut #storage_ident: emcell::retained::Storage<#ty> = emcell::retained::Storage::uninit();
+            unsafe { emcell::retained::Retained::_new(core::ptr::addr_of_mut!(#storage_ident), #expr, #tag) }
+        };
+    };
+
+    TokenStream::from(output)
+}
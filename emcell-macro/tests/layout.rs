@@ -0,0 +1,7 @@
+//! Memory layouts, which `emcell_configuration!` rejects at compile time
+
+#[test]
+fn invalid_layouts_are_rejected() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,23 @@
+emcell_macro::emcell_configuration! {
+    device!{
+        initial_stack_ptr: 0x2000_1000,
+        ram_range_start: 0x2000_0000,
+        ram_range_end: 0x2000_8000,
+        flash_range_start: 0x0800_0000,
+        flash_range_end: 0x0801_0000,
+    }
+
+    #[cell(primary)]
+    #[ram_region(0x1000, 0x2000)]
+    #[flash_region(0x0, 0x4000)]
+    pub struct Cell1 {
+    }
+
+    #[cell]
+    #[ram_region(0x2000, 0x3000)]
+    #[flash_region(0x3000, 0x8000)]
+    pub struct Cell2 {
+    }
+}
+
+fn main() {}
@@ -0,0 +1,553 @@
+//! Boot selection for the primary cell: pick the cell to run, fall back to a recovery cell.
+//!
+//! [`BootManager`] tries the configured cells from the highest priority down, takes the newest valid slot of the first
+//! cell, which passes all checks of the [`CheckPolicy`], and records the boot in the [`BootMailbox`]. The booted cell
+//! confirms it with [`confirm_boot`]. Images, which were never confirmed after several boots, are skipped, so the other
+//! slot of the cell or the recovery cell is booted instead.
+
+use crate::{newest_slot_where, Cell, CellError, CheckPolicy, Slot};
+
+/// Maximum number of cells, a [`BootManager`] can choose from (recovery cell excluded)
+pub const MAX_BOOT_TARGETS: usize = 8;
+
+/// Cell, which can be booted by the primary cell. Implemented by `#[cell]` for headers with a `#[switch_vectors]` field
+pub trait Bootable: Cell {
+    /// Switch interrupt vectors to the cell and jump to its `#[switch_vectors]` function
+    fn boot(&self) -> !;
+}
+
+/// Cell, configured for booting
+#[derive(Copy, Clone)]
+pub struct BootTarget {
+    pub name: &'static str,
+    /// Cells with higher priority are tried first
+    pub priority: u8,
+    select: unsafe fn(&CheckPolicy, &MailboxState) -> Result<Entry, CellError>,
+}
+
+impl BootTarget {
+    pub const fn of<T: Bootable + 'static>(priority: u8) -> Self {
+        Self {
+            name: T::CUR_META.name,
+            priority,
+            select: select::<T>,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Entry {
+    header: *const (),
+    slot: Slot,
+    image: [u32; 2],
+    boot: unsafe fn(*const ()) -> !,
+}
+
+/// Validate cell `T`, skipping failed images from `mailbox`, and initialize its memory
+unsafe fn select<T: Bootable + 'static>(policy: &CheckPolicy, mailbox: &MailboxState) -> Result<Entry, CellError> {
+    let (header, slot) = unsafe { newest_slot_where::<T>(policy, |h| !mailbox.failed.contains(&image_id(h)))? };
+    unsafe { header.call_init(true)? };
+    Ok(Entry {
+        header: header as *const T as *const (),
+        slot,
+        image: image_id(header),
+        boot: boot::<T>,
+    })
+}
+
+unsafe fn boot<T: Bootable>(header: *const ()) -> ! {
+    unsafe { &*(header as *const T) }.boot()
+}
+
+/// Number of never confirmed images, remembered by [`BootMailbox`]
+const FAILED_IMAGES: usize = 4;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct MailboxState {
+    magic: u32,
+    /// Header address of the last booted cell, 0 if it is confirmed or the recovery cell was booted
+    pending_header: u32,
+    /// [`image_id`] of the last booted cell
+    pending_image: u32,
+    /// Number of boots of the pending image without confirmation
+    attempts: u32,
+    /// Header address and image id of images, which were never confirmed, oldest first
+    failed: [[u32; 2]; FAILED_IMAGES],
+}
+
+/// Boot state, shared by the primary cell and the booted cell and kept in RAM across resets.
+///
+/// [`BootManager`] records the booted image as pending, the booted cell confirms it with [`confirm_boot`] once it
+/// works. An image, which was booted `max_attempts` times without confirmation, is not booted anymore: the other slot
+/// of the cell or the recovery cell is booted instead.
+///
+/// It is located at the start of `#[noinit_region]` of `device!`, see [`mailbox`]. After power-on its contents are
+/// random, this is detected and treated as an empty mailbox.
+#[repr(transparent)]
+pub struct BootMailbox(MailboxState);
+
+const _: () = assert!(core::mem::size_of::<BootMailbox>() <= crate::meta::BOOT_MAILBOX_SIZE);
+
+impl BootMailbox {
+    const MAGIC: u32 = 0xB007_B0C5;
+    const EMPTY: MailboxState = MailboxState {
+        magic: Self::MAGIC,
+        pending_header: 0,
+        pending_image: 0,
+        attempts: 0,
+        failed: [[0; 2]; FAILED_IMAGES],
+    };
+
+    pub const fn new() -> Self {
+        Self(Self::EMPTY)
+    }
+
+    fn load(&self) -> MailboxState {
+        let state = unsafe { core::ptr::read_volatile(&self.0) };
+        if state.magic == Self::MAGIC {
+            state
+        } else {
+            Self::EMPTY
+        }
+    }
+
+    fn store(&mut self, state: MailboxState) {
+        unsafe { core::ptr::write_volatile(&mut self.0, state) };
+    }
+
+    /// Number of boots of the last booted image without confirmation, 0 if it is confirmed
+    pub fn attempts(&self) -> u32 {
+        let state = self.load();
+        if state.pending_header != 0 { state.attempts } else { 0 }
+    }
+
+    /// Confirm the last booted image: it works and may be booted again
+    pub fn confirm(&mut self) {
+        let mut state = self.load();
+        state.pending_header = 0;
+        state.attempts = 0;
+        self.store(state);
+    }
+
+    /// Forget all never confirmed images, e.g. after an update or by a recovery cell
+    pub fn clear(&mut self) {
+        self.store(Self::EMPTY);
+    }
+}
+
+impl Default for BootMailbox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Identity of the image in a slot: header address and a checksum of the image, so a new image in the same slot
+/// is not taken for a failed one
+fn image_id<T: Cell>(header: &T) -> [u32; 2] {
+    let info = header.image_info();
+    [header as *const T as *const u8 as u32, info.crc32 ^ header.version().build_timestamp]
+}
+
+/// Boot mailbox of the device, located at the start of `#[noinit_region]` from `device!` of the cells definitions of
+/// `T`. `None` if the region is not configured
+///
+/// # Safety
+/// The mailbox is shared by all cells, the returned reference must not be used concurrently with another one
+pub unsafe fn mailbox<T: Cell>() -> Option<&'static mut BootMailbox> {
+    let addr = T::DEVICE_CONFIG.boot_mailbox()?;
+    // reserved for the mailbox by emcell_configuration!, no cell places anything there
+    Some(unsafe { &mut *(addr as *mut BootMailbox) })
+}
+
+/// Confirm, that the running cell works, so it is booted again after reset. `T` is any cell from the cells
+/// definitions, e.g. the running one. Returns `false` if the device has no boot mailbox
+pub fn confirm_boot<T: Cell>() -> bool {
+    match unsafe { mailbox::<T>() } {
+        Some(mailbox) => {
+            mailbox.confirm();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Why the recovery cell is booted
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RecoveryReason {
+    /// The last booted image was not confirmed with [`confirm_boot`] after `attempts` boots, and no other image is valid
+    TooManyAttempts {
+        attempts: u32,
+    },
+    /// None of the boot targets passed the checks
+    NoValidCell,
+}
+
+/// Cell, selected by [`BootManager`]
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BootDecision {
+    /// Boot the valid cell with the highest priority. `attempt` counts unconfirmed boots of this image, from 1
+    Boot {
+        cell: &'static str,
+        slot: Slot,
+        attempt: u32,
+    },
+    /// Boot the recovery cell
+    Recovery {
+        cell: &'static str,
+        slot: Slot,
+        reason: RecoveryReason,
+    },
+    /// No cell can be booted, including the recovery one
+    NothingToBoot,
+}
+
+/// Cell, rejected by [`BootManager`]
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Rejection {
+    pub cell: &'static str,
+    pub error: CellError,
+}
+
+/// Decision of [`BootManager`] with the reasons of rejected cells
+pub struct BootReport {
+    pub decision: BootDecision,
+    rejected: [Option<Rejection>; MAX_BOOT_TARGETS + 1],
+    entry: Option<Entry>,
+}
+
+impl BootReport {
+    /// Cells, which were tried before the selected one, in the order they were tried
+    pub fn rejected(&self) -> impl Iterator<Item = &Rejection> {
+        self.rejected.iter().flatten()
+    }
+
+    /// Jump to the selected cell. Waits forever if there is nothing to boot
+    pub fn boot(self) -> ! {
+        match self.entry {
+            // entry was produced by a successful check of its cell
+            Some(entry) => unsafe { (entry.boot)(entry.header) },
+            None => loop {
+                core::hint::spin_loop();
+            },
+        }
+    }
+}
+
+/// Boot selection policy of the primary cell.
+///
+/// ```ignore
+/// let report = BootManager::new(&[BootTarget::of::<Cell2>(1)], unsafe { emcell::boot::mailbox::<Cell1>() }.unwrap())
+///     .with_recovery(BootTarget::of::<Cell3>(0))
+///     .select();
+/// report.boot()
+/// ```
+pub struct BootManager<'a> {
+    targets: &'a [BootTarget],
+    recovery: Option<BootTarget>,
+    mailbox: &'a mut BootMailbox,
+    max_attempts: u32,
+    policy: CheckPolicy<'a>,
+}
+
+impl<'a> BootManager<'a> {
+    /// Choose from `targets`, which may contain up to [`MAX_BOOT_TARGETS`] cells.
+    /// By default an image is given 3 boots to confirm itself, cells are checked by signature and header hash only
+    pub fn new(targets: &'a [BootTarget], mailbox: &'a mut BootMailbox) -> Self {
+        assert!(targets.len() <= MAX_BOOT_TARGETS, "too many boot targets");
+        Self {
+            targets,
+            recovery: None,
+            mailbox,
+            max_attempts: 3,
+            policy: CheckPolicy::new(),
+        }
+    }
+
+    /// Cell to boot, when no target is valid
+    pub fn with_recovery(mut self, recovery: BootTarget) -> Self {
+        self.recovery = Some(recovery);
+        self
+    }
+
+    /// Number of boots without [`confirm_boot`], after which an image is not booted anymore
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Checks, every cell must pass to be booted
+    pub fn with_policy(mut self, policy: CheckPolicy<'a>) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Choose the cell to boot and record the boot in the mailbox. Memory of the selected cell is initialized
+    pub fn select(self) -> BootReport {
+        let policy = self.policy;
+        self.select_with(|target, state| unsafe { (target.select)(&policy, state) })
+    }
+
+    /// Boot decision with the mailbox: counts unconfirmed boots, moves images into `failed` after `max_attempts` and
+    /// falls back to the recovery cell. `select` checks a target, skipping the failed images of the state
+    fn select_with(self, mut select: impl FnMut(&BootTarget, &MailboxState) -> Result<Entry, CellError>) -> BootReport {
+        let mut report = BootReport {
+            decision: BootDecision::NothingToBoot,
+            rejected: [None; MAX_BOOT_TARGETS + 1],
+            entry: None,
+        };
+        let mut rejected = 0;
+
+        let mut state = self.mailbox.load();
+        let mut reason = RecoveryReason::NoValidCell;
+        if state.pending_header != 0 && state.attempts >= self.max_attempts {
+            // previous boots of this image were never confirmed
+            state.failed.rotate_left(1);
+            state.failed[FAILED_IMAGES - 1] = [state.pending_header, state.pending_image];
+            reason = RecoveryReason::TooManyAttempts { attempts: state.attempts };
+            state.pending_header = 0;
+        }
+
+        let mut order = [0; MAX_BOOT_TARGETS];
+        let order = &mut order[..self.targets.len()];
+        for (i, index) in order.iter_mut().enumerate() {
+            *index = i;
+        }
+        order.sort_unstable_by_key(|i| (core::cmp::Reverse(self.targets[*i].priority), *i));
+
+        for target in order.iter().map(|i| &self.targets[*i]) {
+            match select(target, &state) {
+                Ok(entry) => {
+                    let same_image = state.pending_header == entry.image[0] && state.pending_image == entry.image[1];
+                    state.attempts = if same_image { state.attempts + 1 } else { 1 };
+                    [state.pending_header, state.pending_image] = entry.image;
+                    self.mailbox.store(state);
+
+                    report.decision = BootDecision::Boot {
+                        cell: target.name,
+                        slot: entry.slot,
+                        attempt: state.attempts,
+                    };
+                    report.entry = Some(entry);
+                    return report;
+                }
+                Err(error) => {
+                    report.rejected[rejected] = Some(Rejection { cell: target.name, error });
+                    rejected += 1;
+                }
+            }
+        }
+
+        // recovery cell is never skipped, so it does not wait for confirmation
+        state.pending_header = 0;
+        self.mailbox.store(state);
+        if let Some(recovery) = &self.recovery {
+            match select(recovery, &BootMailbox::EMPTY) {
+                Ok(entry) => {
+                    report.decision = BootDecision::Recovery {
+                        cell: recovery.name,
+                        slot: entry.slot,
+                        reason,
+                    };
+                    report.entry = Some(entry);
+                }
+                Err(error) => report.rejected[rejected] = Some(Rejection { cell: recovery.name, error }),
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fake cell: images in its slots, `Err` if the slot does not pass the checks
+    struct FakeCell {
+        name: &'static str,
+        slots: &'static [(Slot, Result<u32, CellError>)],
+    }
+
+    const CELL_A: FakeCell = FakeCell { name: "A", slots: &[(Slot::A, Ok(0x100)), (Slot::B, Ok(0x200))] };
+    const CELL_B: FakeCell = FakeCell { name: "B", slots: &[(Slot::A, Ok(0x300))] };
+    const BROKEN: FakeCell = FakeCell { name: "Broken", slots: &[(Slot::A, Err(CellError::NotFlashed))] };
+    const RECOVERY: FakeCell = FakeCell { name: "Recovery", slots: &[(Slot::A, Ok(0x400))] };
+
+    unsafe fn never(_: *const ()) -> ! {
+        unreachable!()
+    }
+
+    unsafe fn unused(_: &CheckPolicy, _: &MailboxState) -> Result<Entry, CellError> {
+        unreachable!()
+    }
+
+    fn target(cell: &FakeCell, priority: u8) -> BootTarget {
+        BootTarget { name: cell.name, priority, select: unused }
+    }
+
+    /// Same as [`select`]: the first valid slot, which image did not fail
+    fn fake_select(target: &BootTarget, state: &MailboxState) -> Result<Entry, CellError> {
+        let cell = [CELL_A, CELL_B, BROKEN, RECOVERY].into_iter().find(|cell| cell.name == target.name).unwrap();
+        let mut error = CellError::NoSuchSlot;
+        for (slot, image) in cell.slots {
+            match image {
+                Ok(header) if state.failed.contains(&[*header, 1]) => error = CellError::NotConfirmed,
+                Ok(header) => return Ok(Entry { header: core::ptr::null(), slot: *slot, image: [*header, 1], boot: never }),
+                Err(e) => error = *e,
+            }
+        }
+        Err(error)
+    }
+
+    fn boot(targets: &[BootTarget], mailbox: &mut BootMailbox) -> BootReport {
+        boot_with_max_attempts(targets, mailbox, 3)
+    }
+
+    fn boot_with_max_attempts(targets: &[BootTarget], mailbox: &mut BootMailbox, max_attempts: u32) -> BootReport {
+        BootManager::new(targets, mailbox)
+            .with_recovery(target(&RECOVERY, 0))
+            .with_max_attempts(max_attempts)
+            .select_with(fake_select)
+    }
+
+    fn booted(cell: &'static str, slot: Slot, attempt: u32) -> BootDecision {
+        BootDecision::Boot { cell, slot, attempt }
+    }
+
+    #[test]
+    fn boots_highest_priority_cell() {
+        let mut mailbox = BootMailbox::new();
+        let targets = [target(&CELL_B, 1), target(&CELL_A, 2)];
+        assert_eq!(boot(&targets, &mut mailbox).decision, booted("A", Slot::A, 1));
+        assert_eq!(mailbox.attempts(), 1);
+    }
+
+    #[test]
+    fn equal_priorities_keep_order() {
+        let mut mailbox = BootMailbox::new();
+        let targets = [target(&CELL_B, 1), target(&CELL_A, 1)];
+        assert_eq!(boot(&targets, &mut mailbox).decision, booted("B", Slot::A, 1));
+    }
+
+    #[test]
+    fn rejected_cells_are_reported() {
+        let mut mailbox = BootMailbox::new();
+        let targets = [target(&BROKEN, 2), target(&CELL_B, 1)];
+        let report = boot(&targets, &mut mailbox);
+        assert_eq!(report.decision, booted("B", Slot::A, 1));
+        assert!(report.rejected().eq([&Rejection { cell: "Broken", error: CellError::NotFlashed }]));
+    }
+
+    #[test]
+    fn unconfirmed_boots_are_counted() {
+        let mut mailbox = BootMailbox::new();
+        let targets = [target(&CELL_B, 1)];
+        for attempt in 1..=3 {
+            assert_eq!(boot(&targets, &mut mailbox).decision, booted("B", Slot::A, attempt));
+            assert_eq!(mailbox.attempts(), attempt);
+        }
+    }
+
+    #[test]
+    fn confirmed_image_starts_over() {
+        let mut mailbox = BootMailbox::new();
+        let targets = [target(&CELL_B, 1)];
+        for _ in 0..5 {
+            assert_eq!(boot(&targets, &mut mailbox).decision, booted("B", Slot::A, 1));
+            mailbox.confirm();
+            assert_eq!(mailbox.attempts(), 0);
+        }
+    }
+
+    #[test]
+    fn failed_image_falls_back_to_other_slot() {
+        let mut mailbox = BootMailbox::new();
+        let targets = [target(&CELL_A, 1)];
+        for attempt in 1..=3 {
+            assert_eq!(boot(&targets, &mut mailbox).decision, booted("A", Slot::A, attempt));
+        }
+        assert_eq!(boot(&targets, &mut mailbox).decision, booted("A", Slot::B, 1));
+    }
+
+    #[test]
+    fn too_many_attempts_boot_recovery() {
+        let mut mailbox = BootMailbox::new();
+        let targets = [target(&CELL_B, 1)];
+        for attempt in 1..=2 {
+            assert_eq!(boot_with_max_attempts(&targets, &mut mailbox, 2).decision, booted("B", Slot::A, attempt));
+        }
+        let report = boot_with_max_attempts(&targets, &mut mailbox, 2);
+        assert_eq!(report.decision, BootDecision::Recovery {
+            cell: "Recovery",
+            slot: Slot::A,
+            reason: RecoveryReason::TooManyAttempts { attempts: 2 },
+        });
+        assert!(report.rejected().eq([&Rejection { cell: "B", error: CellError::NotConfirmed }]));
+        // recovery cell does not wait for confirmation
+        assert_eq!(mailbox.attempts(), 0);
+
+        // failed image stays skipped
+        let report = boot_with_max_attempts(&targets, &mut mailbox, 2);
+        assert_eq!(report.decision, BootDecision::Recovery {
+            cell: "Recovery",
+            slot: Slot::A,
+            reason: RecoveryReason::NoValidCell,
+        });
+
+        mailbox.clear();
+        assert_eq!(boot_with_max_attempts(&targets, &mut mailbox, 2).decision, booted("B", Slot::A, 1));
+    }
+
+    #[test]
+    fn oldest_failed_image_is_forgotten() {
+        let mut mailbox = BootMailbox::new();
+        let mut state = BootMailbox::EMPTY;
+        state.failed = [[0x100, 1], [0x200, 1], [0x500, 1], [0x600, 1]];
+        mailbox.store(state);
+
+        // both slots of A failed
+        let targets = [target(&CELL_A, 1)];
+        assert_eq!(boot(&targets, &mut mailbox).decision, BootDecision::Recovery {
+            cell: "Recovery",
+            slot: Slot::A,
+            reason: RecoveryReason::NoValidCell,
+        });
+
+        // B was never confirmed and takes the place of the oldest failed image, slot A of A
+        state.pending_header = 0x300;
+        state.pending_image = 1;
+        state.attempts = 3;
+        mailbox.store(state);
+        let targets = [target(&CELL_B, 2), target(&CELL_A, 1)];
+        let report = boot(&targets, &mut mailbox);
+        assert_eq!(report.decision, booted("A", Slot::A, 1));
+        assert!(report.rejected().eq([&Rejection { cell: "B", error: CellError::NotConfirmed }]));
+        assert_eq!(mailbox.load().failed, [[0x200, 1], [0x500, 1], [0x600, 1], [0x300, 1]]);
+    }
+
+    #[test]
+    fn nothing_to_boot_without_recovery() {
+        let mut mailbox = BootMailbox::new();
+        let targets = [target(&BROKEN, 1)];
+        let report = BootManager::new(&targets, &mut mailbox).select_with(fake_select);
+        assert_eq!(report.decision, BootDecision::NothingToBoot);
+        assert_eq!(report.rejected().count(), 1);
+    }
+
+    #[test]
+    fn random_mailbox_is_empty() {
+        let mut mailbox = BootMailbox::new();
+        let mut state = BootMailbox::EMPTY;
+        state.magic = 0x1234_5678;
+        state.pending_header = 0x300;
+        state.attempts = 7;
+        mailbox.store(state);
+        assert_eq!(mailbox.attempts(), 0);
+
+        let targets = [target(&CELL_B, 1)];
+        assert_eq!(boot(&targets, &mut mailbox).decision, booted("B", Slot::A, 1));
+    }
+}
@@ -1,50 +1,8 @@
 extern crate std;
 
-use crate::meta::{CellDefMeta, DeviceConfigMeta};
+pub use crate::meta::PartitionedFlashRegion;
 
-const HEADER_SIZE: usize = 1024;
-
-pub struct PartitionedFlashRegion {
-    pub start_flash: usize,
-    pub end_flash: usize,
-    pub start_header: usize,
-    pub end_header: usize,
-}
-
-impl PartitionedFlashRegion {
-    pub fn new_header_first(cell: &CellDefMeta, device_config_meta: &DeviceConfigMeta) -> Self {
-        let start_header = cell.absolute_flash_start(device_config_meta);
-        let end_header = start_header + HEADER_SIZE;
-
-        let start_flash = end_header;
-        let end_flash = cell.absolute_flash_end(device_config_meta);
-
-
-        Self {
-            start_flash,
-            end_flash,
-            start_header,
-            end_header,
-        }
-    }
-
-    pub fn new_header_last(cell: &CellDefMeta, device_config_meta: &DeviceConfigMeta) -> Self {
-        let start_flash = cell.absolute_flash_start(device_config_meta);
-        let end_flash = cell.absolute_flash_end(device_config_meta) - HEADER_SIZE;
-
-        let start_header = end_flash;
-        let end_header = start_header + HEADER_SIZE;
-
-        Self {
-            start_flash,
-            end_flash,
-            start_header,
-            end_header,
-        }
-    }
-}
 use crate::build_rs::std::io::Read;
-use crate::CellType;
 
 #[cfg(feature = "rt-crate-cortex-m-rt")]
 pub fn build_rs<T: crate::Cell + 'static>() {
@@ -62,11 +20,11 @@ pub fn build_rs<T: crate::Cell + 'static>() {
     let cur_cell_name = cur_cell_meta.name;
     let other_cells_names: std::vec::Vec<&str> = cells_meta.iter().map(|cell| cell.name).filter(|name| *name != cur_cell_name).collect();
 
-    let cur_partitioned_flash_region =
-        match cur_cell_meta.cell_type {
-            CellType::Primary => PartitionedFlashRegion::new_header_last(cur_cell_meta, &T::DEVICE_CONFIG),
-            CellType::NonPrimary => PartitionedFlashRegion::new_header_first(cur_cell_meta, &T::DEVICE_CONFIG)
-        };
+    // A/B slot to link this cell for, see emcell::Slot
+    let slot = crate::meta::Slot::from_env(env::var("EMCELL_SLOT").ok().as_deref());
+    let Some(cur_partitioned_flash_region) = cur_cell_meta.partitioned_flash_in(slot, &T::DEVICE_CONFIG) else {
+        panic!("EMCELL_SLOT: cell {} has no flash slot {:?}, declare second #[flash_region] for it", cur_cell_name, slot);
+    };
     let mut memory_definition = String::from("# THIS SCRIPT WAS GENERATED AUTOMATICALLY BY emcell LIBRARY!\nMEMORY {\n")
         // this cell flash definition
         + &std::format!("  FLASH : ORIGIN = 0x{:X}, LENGTH = {}\n",
@@ -75,17 +33,14 @@ pub fn build_rs<T: crate::Cell + 'static>() {
         + &std::format!("  CUR_HEADER : ORIGIN = 0x{:X}, LENGTH = {}\n",
                         cur_partitioned_flash_region.start_header,
                         cur_partitioned_flash_region.end_header - cur_partitioned_flash_region.start_header)
+        // own stack of the cell is not available for .data and .bss
         + &std::format!("  RAM : ORIGIN = 0x{:X}, LENGTH = {}\n\n",
-                        cur_cell_meta.absolute_ram_start(&T::DEVICE_CONFIG),
-                        cur_cell_meta.absolute_ram_end(&T::DEVICE_CONFIG) - cur_cell_meta.absolute_ram_start(&T::DEVICE_CONFIG));
+                        cur_cell_meta.absolute_data_ram_start(&T::DEVICE_CONFIG),
+                        cur_cell_meta.absolute_data_ram_end(&T::DEVICE_CONFIG) - cur_cell_meta.absolute_data_ram_start(&T::DEVICE_CONFIG));
 
     for cell_meta in cells_meta {
         let cell_name = cell_meta.name;
-        let partitioned_flash_region =
-            match cell_meta.cell_type {
-                CellType::Primary => PartitionedFlashRegion::new_header_last(cell_meta, &T::DEVICE_CONFIG),
-                CellType::NonPrimary => PartitionedFlashRegion::new_header_first(cell_meta, &T::DEVICE_CONFIG)
-            };
+        let partitioned_flash_region = cell_meta.partitioned_flash(&T::DEVICE_CONFIG);
         memory_definition += &(std::format!("  {}_FLASH : ORIGIN = 0x{:X}, LENGTH = {}\n",
                                                          cell_name,
                                                          partitioned_flash_region.start_flash,
@@ -100,19 +55,34 @@ pub fn build_rs<T: crate::Cell + 'static>() {
                             cell_meta.absolute_ram_end(&T::DEVICE_CONFIG) - cell_meta.absolute_ram_start(&T::DEVICE_CONFIG)));
     }
 
-    memory_definition += "}\n\n";
+    // no-init region is shared by all cells, see #[emcell::retained]
+    let noinit_range = T::DEVICE_CONFIG.absolute_noinit_range();
+    if let Some((start, end)) = noinit_range {
+        memory_definition += &std::format!("  NOINIT : ORIGIN = 0x{:X}, LENGTH = {}\n", start, end - start);
+    }
 
-    // Stack strategy: place stack at the start of RAM
-    memory_definition += std::format!("_stack_end = 0x{:X};\n\n", T::DEVICE_CONFIG.ram_range_start).as_str();
-    memory_definition += std::format!("_stack_start = 0x{:X};\n\n", T::DEVICE_CONFIG.initial_stack_ptr).as_str();
+    memory_definition += "}\n\n";
 
+    // Stack strategy: own stack of the cell, otherwise the shared one, see emcell::meta::StackStrategy
+    let (stack_end, stack_start) = cur_cell_meta.absolute_stack_range(&T::DEVICE_CONFIG)
+        .unwrap_or(T::DEVICE_CONFIG.stack_range);
+    memory_definition += std::format!("_stack_end = 0x{:X};\n\n", stack_end).as_str();
+    memory_definition += std::format!("_stack_start = 0x{:X};\n\n", stack_start).as_str();
+    for cell_meta in cells_meta {
+        if let Some((end, start)) = cell_meta.absolute_stack_range(&T::DEVICE_CONFIG) {
+            memory_definition += std::format!("_emcell_{}_stack_end = 0x{:X};\n", cell_meta.name, end).as_str();
+            memory_definition += std::format!("_emcell_{}_stack_start = 0x{:X};\n\n", cell_meta.name, start).as_str();
+        }
+    }
     memory_definition += "SECTIONS {\n";
     // this cell header
     memory_definition += &(String::from("    .CUR_HEADER ORIGIN(CUR_HEADER) : {\n")
         + "        . = ALIGN(4);\n"
         + "        KEEP(*(.emcell.cur_header))\n"
-        + "        . = ALIGN(4);\n"
-        + "    } > CUR_HEADER\n");
+        // signature trailer occupies the end of the header region
+        + &std::format!("        . += 0x{:X} - (. - ORIGIN(CUR_HEADER));\n", crate::meta::header_layout::SIGNATURE_TRAILER)
+        + "        KEEP(*(.emcell.signature))\n"
+        + "    } > CUR_HEADER =0xFF\n");
 
     // other cells headers
     for cell_name in &other_cells_names {
@@ -121,7 +91,19 @@ pub fn build_rs<T: crate::Cell + 'static>() {
             + "    } > " + cell_name + "_HEADER\n");
     }
 
+    // retained statics are sorted by name, so cells declaring the same statics share their layout
+    memory_definition += &(String::from("    .emcell.noinit (NOLOAD) : ALIGN(4) {\n")
+        + "        _emcell_noinit_start = .;\n"
+        // emcell::boot::BootMailbox
+        + &if noinit_range.is_some() { std::format!("        . = . + {};\n", crate::meta::BOOT_MAILBOX_SIZE) } else { String::new() }
+        + "        KEEP(*(SORT_BY_NAME(.emcell.noinit.*)))\n"
+        + "        _emcell_noinit_end = .;\n"
+        + if noinit_range.is_some() { "    } > NOINIT\n" } else { "    } > RAM\n" });
+
     memory_definition += "}\n";
+    if noinit_range.is_none() {
+        memory_definition += "ASSERT(_emcell_noinit_end == _emcell_noinit_start, \"#[emcell::retained] statics require #[noinit_region] on device!\");\n";
+    }
     if let Ok(mut f) = File::open("memory.x") {
         memory_definition += "# Start of user-provided memory.x extension\n";
         f.read_to_string(&mut memory_definition).unwrap();
@@ -133,4 +115,61 @@ pub fn build_rs<T: crate::Cell + 'static>() {
     f.write_all(memory_definition.as_bytes()).unwrap();
 
     std::println!("cargo:rustc-link-search={}", out_dir.display());
+    std::println!("cargo:rustc-env=EMCELL_SLOT={:?}", slot);
+    // unlike rerun-if-changed, this keeps the default "rerun on any change in the package"
+    for var in ["EMCELL_SLOT", "SOURCE_DATE_EPOCH"] {
+        std::println!("cargo:rerun-if-env-changed={}", var);
+    }
+
+    emit_version_env();
+}
+
+/// Rerun the build script only when the files, read by [`build_rs`], change: the user `memory.x` extension and the
+/// git revision. Environment variables `EMCELL_SLOT` and `SOURCE_DATE_EPOCH` are tracked by [`build_rs`] itself.
+///
+/// `rerun-if-changed` disables the default "rerun on any change in the package", so other inputs of the build script
+/// must be listed by it as well
+pub fn build_rs_rerun_if_changed() {
+    use std::process::Command;
+    use std::string::String;
+
+    std::println!("cargo:rerun-if-changed=memory.x");
+
+    // git revision changes with HEAD or with the branch it points to
+    let git_path = |path: &str| Command::new("git").args(["rev-parse", "--git-path", path]).output().ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from(String::from_utf8_lossy(&output.stdout).trim()));
+    let branch = Command::new("git").args(["symbolic-ref", "-q", "HEAD"]).output().ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from(String::from_utf8_lossy(&output.stdout).trim()));
+    for path in core::iter::once(String::from("HEAD")).chain(branch) {
+        if let Some(path) = git_path(&path) {
+            std::println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+}
+
+/// Provide git revision and build timestamp for the version block of the cell header
+fn emit_version_env() {
+    use std::process::Command;
+    use std::string::String;
+
+    let git = |args: &[&str]| Command::new("git").args(args).output().ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from(String::from_utf8_lossy(&output.stdout).trim()));
+
+    if let Some(mut revision) = git(&["rev-parse", "--short=12", "HEAD"]) {
+        if git(&["status", "--porcelain", "--untracked-files=no"]).is_some_and(|status| !status.is_empty()) {
+            revision += "-dirty";
+        }
+        std::println!("cargo:rustc-env=EMCELL_GIT_REVISION={}", revision);
+    }
+
+    // SOURCE_DATE_EPOCH allows reproducible builds
+    let timestamp = std::env::var("SOURCE_DATE_EPOCH").ok()
+        .and_then(|epoch| epoch.parse::<u64>().ok())
+        .or_else(|| std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).ok().map(|d| d.as_secs()));
+    if let Some(timestamp) = timestamp {
+        std::println!("cargo:rustc-env=EMCELL_BUILD_TIMESTAMP={}", timestamp);
+    }
 }
\ No newline at end of file
@@ -1,5 +1,8 @@
 /// Must be called once to initialize memory
 /// It is enforced by rule "only one forward extern for cell"
+///
+/// # Safety
+/// Overwrites `.bss` and `.data` of the current cell, so no statics of this cell may be in use.
 pub unsafe fn init() {
     init_memory();
 }
@@ -8,6 +11,9 @@ use core::ptr::{addr_of, addr_of_mut};
 
 /// Initialize BSS and DATA sections
 /// Should not be called directly! Header wrapper use this function to initialize memory on first cell access
+///
+/// # Safety
+/// Same as [`init`].
 #[inline(always)]
 #[cfg(feature = "rt-crate-cortex-m-rt")]
 pub unsafe fn init_memory() {
@@ -36,15 +42,146 @@ pub unsafe fn init_memory() {
     }
 }
 
+/// Point VTOR to the vector table of the current cell
+///
+/// # Safety
+/// Every interrupt enabled at this point will be dispatched to the handlers of the current cell.
 #[cfg(feature = "rt-crate-cortex-m-rt")]
 pub unsafe fn switch_vectors() {
     extern "C" {
         static mut __vector_table: u32;
     }
-    let vector_table = addr_of_mut!(__vector_table) as *mut u32;
+    let vector_table = addr_of_mut!(__vector_table);
 
     let scb = unsafe {&*cortex_m::peripheral::SCB::PTR};
 
     // let vtor = scb.vtor.read() as *mut u32;
     scb.vtor.write(vector_table as u32);
-}
\ No newline at end of file
+}
+/// Put the core into a state close to reset before another cell takes over its vector table:
+/// - all NVIC interrupts are disabled and their pending bits cleared,
+/// - pending PendSV and SysTick exceptions are cleared,
+/// - SysTick is stopped and reset,
+/// - MemManage, BusFault and UsageFault handlers are disabled (ARMv7-M and later),
+/// - instruction cache is invalidated (ARMv7-M and later, no-op without cache), so code of the other cell is fetched
+///   from flash even after an update. Data cache is left as is, it stays coherent for the core.
+///
+/// PRIMASK is restored afterwards, no interrupt can fire since all of them are disabled.
+/// Called automatically by `switch_vectors_and_run` and [`boot_cell`], unless the cell opted out with
+/// `#[pre_switch(quiesce = false)]` in `define_header!`.
+///
+/// # Safety
+/// Interrupt handlers of the current cell stop working, peripherals still may generate requests.
+#[cfg(feature = "rt-crate-cortex-m-rt")]
+pub unsafe fn quiesce() {
+    use cortex_m::peripheral::{NVIC, SCB, SYST};
+
+    const ICSR_PENDSVCLR: u32 = 1 << 27;
+    const ICSR_PENDSTCLR: u32 = 1 << 25;
+    const ICIALLU: *mut u32 = 0xE000_EF50 as *mut u32;
+
+    use crate::interrupts::{is_armv7m, SHCSR_FAULTENA};
+
+    cortex_m::interrupt::free(|_| {
+        let nvic = unsafe { &*NVIC::PTR };
+        for (icer, icpr) in nvic.icer.iter().zip(&nvic.icpr) {
+            unsafe {
+                icer.write(u32::MAX);
+                icpr.write(u32::MAX);
+            }
+        }
+
+        let syst = unsafe { &*SYST::PTR };
+        unsafe {
+            syst.csr.write(0);
+            syst.rvr.write(0);
+            syst.cvr.write(0);
+        }
+
+        let scb = unsafe { &*SCB::PTR };
+        unsafe { scb.icsr.write(ICSR_PENDSVCLR | ICSR_PENDSTCLR) };
+
+        if is_armv7m() {
+            unsafe {
+                scb.shcsr.modify(|shcsr| shcsr & !SHCSR_FAULTENA);
+                cortex_m::asm::dsb();
+                ICIALLU.write_volatile(0);
+            }
+        }
+        cortex_m::asm::dsb();
+        cortex_m::asm::isb();
+    });
+}
+
+/// Start cell `T` from slot A, see [`boot_cell_in`]
+///
+/// # Safety
+/// Same as [`boot_cell_in`].
+#[cfg(feature = "rt-crate-cortex-m-rt")]
+pub unsafe fn boot_cell<T: crate::boot::Bootable>() -> ! {
+    unsafe { boot_cell_in::<T>(crate::Slot::A) }
+}
+
+/// Start cell `T` from the reset handler of its vector table, as after a reset of the device.
+///
+/// Unlike `switch_vectors_and_run`, the current cell is left for good:
+/// 1. [`crate::pre_switch`] runs the `#[pre_switch]` hook of the current cell and [`quiesce`], unless the cell opted
+///    out with `#[pre_switch(quiesce = false)]`,
+/// 2. VTOR is pointed to the vector table of `T` at the start of its flash, with interrupts disabled,
+/// 3. interrupts are enabled again, MSP is loaded from the vector table and the reset handler of `T` is called.
+///
+/// The runtime of `T` initializes its memory and enters `define_header!`, which calls the `#[switch_vectors]` function
+/// of the cell. Peripherals are not reset, so DMA and clocks keep running. Without [`quiesce`], interrupts, left
+/// enabled by the current cell, are dispatched to the handlers of `T` right away, even before its runtime started.
+///
+/// # Safety
+/// The image of `T` in `slot` must be validated, e.g. with [`crate::Cell::validate`]. Nothing of the current cell,
+/// including its stack, may be used after the hand-over.
+#[cfg(feature = "rt-crate-cortex-m-rt")]
+pub unsafe fn boot_cell_in<T: crate::boot::Bootable>(slot: crate::Slot) -> ! {
+    let Some(flash) = T::CUR_META.partitioned_flash_in(slot, &T::DEVICE_CONFIG) else {
+        panic!("emcell: cell {} has no flash slot {:?}", T::CUR_META.name, slot);
+    };
+    unsafe { crate::pre_switch() };
+    // cortex-m-rt places the vector table at the start of the cell flash
+    unsafe { hand_over(flash.start_flash as *const u32) }
+}
+
+/// Start the primary cell from the reset handler of its vector table at the start of the device flash, as after a
+/// reset of the device. `define_header!` calls it, when a cell is entered by a reset rather than by [`boot_cell`].
+///
+/// # Safety
+/// Nothing of the current cell, including its stack, may be used after the hand-over.
+#[cfg(feature = "rt-crate-cortex-m-rt")]
+pub unsafe fn boot_primary<T: crate::Cell>() -> ! {
+    unsafe { hand_over(T::DEVICE_CONFIG.flash_range_start as *const u32) }
+}
+
+/// Point VTOR to `vector_table`, load MSP from it and call its reset handler
+#[cfg(feature = "rt-crate-cortex-m-rt")]
+unsafe fn hand_over(vector_table: *const u32) -> ! {
+    use cortex_m::peripheral::SCB;
+
+    cortex_m::interrupt::disable();
+
+    let scb = unsafe { &*SCB::PTR };
+    unsafe { scb.vtor.write(vector_table as u32) };
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+
+    unsafe {
+        cortex_m::interrupt::enable();
+        cortex_m::asm::bootload(vector_table)
+    }
+}
+
+/// Whether VTOR points to the vector table of the current cell, i.e. it was started by [`boot_cell`] rather than
+/// by a reset of the device
+#[cfg(feature = "rt-crate-cortex-m-rt")]
+pub fn started_by_boot_cell() -> bool {
+    extern "C" {
+        static __vector_table: u32;
+    }
+    let scb = unsafe { &*cortex_m::peripheral::SCB::PTR };
+    scb.vtor.read() == addr_of!(__vector_table) as u32
+}
@@ -0,0 +1,65 @@
+/// Reason why a cell header was rejected
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CellError {
+    /// Header location is erased, cell was never flashed
+    NotFlashed,
+    /// Header location contains something else than a header of this cell
+    BadSignature {
+        found: u32,
+    },
+    /// Header belongs to this cell, but was built from different cells definitions.
+    ///
+    /// `first_differing_field` is `None` if all known fields match, but the layout or device configuration differs.
+    HashMismatch {
+        first_differing_field: Option<&'static str>,
+    },
+    /// Cell refused initialization
+    InitFailed,
+    /// Integrity check requested, but image length and checksum were never written into the header
+    ImageNotPatched,
+    /// Image length from the header does not fit into the cell flash region
+    ImageLengthInvalid {
+        length: u32,
+    },
+    /// Image checksum does not match the one from the header, e.g. after an interrupted update
+    ImageCorrupted,
+    /// Cell must be signed, but its signature trailer is erased
+    ImageNotSigned,
+    /// Image signature does not match the public key of the cell, or signature verification is not available
+    ImageSignatureInvalid,
+    /// Security counter of the cell is lower than the minimum accepted one, see `emcell::rollback`
+    RolledBack {
+        counter: u32,
+        min_counter: u32,
+    },
+    /// Cell has no such flash slot
+    NoSuchSlot,
+    /// Image in this slot was linked for another slot (`linked` is `Slot as u32`)
+    WrongSlot {
+        linked: u32,
+    },
+    /// Image was booted before, but never confirmed with `emcell::confirm_boot`, see `emcell::boot`
+    NotConfirmed,
+}
+
+impl core::fmt::Display for CellError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            CellError::NotFlashed => write!(f, "cell is not flashed"),
+            CellError::BadSignature { found } => write!(f, "bad header signature 0x{:08X}", found),
+            CellError::HashMismatch { first_differing_field: Some(field) } => write!(f, "header hash mismatch, first differing field: {}", field),
+            CellError::HashMismatch { first_differing_field: None } => write!(f, "header hash mismatch in cell layout or device configuration"),
+            CellError::InitFailed => write!(f, "cell initialization failed"),
+            CellError::ImageNotPatched => write!(f, "image length and checksum are missing in the header"),
+            CellError::ImageLengthInvalid { length } => write!(f, "image length {} does not fit into the cell flash region", length),
+            CellError::ImageCorrupted => write!(f, "image checksum mismatch"),
+            CellError::ImageNotSigned => write!(f, "image is not signed"),
+            CellError::ImageSignatureInvalid => write!(f, "invalid image signature"),
+            CellError::RolledBack { counter, min_counter } => write!(f, "security counter {} is lower than minimum {}", counter, min_counter),
+            CellError::NoSuchSlot => write!(f, "cell has no such slot"),
+            CellError::WrongSlot { linked } => write!(f, "image is linked for another slot ({})", linked),
+            CellError::NotConfirmed => write!(f, "image was booted, but never confirmed"),
+        }
+    }
+}
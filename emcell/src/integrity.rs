@@ -0,0 +1,144 @@
+use crate::meta::{header_layout, ImageInfo, SignatureTrailer};
+use crate::{Cell, CellError, Slot};
+
+/// How to verify the cell image before the first call into it
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum IntegrityCheck {
+    /// Trust signature and header hash only
+    None,
+    /// Compare CRC-32 of the image with the one from the header
+    Crc32,
+    /// Compare SHA-256 of the image with the one from the header
+    #[cfg(feature = "sha256")]
+    Sha256,
+}
+
+const CRC32_POLY: u32 = 0xEDB8_8320;
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ CRC32_POLY } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Incremental CRC-32 (ISO-HDLC, same as zlib and Ethernet)
+#[derive(Copy, Clone)]
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+    pub const fn new() -> Self {
+        Self { state: 0xFFFF_FFFF }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.state = CRC32_TABLE[((self.state ^ *byte as u32) & 0xFF) as usize] ^ (self.state >> 8);
+        }
+    }
+
+    pub fn finalize(&self) -> u32 {
+        !self.state
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finalize()
+}
+
+/// Location of the image of cell `T` in flash `slot`, as described by `info`
+pub fn image_range<T: Cell + ?Sized>(slot: Slot, info: &ImageInfo) -> Result<core::ops::Range<usize>, CellError> {
+    if !info.is_patched() {
+        return Err(CellError::ImageNotPatched);
+    }
+
+    let region = T::CUR_META.partitioned_flash_in(slot, &T::DEVICE_CONFIG).ok_or(CellError::NoSuchSlot)?;
+    let length = info.length as usize;
+    if length > region.end_flash - region.start_flash {
+        return Err(CellError::ImageLengthInvalid { length: info.length });
+    }
+    Ok(region.start_flash..region.start_flash + length)
+}
+
+/// Check image of cell `T` in `slot` against length and checksum from its header
+pub fn verify_image<T: Cell + ?Sized>(slot: Slot, info: &ImageInfo, check: IntegrityCheck) -> Result<(), CellError> {
+    if check == IntegrityCheck::None {
+        return Ok(());
+    }
+
+    let range = image_range::<T>(slot, info)?;
+    // image range is inside the flash region of the cell, which is always readable
+    let image = unsafe { core::slice::from_raw_parts(range.start as *const u8, range.len()) };
+
+    let ok = match check {
+        IntegrityCheck::None => true,
+        IntegrityCheck::Crc32 => crc32(image) == info.crc32,
+        #[cfg(feature = "sha256")]
+        IntegrityCheck::Sha256 => {
+            use sha2::Digest;
+            sha2::Sha256::digest(image).as_slice() == info.sha256
+        }
+    };
+
+    if ok {
+        Ok(())
+    } else {
+        Err(CellError::ImageCorrupted)
+    }
+}
+
+/// Check Ed25519 signature of cell `T` in `slot` with the public key from its definition.
+///
+/// Fails with [`CellError::ImageSignatureInvalid`] if `ed25519` feature is disabled, so cells requiring a signature
+/// are never accepted unchecked.
+pub fn verify_signature<T: Cell + ?Sized>(slot: Slot, info: &ImageInfo) -> Result<(), CellError> {
+    let Some(public_key) = T::CUR_META.public_key else {
+        return Ok(());
+    };
+
+    let region = T::CUR_META.partitioned_flash_in(slot, &T::DEVICE_CONFIG).ok_or(CellError::NoSuchSlot)?;
+    // trailer is inside the header region of the cell, which is always readable
+    let trailer = unsafe { &*((region.start_header + header_layout::SIGNATURE_TRAILER) as *const SignatureTrailer) };
+    if !trailer.is_signed() {
+        return Err(CellError::ImageNotSigned);
+    }
+    let range = image_range::<T>(slot, info)?;
+
+    #[cfg(feature = "ed25519")]
+    {
+        use sha2::Digest;
+
+        let header = unsafe { core::slice::from_raw_parts(region.start_header as *const u8, header_layout::SIGNATURE_TRAILER) };
+        let image = unsafe { core::slice::from_raw_parts(range.start as *const u8, range.len()) };
+        let digest = sha2::Sha256::new().chain_update(header).chain_update(image).finalize();
+
+        let key = ed25519_dalek::VerifyingKey::from_bytes(&public_key).map_err(|_| CellError::ImageSignatureInvalid)?;
+        let signature = ed25519_dalek::Signature::from_bytes(&trailer.signature);
+        key.verify_strict(&digest, &signature).map_err(|_| CellError::ImageSignatureInvalid)
+    }
+    #[cfg(not(feature = "ed25519"))]
+    {
+        let _ = (public_key, range);
+        Err(CellError::ImageSignatureInvalid)
+    }
+}
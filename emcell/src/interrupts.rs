@@ -0,0 +1,77 @@
+//! Interrupt configuration of a cell, which is given back to it after a call into another cell.
+//!
+//! Unlike `device`, available with `build-rs` feature too, so `switch_vectors_and_call` never skips
+//! restoring it.
+
+/// MemManage, BusFault and UsageFault enable bits of SHCSR
+pub(crate) const SHCSR_FAULTENA: u32 = 0b111 << 16;
+
+/// ARMv7-M or later, which has configurable fault handlers and may have an instruction cache
+pub(crate) fn is_armv7m() -> bool {
+    // CPUID.ARCHITECTURE: 0xC for ARMv6-M, 0xF for ARMv7-M and ARMv8-M
+    const ARCH_V7M: u32 = 0xF;
+    let cpuid = unsafe { &*cortex_m::peripheral::CPUID::PTR };
+    (cpuid.base.read() >> 16) & 0xF == ARCH_V7M
+}
+
+/// Interrupt configuration of the current cell, which `device::quiesce` turns off: NVIC enables, SysTick and fault
+/// handler enables. Used by `switch_vectors_and_call` to give the caller its interrupts back
+pub struct InterruptState {
+    iser: [u32; 16],
+    syst_csr: u32,
+    syst_rvr: u32,
+    shcsr: u32,
+}
+
+impl InterruptState {
+    pub fn save() -> Self {
+        use cortex_m::peripheral::{NVIC, SCB, SYST};
+
+        let nvic = unsafe { &*NVIC::PTR };
+        let mut iser = [0; 16];
+        for (saved, reg) in iser.iter_mut().zip(&nvic.iser) {
+            *saved = reg.read();
+        }
+        let syst = unsafe { &*SYST::PTR };
+        let scb = unsafe { &*SCB::PTR };
+        Self {
+            iser,
+            syst_csr: syst.csr.read(),
+            syst_rvr: syst.rvr.read(),
+            shcsr: scb.shcsr.read() & SHCSR_FAULTENA,
+        }
+    }
+
+    /// Enable exactly the saved NVIC interrupts, restart SysTick with the saved configuration and enable the saved
+    /// fault handlers. Pending bits are left as is
+    ///
+    /// # Safety
+    /// VTOR must point to the vector table of the cell, which saved the state, its handlers fire right away
+    pub unsafe fn restore(&self) {
+        use cortex_m::peripheral::{NVIC, SCB, SYST};
+
+        cortex_m::interrupt::free(|_| {
+            let nvic = unsafe { &*NVIC::PTR };
+            for ((icer, iser), saved) in nvic.icer.iter().zip(&nvic.iser).zip(self.iser) {
+                unsafe {
+                    icer.write(u32::MAX);
+                    iser.write(saved);
+                }
+            }
+
+            let syst = unsafe { &*SYST::PTR };
+            unsafe {
+                syst.rvr.write(self.syst_rvr);
+                syst.cvr.write(0);
+                syst.csr.write(self.syst_csr);
+            }
+
+            if is_armv7m() {
+                let scb = unsafe { &*SCB::PTR };
+                unsafe { scb.shcsr.modify(|shcsr| shcsr & !SHCSR_FAULTENA | self.shcsr) };
+            }
+            cortex_m::asm::dsb();
+            cortex_m::asm::isb();
+        });
+    }
+}
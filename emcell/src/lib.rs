@@ -1,4 +1,4 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 #[cfg(not(feature = "rt-crate-cortex-m-rt"))]
 compile_error!("This crate requires any rt-crate-* to be enabled (when using build-rs feature)! *currently only rt-crate-cortex-m-rt is supported*");
@@ -14,9 +14,26 @@ pub use build_rs::*;
 use core::sync::atomic::AtomicBool;
 
 pub mod meta;
+pub use meta::Slot;
+mod error;
+pub use error::CellError;
+pub mod integrity;
+pub use integrity::IntegrityCheck;
+pub mod rollback;
+pub use rollback::RollbackStore;
+pub mod boot;
+pub use boot::confirm_boot;
+pub mod retained;
+pub use emcell_macro::retained;
+#[cfg(feature = "update")]
+pub mod update;
+#[cfg(feature = "update")]
+pub mod protocol;
 
 #[cfg(not(feature = "build-rs"))]
 pub mod device;
+#[cfg(feature = "rt-crate-cortex-m-rt")]
+pub mod interrupts;
 
 #[derive(Copy, Clone)]
 pub enum CellType {
@@ -30,20 +47,314 @@ pub enum HeaderType {
     Dummy
 }
 
+/// # Safety
+/// Implemented by `emcell_configuration!` only.
 pub unsafe trait WithSignature {
     const VALID_SIGNATURE: u32;
 }
 
+/// # Safety
+/// Implemented by `emcell_configuration!` only. `Self` must be a `#[cell]` header struct.
 pub unsafe trait Cell: WithSignature {
     const CUR_META: meta::CellDefMeta;
     const CELLS_META: &'static [meta::CellDefMeta];
     const DEVICE_CONFIG: meta::DeviceConfigMeta;
-    fn check_signature(&self, init_memory: bool) -> bool;
+    /// Version block from the header of this cell
+    fn version(&self) -> &meta::CellVersion;
+    /// Image description from the header of this cell
+    fn image_info(&self) -> &meta::ImageInfo;
+    /// Anti-rollback counter from the header of this cell, see [`rollback`]
+    fn security_counter(&self) -> u32;
+    /// Slot, this cell image was linked for (`Slot as u32`)
+    fn linked_slot(&self) -> u32;
+    /// Compare signature and ABI of this header against the cells definitions, without calling into the cell
+    fn check_header(&self) -> Result<(), CellError>;
+    /// Call init function of the cell, which checks header hash on its side and optionally initializes cell memory
+    ///
+    /// # Safety
+    /// Header must be validated with [`Cell::check_header`] first
+    unsafe fn call_init(&self, init_memory: bool) -> Result<(), CellError>;
+    /// `#[switch_vectors]` function of this cell, entered after [`device::boot_cell`]
+    fn boot_entry(&self) -> Option<fn() -> !>;
+
+    /// Validate header of this cell and optionally initialize its memory
+    fn check(&self, init_memory: bool) -> Result<(), CellError> {
+        self.check_with(init_memory, &CheckPolicy::new())
+    }
+    /// Same as [`Cell::check`] with additional checks from `policy`, performed before any call into the cell
+    fn check_with(&self, init_memory: bool, policy: &CheckPolicy) -> Result<(), CellError> {
+        self.validate(policy)?;
+        unsafe { self.call_init(init_memory) }
+    }
+    /// All checks of [`Cell::check_with`], without calling into the cell
+    fn validate(&self, policy: &CheckPolicy) -> Result<(), CellError> {
+        self.check_header()?;
+        let slot = self.slot()?;
+        integrity::verify_image::<Self>(slot, self.image_info(), policy.integrity)?;
+        if Self::CUR_META.public_key.is_some() {
+            integrity::verify_signature::<Self>(slot, self.image_info())?;
+        }
+        if let Some(store) = policy.rollback {
+            rollback::check(self, store)?;
+        }
+        Ok(())
+    }
+    /// Slot, this header is located in. Fails if the image was linked for another slot
+    fn slot(&self) -> Result<Slot, CellError> {
+        let addr = self as *const Self as *const u8 as usize;
+        let linked = self.linked_slot();
+        match Self::CUR_META.slot_at(addr, &Self::DEVICE_CONFIG) {
+            Some(slot) if slot as u32 == linked => Ok(slot),
+            _ => Err(CellError::WrongSlot { linked }),
+        }
+    }
+    fn check_signature(&self, init_memory: bool) -> bool {
+        self.check(init_memory).is_ok()
+    }
     fn static_sha256(&self) -> [u8; 32] {
         Self::CUR_META.struct_sha256
     }
 }
 
+/// Additional checks of a cell, performed before the first call into it
+#[derive(Copy, Clone)]
+pub struct CheckPolicy<'a> {
+    pub integrity: IntegrityCheck,
+    pub rollback: Option<&'a dyn RollbackStore>,
+}
+
+impl<'a> CheckPolicy<'a> {
+    /// Check only signature and header hash
+    pub const fn new() -> Self {
+        Self {
+            integrity: IntegrityCheck::None,
+            rollback: None,
+        }
+    }
+
+    /// Verify the cell image against length and checksum from its header
+    pub const fn with_integrity(mut self, integrity: IntegrityCheck) -> Self {
+        self.integrity = integrity;
+        self
+    }
+
+    /// Reject cells with security counter lower than the minimum from `store`
+    pub const fn with_rollback(mut self, store: &'a dyn RollbackStore) -> Self {
+        self.rollback = Some(store);
+        self
+    }
+}
+
+impl Default for CheckPolicy<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Compare signature and ABI of a header, provided by other cell, against the cells definitions of `T`.
+pub fn check_header<T: Cell>(signature: u32, abi: &meta::HeaderAbi) -> Result<(), CellError> {
+    if signature == 0xFFFF_FFFF {
+        return Err(CellError::NotFlashed);
+    }
+    if signature != T::VALID_SIGNATURE {
+        return Err(CellError::BadSignature { found: signature });
+    }
+
+    let meta = &T::CUR_META;
+    // fingerprints are stored in the image, which can be linked for any slot of the cell
+    let provided = [Slot::A, Slot::B].into_iter()
+        .filter_map(|slot| meta.slot_flash_range(slot, &T::DEVICE_CONFIG))
+        .find_map(|(start, end)| abi.field_fingerprints(start..end));
+    let known = meta.field_fingerprints.iter().zip(meta.field_names).enumerate();
+
+    if abi.struct_sha256 == meta.struct_sha256 {
+        // required fields match, optional fields are allowed to be missing on either side
+        if meta.field_fingerprints.len() == meta.base_field_count {
+            return Ok(());
+        }
+        let Some(provided) = provided else {
+            return Err(CellError::HashMismatch { first_differing_field: None });
+        };
+        return match known.skip(meta.base_field_count)
+            .find(|(i, (fingerprint, _))| provided.get(*i).is_some_and(|p| p != *fingerprint)) {
+            Some((_, (_, name))) => Err(CellError::HashMismatch { first_differing_field: Some(name) }),
+            None => Ok(()),
+        };
+    }
+
+    let first_differing_field = provided.and_then(|provided| {
+        known.take(meta.base_field_count)
+            .find(|(i, (fingerprint, _))| provided.get(*i) != Some(*fingerprint))
+            .map(|(_, (_, name))| *name)
+    });
+    Err(CellError::HashMismatch { first_differing_field })
+}
+
+/// Header of cell `T` in `slot`
+///
+/// # Safety
+/// Header location is read as `T`, even if the slot is erased or contains something else. Check it before use
+pub unsafe fn slot_header<T: Cell>(slot: Slot) -> Result<&'static T, CellError> {
+    match T::CUR_META.partitioned_flash_in(slot, &T::DEVICE_CONFIG) {
+        Some(region) => Ok(unsafe { &*(region.start_header as *const T) }),
+        None => Err(CellError::NoSuchSlot),
+    }
+}
+
+/// Valid slot of cell `T` with the newest image: higher security counter, then higher version (with semver pre-release
+/// precedence, see [`meta::CellVersion::cmp_precedence`]), then later build. Slot A wins if both are equal. Returns the error of the last rejected slot if none is valid
+///
+/// # Safety
+/// Same as [`slot_header`]
+pub unsafe fn newest_slot<T: Cell>(policy: &CheckPolicy) -> Result<(&'static T, Slot), CellError> {
+    unsafe { newest_slot_where(policy, |_| true) }
+}
+
+/// Same as [`newest_slot`], but skips valid slots, which are not accepted by `accept`
+pub(crate) unsafe fn newest_slot_where<T: Cell>(policy: &CheckPolicy, accept: impl Fn(&T) -> bool) -> Result<(&'static T, Slot), CellError> {
+    let newer = |h: &T, other: &T| {
+        h.security_counter().cmp(&other.security_counter())
+            .then_with(|| h.version().cmp_precedence(other.version()))
+            .then_with(|| h.version().build_timestamp.cmp(&other.version().build_timestamp))
+            .is_gt()
+    };
+
+    let mut newest: Option<(&'static T, Slot)> = None;
+    let mut error = CellError::NoSuchSlot;
+    for slot in [Slot::A, Slot::B] {
+        let Ok(h) = (unsafe { slot_header::<T>(slot) }) else {
+            continue;
+        };
+        match h.validate(policy) {
+            Ok(()) if !accept(h) => error = CellError::NotConfirmed,
+            Ok(()) if newest.is_none_or(|(other, _)| newer(h, other)) => newest = Some((h, slot)),
+            Ok(()) => {}
+            Err(e) => error = e,
+        }
+    }
+    newest.ok_or(error)
+}
+
+/// Prepare the current cell for leaving it: run its `#[pre_switch]` hook and, unless it opted out,
+/// [`device::quiesce`]. Called by `switch_vectors_and_run` and [`device::boot_cell`]
+///
+/// # Safety
+/// Interrupts of the current cell are disabled, peripherals may be deinitialized by the hook
+pub unsafe fn pre_switch() {
+    extern "Rust" {
+        // defined by define_header! and define_primary_header!
+        fn _emcell_pre_switch();
+    }
+    unsafe { _emcell_pre_switch() }
+}
+
+/// Call `f` with interrupt vectors of another cell, switched by `switch_vectors`, and switch back after it returns.
+/// Used by `switch_vectors_and_call` of headers with a `#[switch_vectors]` field.
+///
+/// [`pre_switch`] runs first, as for `switch_vectors_and_run`. VTOR, MSP and interrupt configuration of the caller
+/// ([`interrupts::InterruptState`]: NVIC enables, SysTick, fault handlers) are saved and restored after `f` returns, even if
+/// the cell changed them. Interrupts, enabled by the cell, are disabled again. `f` runs on the own stack of the cell, if
+/// it has one (see [`call_on_stack`]).
+///
+/// Peripherals, deinitialized by the `#[pre_switch]` hook of the caller, are not restored.
+///
+/// # Safety
+/// `switch_vectors` must point VTOR to the vector table of the cell, `f` calls into.
+#[cfg(feature = "rt-crate-cortex-m-rt")]
+pub unsafe fn switch_vectors_and_call<R>(switch_vectors: unsafe fn(), stack: Option<(usize, usize)>, f: impl FnOnce() -> R) -> R {
+    let scb = unsafe { &*cortex_m::peripheral::SCB::PTR };
+    let vtor = scb.vtor.read();
+    let interrupts = interrupts::InterruptState::save();
+
+    unsafe { pre_switch() };
+    unsafe { switch_vectors() };
+
+    let result = unsafe { call_with_msp(stack_top(stack), f) };
+
+    cortex_m::interrupt::free(|_| unsafe {
+        scb.vtor.write(vtor);
+        cortex_m::asm::dsb();
+        cortex_m::asm::isb();
+    });
+    unsafe { interrupts.restore() };
+    result
+}
+
+/// Call `f` on the own stack `[start, end)` of a cell, see `#[stack(size)]`. MSP is set to the top of the stack and
+/// restored after `f` returns. If the stack is `None` or MSP is already inside of it (nested call into the same cell),
+/// `f` runs on the current stack.
+///
+/// # Safety
+/// Nothing else may use the stack, e.g. an interrupt handler of the cell running on MSP.
+#[cfg(feature = "rt-crate-cortex-m-rt")]
+pub unsafe fn call_on_stack<R>(stack: Option<(usize, usize)>, f: impl FnOnce() -> R) -> R {
+    unsafe { call_with_msp(stack_top(stack), f) }
+}
+
+/// Set MSP to the top of the own stack of a cell and jump to `run`, see `switch_vectors_and_run`
+///
+/// # Safety
+/// Current stack is abandoned, nothing on it may be used afterwards.
+#[cfg(feature = "rt-crate-cortex-m-rt")]
+pub unsafe fn run_on_stack(stack: (usize, usize), run: fn() -> !) -> ! {
+    unsafe { cortex_m::asm::bootstrap(stack.1 as *const u32, run as usize as *const u32) }
+}
+
+/// Top of `stack`, unless MSP is already inside of it
+#[cfg(feature = "rt-crate-cortex-m-rt")]
+fn stack_top(stack: Option<(usize, usize)>) -> Option<usize> {
+    let (start, end) = stack?;
+    let msp = cortex_m::register::msp::read() as usize;
+    (!(start..=end).contains(&msp)).then_some(end)
+}
+
+/// Call `f` with MSP set to `msp` (if any) and set MSP back to its value before the call
+#[cfg(feature = "rt-crate-cortex-m-rt")]
+unsafe fn call_with_msp<R>(msp: Option<usize>, f: impl FnOnce() -> R) -> R {
+    let mut f = Some(f);
+    let mut result = None;
+    unsafe { call_with_msp_dyn(msp, &mut || result = f.take().map(|f| f())) };
+    result.unwrap()
+}
+
+#[cfg(all(feature = "rt-crate-cortex-m-rt", target_arch = "arm"))]
+unsafe fn call_with_msp_dyn<F: FnMut()>(msp: Option<usize>, f: &mut F) {
+    unsafe extern "C" fn trampoline<F: FnMut()>(f: *mut F) {
+        unsafe { (*f)() }
+    }
+
+    // r4 is callee-saved, so it keeps MSP of the caller across the call
+    unsafe {
+        match msp {
+            Some(msp) => core::arch::asm!(
+                "mrs r4, MSP",
+                "msr MSP, {msp}",
+                "blx {trampoline}",
+                "msr MSP, r4",
+                msp = in(reg) msp,
+                trampoline = in(reg) trampoline::<F> as usize,
+                inout("r0") f as *mut F => _,
+                out("r4") _,
+                clobber_abi("C"),
+            ),
+            None => core::arch::asm!(
+                "mrs r4, MSP",
+                "blx {trampoline}",
+                "msr MSP, r4",
+                trampoline = in(reg) trampoline::<F> as usize,
+                inout("r0") f as *mut F => _,
+                out("r4") _,
+                clobber_abi("C"),
+            ),
+        }
+    }
+}
+
+#[cfg(all(feature = "rt-crate-cortex-m-rt", not(target_arch = "arm")))]
+unsafe fn call_with_msp_dyn<F: FnMut()>(_msp: Option<usize>, f: &mut F) {
+    f()
+}
+
 /// Safe cell header wrapper.
 /// If you create a CellWrapper with new_uninit, you should call ensure_init to handle
 pub struct CellWrapper<T, K>
@@ -93,6 +404,13 @@ impl<T> core::ops::Deref for CellWrapper<T, Backward>
 
 impl<T, K> CellWrapper<T, K>
     where T: Cell + 'static {
+    /// Version block of the wrapped cell. Does not trigger initialization of the cell
+    pub fn version(&self) -> &meta::CellVersion {
+        self.header.version()
+    }
+
+    /// # Safety
+    /// `h` must point to the header location of the cell. Used by generated wrappers only.
     pub const unsafe fn _new_uninit(h: &'static T) -> Self {
         Self {
             header: h,
@@ -115,12 +433,24 @@ impl<T, K> CellWrapper<T, K>
 
 impl<T> CellWrapper<T, Forward>
     where T: Cell + 'static {
+    /// # Safety
+    /// `h` must point to the header location of the cell. Used by generated wrappers only.
     pub unsafe fn _new_init(h: &'static T) -> Option<Self> {
-        if !h.check_signature(true) {
-            return None;
-        }
+        Self::_try_new_init(h).ok()
+    }
+
+    /// # Safety
+    /// `h` must point to the header location of the cell. Used by generated wrappers only.
+    pub unsafe fn _try_new_init(h: &'static T) -> Result<Self, CellError> {
+        Self::_try_new_init_with(h, &CheckPolicy::new())
+    }
+
+    /// # Safety
+    /// `h` must point to the header location of the cell. Used by generated wrappers only.
+    pub unsafe fn _try_new_init_with(h: &'static T, policy: &CheckPolicy) -> Result<Self, CellError> {
+        h.check_with(true, policy)?;
 
-        Some(Self {
+        Ok(Self {
             header: h,
             header_type: HeaderType::Actual,
             is_init: AtomicBool::new(true),
@@ -128,17 +458,36 @@ impl<T> CellWrapper<T, Forward>
         })
     }
 
+    /// Initialize the newest valid slot of the cell, see [`newest_slot`]
+    ///
+    /// # Safety
+    /// Used by generated wrappers only.
+    pub unsafe fn _try_new_newest_with(policy: &CheckPolicy) -> Result<(Self, Slot), CellError> {
+        let (h, slot) = unsafe { newest_slot::<T>(policy)? };
+        unsafe { h.call_init(true)? };
+
+        Ok((Self {
+            header: h,
+            header_type: HeaderType::Actual,
+            is_init: AtomicBool::new(true),
+            _phantom: PhantomData
+        }, slot))
+    }
+
     /// If header wrapper was created with new_uninit, this function must be called to potentially initialize other cell's memory.
     pub fn ensure_init(&self) -> Option<()> {
+        self.try_ensure_init().ok()
+    }
+
+    /// Same as [`Self::ensure_init`], but reports the reason of failure
+    pub fn try_ensure_init(&self) -> Result<(), CellError> {
         if self.is_init.load(core::sync::atomic::Ordering::Relaxed) {
-            return Some(());
+            return Ok(());
         }
         //init
-        if !self.header.check_signature(true) {
-            return None;
-        }
+        self.header.check(true)?;
         self.is_init.store(true, core::sync::atomic::Ordering::Relaxed);
-        Some(())
+        Ok(())
     }
 
     pub fn is_dummy(&self) -> bool {
@@ -150,12 +499,24 @@ impl<T> CellWrapper<T, Forward>
 
 impl<T> CellWrapper<T, Backward>
     where T: Cell + 'static {
+    /// # Safety
+    /// `h` must point to the header location of the cell. Used by generated wrappers only.
     pub unsafe fn _new_init(h: &'static T) -> Option<Self> {
-        if !h.check_signature(false) {
-            return None;
-        }
+        Self::_try_new_init(h).ok()
+    }
+
+    /// # Safety
+    /// `h` must point to the header location of the cell. Used by generated wrappers only.
+    pub unsafe fn _try_new_init(h: &'static T) -> Result<Self, CellError> {
+        Self::_try_new_init_with(h, &CheckPolicy::new())
+    }
+
+    /// # Safety
+    /// `h` must point to the header location of the cell. Used by generated wrappers only.
+    pub unsafe fn _try_new_init_with(h: &'static T, policy: &CheckPolicy) -> Result<Self, CellError> {
+        h.check_with(false, policy)?;
 
-        Some(Self {
+        Ok(Self {
             header: h,
             header_type: HeaderType::Actual,
             is_init: AtomicBool::new(true),
@@ -163,17 +524,36 @@ impl<T> CellWrapper<T, Backward>
         })
     }
 
+    /// Initialize the newest valid slot of the cell, see [`newest_slot`]
+    ///
+    /// # Safety
+    /// Used by generated wrappers only.
+    pub unsafe fn _try_new_newest_with(policy: &CheckPolicy) -> Result<(Self, Slot), CellError> {
+        let (h, slot) = unsafe { newest_slot::<T>(policy)? };
+        unsafe { h.call_init(false)? };
+
+        Ok((Self {
+            header: h,
+            header_type: HeaderType::Actual,
+            is_init: AtomicBool::new(true),
+            _phantom: PhantomData
+        }, slot))
+    }
+
     /// If header wrapper was created with new_uninit, this function must be called to potentially initialize other cell's memory.
     pub fn ensure_init(&self) -> Option<()> {
+        self.try_ensure_init().ok()
+    }
+
+    /// Same as [`Self::ensure_init`], but reports the reason of failure
+    pub fn try_ensure_init(&self) -> Result<(), CellError> {
         if self.is_init.load(core::sync::atomic::Ordering::Relaxed) {
-            return Some(());
+            return Ok(());
         }
         //init
-        if !self.header.check_signature(false) {
-            return None;
-        }
+        self.header.check(false)?;
         self.is_init.store(true, core::sync::atomic::Ordering::Relaxed);
-        Some(())
+        Ok(())
     }
 
     pub fn is_dummy(&self) -> bool {
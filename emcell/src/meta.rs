@@ -1,9 +1,17 @@
 use crate::CellType;
 
+/// Size of the flash area reserved for the cell header
+pub const HEADER_SIZE: usize = 1024;
+/// Size of the RAM area reserved for `emcell::boot::BootMailbox` at the start of `#[noinit_region]` of `device!`
+pub const BOOT_MAILBOX_SIZE: usize = 64;
+
 #[derive(Copy, Clone)]
 pub struct CellDefMeta {
     pub name: &'static str,
     pub cell_type: CellType,
+    /// Magic value of the first header word. Derived from the cell name only: stable across changes of the cells
+    /// definitions and unique only per cell name
+    pub signature: u32,
 
     pub ram_range_start_offs: usize,
     pub ram_range_end_offs: usize,
@@ -11,7 +19,100 @@ pub struct CellDefMeta {
     pub flash_range_start_offs: usize,
     pub flash_range_end_offs: usize,
 
+    /// Hash of the header fields, cell type, absolute cell regions, [`HEADER_SIZE`] and device configuration.
+    /// Cells built against different definitions refuse to talk to each other.
+    pub struct_sha256: [u8; 32],
+    /// Names of the header fields, in declaration order
+    pub field_names: &'static [&'static str],
+    /// Fingerprint of each header field (position, name and type), in declaration order
+    pub field_fingerprints: &'static [u32],
+    /// Number of required fields, covered by `struct_sha256`. Fields after them are optional (`#[since(N)]`)
+    pub base_field_count: usize,
+    /// Version of the latest optional field in this header, 1 if there are none
+    pub abi_minor: u32,
+    /// Ed25519 key, the cell image must be signed with, see `#[signed(public_key = "...")]`
+    pub public_key: Option<[u8; 32]>,
+    /// Second flash region of the cell, if it declares two `#[flash_region]`s. The first one is slot A
+    pub slot_b_flash_range_offs: Option<(usize, usize)>,
+    /// Size of the own stack of the cell at the top of its RAM region, see `#[stack(size)]`.
+    /// Cells without it run on the stack of the device
+    pub stack_size: Option<usize>,
+    /// Version of the cells definitions crate, the cell is declared in, with [`CellDefMeta::abi_minor`].
+    /// Version of the flashed cell itself is in its header, see [`crate::Cell::version`]
+    pub version: CellVersion,
+}
+
+/// Flash slot of a cell. Cells with two `#[flash_region]`s can be linked for either of them
+#[repr(u32)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Slot {
+    A = 0,
+    B = 1,
+}
+
+impl Slot {
+    /// Slot, selected with `EMCELL_SLOT` environment variable (`A` or `B`), `A` if it is not set
+    pub const fn from_env(value: Option<&str>) -> Slot {
+        match value {
+            None => Slot::A,
+            Some(value) => match value.as_bytes() {
+                b"A" | b"a" => Slot::A,
+                b"B" | b"b" => Slot::B,
+                _ => panic!("EMCELL_SLOT must be either A or B"),
+            },
+        }
+    }
+
+    pub const fn from_u32(value: u32) -> Option<Slot> {
+        match value {
+            0 => Some(Slot::A),
+            1 => Some(Slot::B),
+            _ => None,
+        }
+    }
+}
+
+/// ABI description, stored in every cell header right after the init function.
+///
+/// Allows the consumer to find out why the header of a peer cell is not compatible.
+#[repr(C)]
+pub struct HeaderAbi {
     pub struct_sha256: [u8; 32],
+    pub abi_minor: u32,
+    pub field_count: u32,
+    field_fingerprints: *const u32,
+}
+
+// field_fingerprints always points to an immutable array in flash
+unsafe impl Sync for HeaderAbi {}
+
+impl HeaderAbi {
+    pub const fn new(meta: &CellDefMeta) -> Self {
+        Self {
+            struct_sha256: meta.struct_sha256,
+            abi_minor: meta.abi_minor,
+            field_count: meta.field_fingerprints.len() as u32,
+            field_fingerprints: meta.field_fingerprints.as_ptr(),
+        }
+    }
+
+    /// Per-field fingerprints of the cell, which provided this header.
+    ///
+    /// Header can be written by a completely different firmware, so the table is only returned if it is located
+    /// inside `flash_range`.
+    pub fn field_fingerprints(&self, flash_range: core::ops::Range<usize>) -> Option<&[u32]> {
+        if self.field_count == 0 {
+            return Some(&[]);
+        }
+        let start = self.field_fingerprints as usize;
+        let end = start.checked_add(self.field_count as usize * core::mem::size_of::<u32>())?;
+        if !start.is_multiple_of(core::mem::align_of::<u32>()) || start < flash_range.start || end > flash_range.end {
+            return None;
+        }
+
+        Some(unsafe { core::slice::from_raw_parts(self.field_fingerprints, self.field_count as usize) })
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -21,6 +122,48 @@ pub struct DeviceConfigMeta {
     pub ram_range_end: usize,
     pub flash_range_start: usize,
     pub flash_range_end: usize,
+    /// `[start, end)` offsets of the RAM region for the boot mailbox and `#[emcell::retained]` statics, not initialized
+    /// on startup
+    pub noinit_range_offs: Option<(usize, usize)>,
+    pub stack_strategy: StackStrategy,
+    /// Absolute `[end, start)` of the stack, shared by cells without `#[stack(size)]`. It grows down from `start`
+    pub stack_range: (usize, usize),
+}
+
+/// Placement of the stacks, `stack_strategy` of `device!`
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum StackStrategy {
+    /// `global_bottom` (default): shared stack from `initial_stack_ptr` down to the start of device RAM, so an
+    /// overflow faults below RAM. Own stacks of cells are at the top of their RAM regions
+    GlobalBottom,
+    /// `global_top`: shared stack from `initial_stack_ptr` down to the end of the highest region below it, e.g.
+    /// `initial_stack_ptr = ram_range_end` as in plain cortex-m-rt. Own stacks of cells are at the top of their RAM
+    /// regions
+    GlobalTop,
+    /// `cell_bottom`: shared stack as with `global_bottom`, own stacks of cells are at the bottom of their RAM regions
+    /// with `.data` and `.bss` above them, so an overflow leaves the region instead of corrupting its data. It faults
+    /// only if nothing is mapped below: the start of device RAM, or an MPU guard declared with
+    /// `#[stack(size, guarded)]`
+    CellBottom,
+}
+
+impl DeviceConfigMeta {
+    /// Absolute `[start, end)` of the no-init RAM region, if it is configured
+    pub const fn absolute_noinit_range(&self) -> Option<(usize, usize)> {
+        match self.noinit_range_offs {
+            Some((start, end)) => Some((self.ram_range_start + start, self.ram_range_start + end)),
+            None => None,
+        }
+    }
+
+    /// Address of [`crate::boot::BootMailbox`], which keeps boot state across resets: the first [`BOOT_MAILBOX_SIZE`]
+    /// bytes of the no-init region. `None` if the region is not configured
+    pub const fn boot_mailbox(&self) -> Option<usize> {
+        match self.absolute_noinit_range() {
+            Some((start, _)) => Some(start),
+            None => None,
+        }
+    }
 }
 
 
@@ -36,7 +179,93 @@ impl<const N: usize> CellDefsMeta<N> {
     }
 }
 
+/// Cell flash region, split into the header and the rest of the cell image
+pub struct PartitionedFlashRegion {
+    pub start_flash: usize,
+    pub end_flash: usize,
+    pub start_header: usize,
+    pub end_header: usize,
+}
+
+impl PartitionedFlashRegion {
+    pub const fn new_header_first(cell: &CellDefMeta, device_config_meta: &DeviceConfigMeta) -> Self {
+        Self::header_first(cell.absolute_flash_start(device_config_meta), cell.absolute_flash_end(device_config_meta))
+    }
+
+    pub const fn new_header_last(cell: &CellDefMeta, device_config_meta: &DeviceConfigMeta) -> Self {
+        Self::header_last(cell.absolute_flash_start(device_config_meta), cell.absolute_flash_end(device_config_meta))
+    }
+
+    const fn header_first(start: usize, end: usize) -> Self {
+        let start_header = start;
+        let end_header = start_header + HEADER_SIZE;
+
+        let start_flash = end_header;
+        let end_flash = end;
+
+
+        Self {
+            start_flash,
+            end_flash,
+            start_header,
+            end_header,
+        }
+    }
+
+    const fn header_last(start: usize, end: usize) -> Self {
+        let start_flash = start;
+        let end_flash = end - HEADER_SIZE;
+
+        let start_header = end_flash;
+        let end_header = start_header + HEADER_SIZE;
+
+        Self {
+            start_flash,
+            end_flash,
+            start_header,
+            end_header,
+        }
+    }
+}
+
 impl CellDefMeta {
+    /// Primary cell keeps its header at the end of the region, so the vector table is at the start of flash.
+    /// Other cells keep header at the start of the region.
+    pub const fn partitioned_flash(&self, device_config_meta: &DeviceConfigMeta) -> PartitionedFlashRegion {
+        match self.cell_type {
+            CellType::Primary => PartitionedFlashRegion::new_header_last(self, device_config_meta),
+            CellType::NonPrimary => PartitionedFlashRegion::new_header_first(self, device_config_meta),
+        }
+    }
+
+    /// Same as [`Self::partitioned_flash`] for the given slot. `None` if the cell has no such slot
+    pub const fn partitioned_flash_in(&self, slot: Slot, device_config_meta: &DeviceConfigMeta) -> Option<PartitionedFlashRegion> {
+        let Some((start, end)) = self.slot_flash_range(slot, device_config_meta) else {
+            return None;
+        };
+        Some(match self.cell_type {
+            CellType::Primary => PartitionedFlashRegion::header_last(start, end),
+            CellType::NonPrimary => PartitionedFlashRegion::header_first(start, end),
+        })
+    }
+
+    /// Absolute flash region of the given slot
+    pub const fn slot_flash_range(&self, slot: Slot, device_config_meta: &DeviceConfigMeta) -> Option<(usize, usize)> {
+        match (slot, self.slot_b_flash_range_offs) {
+            (Slot::A, _) => Some((self.absolute_flash_start(device_config_meta), self.absolute_flash_end(device_config_meta))),
+            (Slot::B, Some((start, end))) => Some((device_config_meta.flash_range_start + start, device_config_meta.flash_range_start + end)),
+            (Slot::B, None) => None,
+        }
+    }
+
+    /// Slot, which has its header at `header_addr`
+    pub fn slot_at(&self, header_addr: usize, device_config_meta: &DeviceConfigMeta) -> Option<Slot> {
+        [Slot::A, Slot::B].into_iter().find(|slot| {
+            self.partitioned_flash_in(*slot, device_config_meta)
+                .is_some_and(|region| region.start_header == header_addr)
+        })
+    }
+
 
     pub const fn absolute_ram_start(&self, device_config_meta: &DeviceConfigMeta) -> usize {
         device_config_meta.ram_range_start + self.ram_range_start_offs
@@ -44,10 +273,312 @@ impl CellDefMeta {
     pub const fn absolute_ram_end(&self, device_config_meta: &DeviceConfigMeta) -> usize {
         device_config_meta.ram_range_start + self.ram_range_end_offs
     }
+    /// `[start, end)` of the own stack of the cell, if it has one. Its placement depends on [`StackStrategy`]
+    pub const fn absolute_stack_range(&self, device_config_meta: &DeviceConfigMeta) -> Option<(usize, usize)> {
+        let start = self.absolute_ram_start(device_config_meta);
+        let end = self.absolute_ram_end(device_config_meta);
+        match (self.stack_size, device_config_meta.stack_strategy) {
+            (Some(size), StackStrategy::CellBottom) => Some((start, start + size)),
+            (Some(size), _) => Some((end - size, end)),
+            (None, _) => None,
+        }
+    }
+    /// Start of the RAM for `.data` and `.bss` of the cell, above its own stack with [`StackStrategy::CellBottom`]
+    pub const fn absolute_data_ram_start(&self, device_config_meta: &DeviceConfigMeta) -> usize {
+        match (self.stack_size, device_config_meta.stack_strategy) {
+            (Some(size), StackStrategy::CellBottom) => self.absolute_ram_start(device_config_meta) + size,
+            _ => self.absolute_ram_start(device_config_meta),
+        }
+    }
+    /// End of the RAM for `.data` and `.bss` of the cell, below its own stack with other strategies
+    pub const fn absolute_data_ram_end(&self, device_config_meta: &DeviceConfigMeta) -> usize {
+        match (self.stack_size, device_config_meta.stack_strategy) {
+            (Some(_), StackStrategy::CellBottom) | (None, _) => self.absolute_ram_end(device_config_meta),
+            (Some(size), _) => self.absolute_ram_end(device_config_meta) - size,
+        }
+    }
     pub const fn absolute_flash_start(&self, device_config_meta: &DeviceConfigMeta) -> usize {
         device_config_meta.flash_range_start + self.flash_range_start_offs
     }
     pub const fn absolute_flash_end(&self, device_config_meta: &DeviceConfigMeta) -> usize {
         device_config_meta.flash_range_start + self.flash_range_end_offs
     }
-}
\ No newline at end of file
+}
+/// Offsets of the fields, inserted by `#[cell]` at the beginning of every header, on 32-bit targets.
+///
+/// Host tools use them to read headers from cell images. User fields start at [`header_layout::USER_FIELDS`].
+pub mod header_layout {
+    pub const SIGNATURE: usize = 0;
+    pub const INIT: usize = 4;
+
+    pub const ABI: usize = 8;
+    pub const ABI_STRUCT_SHA256: usize = ABI;
+    pub const ABI_MINOR: usize = ABI + 32;
+    pub const ABI_FIELD_COUNT: usize = ABI + 36;
+    pub const ABI_FIELD_FINGERPRINTS: usize = ABI + 40;
+
+    pub const VERSION: usize = ABI + 44;
+    pub const VERSION_CRATE_NAME: usize = VERSION;
+    pub const VERSION_MAJOR: usize = VERSION + 32;
+    pub const VERSION_MINOR: usize = VERSION + 34;
+    pub const VERSION_PATCH: usize = VERSION + 36;
+    pub const VERSION_PRE: usize = VERSION + 38;
+    pub const VERSION_GIT_REVISION: usize = VERSION + 56;
+    pub const VERSION_BUILD_TIMESTAMP: usize = VERSION + 80;
+    pub const VERSION_ABI_VERSION: usize = VERSION + 84;
+
+    pub const IMAGE: usize = VERSION + 88;
+    pub const IMAGE_LENGTH: usize = IMAGE;
+    pub const IMAGE_CRC32: usize = IMAGE + 4;
+    pub const IMAGE_SHA256: usize = IMAGE + 8;
+
+    pub const SECURITY_COUNTER: usize = IMAGE + 40;
+    pub const LINKED_SLOT: usize = SECURITY_COUNTER + 4;
+
+    pub const USER_FIELDS: usize = LINKED_SLOT + 4;
+
+    /// Offset of [`SignatureTrailer`](super::SignatureTrailer) from the start of the header region.
+    /// It is placed at the end of the region, after the header struct
+    pub const SIGNATURE_TRAILER: usize = super::HEADER_SIZE - super::SIGNATURE_SIZE;
+}
+
+#[cfg(target_pointer_width = "32")]
+const _: () = {
+    use core::mem::{offset_of, size_of};
+    use header_layout::*;
+
+    assert!(offset_of!(HeaderAbi, abi_minor) == ABI_MINOR - ABI);
+    assert!(offset_of!(HeaderAbi, field_count) == ABI_FIELD_COUNT - ABI);
+    assert!(offset_of!(HeaderAbi, field_fingerprints) == ABI_FIELD_FINGERPRINTS - ABI);
+    assert!(size_of::<HeaderAbi>() == VERSION - ABI);
+
+    assert!(offset_of!(CellVersion, major) == VERSION_MAJOR - VERSION);
+    assert!(offset_of!(CellVersion, minor) == VERSION_MINOR - VERSION);
+    assert!(offset_of!(CellVersion, patch) == VERSION_PATCH - VERSION);
+    assert!(offset_of!(CellVersion, pre) == VERSION_PRE - VERSION);
+    assert!(offset_of!(CellVersion, git_revision) == VERSION_GIT_REVISION - VERSION);
+    assert!(offset_of!(CellVersion, build_timestamp) == VERSION_BUILD_TIMESTAMP - VERSION);
+    assert!(offset_of!(CellVersion, abi_version) == VERSION_ABI_VERSION - VERSION);
+    assert!(size_of::<CellVersion>() == IMAGE - VERSION);
+
+    assert!(offset_of!(ImageInfo, crc32) == IMAGE_CRC32 - IMAGE);
+    assert!(offset_of!(ImageInfo, sha256) == IMAGE_SHA256 - IMAGE);
+    assert!(size_of::<ImageInfo>() == SECURITY_COUNTER - IMAGE);
+};
+
+pub const SIGNATURE_SIZE: usize = 64;
+
+/// Ed25519 signature of the cell, stored at the end of the header region.
+///
+/// Covers the header region up to the trailer and the image: `sha256(header_region[..SIGNATURE_TRAILER] || image)`
+/// is signed, so the image must be patched with its length first. Written by the host signing step.
+#[repr(C)]
+pub struct SignatureTrailer {
+    pub signature: [u8; SIGNATURE_SIZE],
+}
+
+impl SignatureTrailer {
+    /// Value before signing. Same as erased flash
+    pub const UNSIGNED: SignatureTrailer = SignatureTrailer {
+        signature: [0xFF; SIGNATURE_SIZE],
+    };
+
+    pub fn is_signed(&self) -> bool {
+        self.signature != Self::UNSIGNED.signature
+    }
+}
+
+/// Cell image description, stored in every cell header.
+///
+/// `define_header!` cannot know the final image, so these values are written into the linked image by a post-link step.
+/// Image covers the cell flash region without the header: `[start_flash, start_flash + length)`.
+#[repr(C)]
+pub struct ImageInfo {
+    pub length: u32,
+    /// CRC-32 (ISO-HDLC, as in zlib) of the image
+    pub crc32: u32,
+    pub sha256: [u8; 32],
+}
+
+impl ImageInfo {
+    /// Value before the post-link step. Same as erased flash, so it can be patched in the flashed image as well
+    pub const UNPATCHED: ImageInfo = ImageInfo {
+        length: 0xFFFF_FFFF,
+        crc32: 0xFFFF_FFFF,
+        sha256: [0xFF; 32],
+    };
+
+    pub fn is_patched(&self) -> bool {
+        self.length != Self::UNPATCHED.length
+    }
+}
+
+/// Version block, stored in every cell header.
+///
+/// Filled by `define_header!` from the cargo environment of the cell crate and from the values provided by `build_rs`.
+/// Strings are stored as zero-padded UTF-8, so host tools can read them right from the flashed image.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct CellVersion {
+    pub crate_name: [u8; 32],
+    pub major: u16,
+    pub minor: u16,
+    pub patch: u16,
+    /// Pre-release part of the crate version, without leading `-`
+    pub pre: [u8; 18],
+    /// Short git revision of the cell crate, `-dirty` suffixed if the working tree had changes
+    pub git_revision: [u8; 24],
+    /// Unix timestamp of the build, 0 if unknown
+    pub build_timestamp: u32,
+    /// ABI minor version of the header, see [`CellDefMeta::abi_minor`]
+    pub abi_version: u32,
+}
+
+impl CellVersion {
+    /// `version` is a semver string like `CARGO_PKG_VERSION`, build metadata after `+` is ignored.
+    ///
+    /// Version components above 65535 are rejected, at compile time in const context
+    pub const fn new(
+        crate_name: &str,
+        version: &str,
+        git_revision: Option<&str>,
+        build_timestamp: Option<&str>,
+        abi_version: u32
+    ) -> Self {
+        let git_revision = match git_revision {
+            Some(rev) => rev.as_bytes(),
+            None => &[],
+        };
+        let build_timestamp = match build_timestamp {
+            Some(ts) => parse_u32(ts.as_bytes(), 0).0,
+            None => 0,
+        };
+
+        let version = version.as_bytes();
+        let (major, i) = parse_u32(version, 0);
+        let (minor, i) = parse_u32(version, i + 1);
+        let (patch, i) = parse_u32(version, i + 1);
+        assert!(major <= u16::MAX as u32 && minor <= u16::MAX as u32 && patch <= u16::MAX as u32,
+                "crate version components must not exceed 65535");
+        // "-pre" part, up to "+build"
+        let mut pre = [0; 18];
+        if i < version.len() && version[i] == b'-' {
+            let mut j = 0;
+            while j < pre.len() && i + 1 + j < version.len() && version[i + 1 + j] != b'+' {
+                pre[j] = version[i + 1 + j];
+                j += 1;
+            }
+        }
+
+        Self {
+            crate_name: bytes_to_array(crate_name.as_bytes()),
+            major: major as u16,
+            minor: minor as u16,
+            patch: patch as u16,
+            pre,
+            git_revision: bytes_to_array(git_revision),
+            build_timestamp,
+            abi_version,
+        }
+    }
+
+    pub fn crate_name(&self) -> &str {
+        array_to_str(&self.crate_name)
+    }
+
+    pub fn pre(&self) -> Option<&str> {
+        Some(array_to_str(&self.pre)).filter(|pre| !pre.is_empty())
+    }
+
+    pub fn git_revision(&self) -> Option<&str> {
+        Some(array_to_str(&self.git_revision)).filter(|rev| !rev.is_empty())
+    }
+
+    /// Crate version as (major, minor, patch)
+    pub fn semver(&self) -> (u16, u16, u16) {
+        (self.major, self.minor, self.patch)
+    }
+
+    /// Semver precedence of the crate versions: (major, minor, patch), then a release is newer than its pre-releases,
+    /// which are compared by dot-separated identifiers (numeric ones numerically and below alphanumeric ones).
+    /// Pre-release tags longer than 18 bytes are compared truncated. Git revision and build timestamp are ignored
+    pub fn cmp_precedence(&self, other: &Self) -> core::cmp::Ordering {
+        use core::cmp::Ordering;
+
+        self.semver().cmp(&other.semver()).then_with(|| match (self.pre(), other.pre()) {
+            (None, None) => Ordering::Equal,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(_), None) => Ordering::Less,
+            (Some(pre), Some(other_pre)) => {
+                fn identifier(id: &str) -> Result<u64, &str> {
+                    match id.parse::<u64>() {
+                        Ok(number) if id.bytes().all(|b| b.is_ascii_digit()) => Ok(number),
+                        _ => Err(id),
+                    }
+                }
+                // numeric identifiers (Ok) have lower precedence than alphanumeric ones (Err)
+                pre.split('.').map(identifier).cmp(other_pre.split('.').map(identifier))
+            }
+        })
+    }
+}
+
+impl core::fmt::Display for CellVersion {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} {}.{}.{}", self.crate_name(), self.major, self.minor, self.patch)?;
+        if let Some(pre) = self.pre() {
+            write!(f, "-{}", pre)?;
+        }
+        if let Some(rev) = self.git_revision() {
+            write!(f, " ({})", rev)?;
+        }
+        write!(f, " abi {}", self.abi_version)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for CellVersion {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{=str} {}.{}.{}", self.crate_name(), self.major, self.minor, self.patch);
+        if let Some(pre) = self.pre() {
+            defmt::write!(f, "-{=str}", pre);
+        }
+        if let Some(rev) = self.git_revision() {
+            defmt::write!(f, " ({=str})", rev);
+        }
+        defmt::write!(f, " abi {}", self.abi_version);
+    }
+}
+
+/// Copy string into zero-padded array, truncating it if it does not fit
+const fn bytes_to_array<const N: usize>(bytes: &[u8]) -> [u8; N] {
+    let mut res = [0; N];
+    let mut i = 0;
+    while i < N && i < bytes.len() {
+        res[i] = bytes[i];
+        i += 1;
+    }
+    res
+}
+
+fn array_to_str(arr: &[u8]) -> &str {
+    let len = arr.iter().position(|b| *b == 0).unwrap_or(arr.len());
+    // string could be truncated in the middle of a character
+    match core::str::from_utf8(&arr[..len]) {
+        Ok(s) => s,
+        Err(e) => core::str::from_utf8(&arr[..e.valid_up_to()]).unwrap_or(""),
+    }
+}
+
+/// Parse decimal number starting at `start`, stopping at the first non-digit character.
+///
+/// Returns the number and index of the first character after it
+const fn parse_u32(bytes: &[u8], start: usize) -> (u32, usize) {
+    let mut res: u32 = 0;
+    let mut i = start;
+    while i < bytes.len() && bytes[i].is_ascii_digit() {
+        res = res.saturating_mul(10).saturating_add((bytes[i] - b'0') as u32);
+        i += 1;
+    }
+    (res, i)
+}
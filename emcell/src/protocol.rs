@@ -0,0 +1,469 @@
+//! Framed protocol for updating a cell over any byte transport (UART, USB CDC, RTT...).
+//!
+//! Every frame is `MAGIC, kind, length (u16 LE), payload, CRC-32 (LE) of kind, length and payload`. The host sends one
+//! request at a time and waits for a [`Status`] frame in response:
+//!
+//! - [`Kind::Query`]: no payload, reports the current state
+//! - [`Kind::Begin`]: slot (`Slot as u8`), image length (u32 LE) and cell name. Erases the slot
+//! - [`Kind::Data`]: offset of the chunk in the image (u32 LE) and up to [`MAX_DATA`] bytes of it
+//! - [`Kind::End`]: no payload, verifies the image and makes it bootable, see [`crate::update::Update::finish`]
+//!
+//! Data chunks must be sent in order. Repeating the last chunk is harmless, so the host may resend a chunk, when its
+//! response was lost. [`UpdateServer`] is the device side, `emcell-cli` contains the host client.
+
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::integrity::Crc32;
+use crate::meta::{CellDefMeta, DeviceConfigMeta};
+use crate::update::{UpdateError, Writer};
+use crate::{Cell, CellError, Slot};
+
+/// First byte of every frame
+pub const MAGIC: u8 = 0xEC;
+/// Largest image chunk in a [`Kind::Data`] frame
+pub const MAX_DATA: usize = 256;
+/// Largest payload of a frame
+pub const MAX_PAYLOAD: usize = MAX_DATA + 4;
+/// Largest encoded frame
+pub const MAX_FRAME: usize = 4 + MAX_PAYLOAD + 4;
+
+/// Frame kind
+#[repr(u8)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Kind {
+    Query = 0x01,
+    Begin = 0x02,
+    Data = 0x03,
+    End = 0x04,
+    /// Response of the device to any request
+    Status = 0x80,
+}
+
+impl Kind {
+    pub fn from_u8(value: u8) -> Option<Kind> {
+        Some(match value {
+            0x01 => Kind::Query,
+            0x02 => Kind::Begin,
+            0x03 => Kind::Data,
+            0x04 => Kind::End,
+            0x80 => Kind::Status,
+            _ => return None,
+        })
+    }
+}
+
+/// Decoded frame
+#[derive(Clone)]
+pub struct Frame {
+    pub kind: u8,
+    len: usize,
+    payload: [u8; MAX_PAYLOAD],
+}
+
+impl Frame {
+    pub fn payload(&self) -> &[u8] {
+        &self.payload[..self.len]
+    }
+}
+
+/// Reason why a received frame was dropped
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FrameError {
+    /// CRC of the frame does not match its contents
+    Crc,
+    /// Frame length is larger than [`MAX_PAYLOAD`]
+    TooLong,
+}
+
+/// Encode a frame into `out`, which must hold at least `payload.len() + 8` bytes. Returns the frame length
+pub fn encode_frame(kind: u8, payload: &[u8], out: &mut [u8]) -> usize {
+    assert!(payload.len() <= MAX_PAYLOAD);
+    let len = payload.len() as u16;
+    out[0] = MAGIC;
+    out[1] = kind;
+    out[2..4].copy_from_slice(&len.to_le_bytes());
+    out[4..4 + payload.len()].copy_from_slice(payload);
+
+    let mut crc = Crc32::new();
+    crc.update(&out[1..4 + payload.len()]);
+    out[4 + payload.len()..8 + payload.len()].copy_from_slice(&crc.finalize().to_le_bytes());
+    8 + payload.len()
+}
+
+/// Byte by byte frame decoder. Skips everything until [`MAGIC`], so it resynchronizes after a broken frame
+pub struct FrameDecoder {
+    buf: [u8; MAX_FRAME],
+    len: usize,
+}
+
+impl FrameDecoder {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; MAX_FRAME],
+            len: 0,
+        }
+    }
+
+    /// Feed next received byte. Returns a frame, once it is complete
+    pub fn push(&mut self, byte: u8) -> Option<Result<Frame, FrameError>> {
+        if self.len == 0 && byte != MAGIC {
+            return None;
+        }
+        self.buf[self.len] = byte;
+        self.len += 1;
+        if self.len < 4 {
+            return None;
+        }
+
+        let payload_len = u16::from_le_bytes([self.buf[2], self.buf[3]]) as usize;
+        if payload_len > MAX_PAYLOAD {
+            self.len = 0;
+            return Some(Err(FrameError::TooLong));
+        }
+        if self.len < 8 + payload_len {
+            return None;
+        }
+        self.len = 0;
+
+        let mut crc = Crc32::new();
+        crc.update(&self.buf[1..4 + payload_len]);
+        if crc.finalize().to_le_bytes() != self.buf[4 + payload_len..8 + payload_len] {
+            return Some(Err(FrameError::Crc));
+        }
+
+        let mut payload = [0; MAX_PAYLOAD];
+        payload[..payload_len].copy_from_slice(&self.buf[4..4 + payload_len]);
+        Some(Ok(Frame {
+            kind: self.buf[1],
+            len: payload_len,
+            payload,
+        }))
+    }
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// State of the update on the device
+#[repr(u8)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum State {
+    /// No update was started
+    Idle = 0,
+    /// Image is being received
+    Receiving = 1,
+    /// Image was verified and is bootable
+    Done = 2,
+    /// Last update failed, the slot is not bootable
+    Failed = 3,
+}
+
+/// Result of a request
+#[repr(u8)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ResultCode {
+    Ok = 0,
+    /// Request frame was broken, it may be resent
+    BadFrame = 1,
+    /// Unknown request kind or malformed payload
+    BadRequest = 2,
+    /// Cell name from [`Kind::Begin`] is not present in the cells definitions
+    UnknownCell = 3,
+    /// Data or end request without an update in progress
+    NotStarted = 4,
+    /// Data chunk does not continue the image, `written` of the status tells the expected offset
+    OutOfOrder = 5,
+    /// End request before the whole image was received
+    Incomplete = 6,
+    /// Image does not fit into the slot or the flash
+    OutOfRegion = 7,
+    /// Target slot belongs to the running cell
+    RunningCell = 8,
+    /// Slot is not aligned to the flash erase sectors or flash block size is not supported
+    UnsupportedFlash = 9,
+    /// Flash driver error
+    FlashError = 10,
+    /// Cell has no such slot
+    NoSuchSlot = 11,
+    /// Image header does not belong to the cell or was built from other cells definitions
+    BadHeader = 12,
+    /// Image was linked for another slot
+    WrongSlot = 13,
+    /// Image length and checksums are not filled in the header
+    ImageNotPatched = 14,
+    /// Image checksum does not match the header
+    ImageCorrupted = 15,
+    /// Cell must be signed, but the image is not
+    ImageNotSigned = 16,
+    /// Image signature does not match the public key of the cell
+    ImageSignatureInvalid = 17,
+}
+
+impl ResultCode {
+    pub fn from_u8(value: u8) -> Option<ResultCode> {
+        use ResultCode::*;
+        [Ok, BadFrame, BadRequest, UnknownCell, NotStarted, OutOfOrder, Incomplete, OutOfRegion, RunningCell,
+            UnsupportedFlash, FlashError, NoSuchSlot, BadHeader, WrongSlot, ImageNotPatched, ImageCorrupted,
+            ImageNotSigned, ImageSignatureInvalid]
+            .into_iter()
+            .find(|code| *code as u8 == value)
+    }
+
+    fn from_update_error<E>(e: &UpdateError<E>) -> ResultCode {
+        match e {
+            UpdateError::Flash(_) => ResultCode::FlashError,
+            UpdateError::RunningCell => ResultCode::RunningCell,
+            UpdateError::SlotNotAligned | UpdateError::UnsupportedBlockSize => ResultCode::UnsupportedFlash,
+            UpdateError::OutOfRegion { .. } => ResultCode::OutOfRegion,
+            UpdateError::Rejected(e) => match e {
+                CellError::NoSuchSlot => ResultCode::NoSuchSlot,
+                CellError::WrongSlot { .. } => ResultCode::WrongSlot,
+                CellError::ImageNotPatched => ResultCode::ImageNotPatched,
+                CellError::ImageLengthInvalid { .. } | CellError::ImageCorrupted => ResultCode::ImageCorrupted,
+                CellError::ImageNotSigned => ResultCode::ImageNotSigned,
+                CellError::ImageSignatureInvalid => ResultCode::ImageSignatureInvalid,
+                _ => ResultCode::BadHeader,
+            },
+        }
+    }
+}
+
+impl core::fmt::Display for ResultCode {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let text = match self {
+            ResultCode::Ok => "ok",
+            ResultCode::BadFrame => "broken frame",
+            ResultCode::BadRequest => "bad request",
+            ResultCode::UnknownCell => "unknown cell",
+            ResultCode::NotStarted => "no update in progress",
+            ResultCode::OutOfOrder => "data chunk out of order",
+            ResultCode::Incomplete => "image is incomplete",
+            ResultCode::OutOfRegion => "image does not fit into the slot",
+            ResultCode::RunningCell => "cannot update the running cell",
+            ResultCode::UnsupportedFlash => "slot or flash block size is not supported",
+            ResultCode::FlashError => "flash error",
+            ResultCode::NoSuchSlot => "cell has no such slot",
+            ResultCode::BadHeader => "image header does not match cells definitions",
+            ResultCode::WrongSlot => "image is linked for another slot",
+            ResultCode::ImageNotPatched => "image length and checksum are missing in the header",
+            ResultCode::ImageCorrupted => "image checksum mismatch",
+            ResultCode::ImageNotSigned => "image is not signed",
+            ResultCode::ImageSignatureInvalid => "invalid image signature",
+        };
+        f.write_str(text)
+    }
+}
+
+/// Payload of a [`Kind::Status`] frame
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Status {
+    /// Kind of the request, this status responds to
+    pub request: u8,
+    pub result: ResultCode,
+    pub state: State,
+    /// Number of image bytes received so far
+    pub written: u32,
+}
+
+impl Status {
+    pub const SIZE: usize = 8;
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut res = [0; Self::SIZE];
+        res[0] = self.request;
+        res[1] = self.result as u8;
+        res[2] = self.state as u8;
+        res[4..8].copy_from_slice(&self.written.to_le_bytes());
+        res
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Status> {
+        if bytes.len() != Self::SIZE {
+            return None;
+        }
+        let state = match bytes[2] {
+            0 => State::Idle,
+            1 => State::Receiving,
+            2 => State::Done,
+            3 => State::Failed,
+            _ => return None,
+        };
+        Some(Status {
+            request: bytes[0],
+            result: ResultCode::from_u8(bytes[1])?,
+            state,
+            written: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+        })
+    }
+}
+
+struct Session<'a> {
+    writer: Writer<'a>,
+    length: usize,
+}
+
+/// Device side of the protocol: receives an image of a cell and writes it with [`crate::update`]
+pub struct UpdateServer<'a, F: NorFlash> {
+    flash: &'a mut F,
+    cells: &'a [CellDefMeta],
+    device: DeviceConfigMeta,
+    decoder: FrameDecoder,
+    session: Option<Session<'a>>,
+    state: State,
+    /// Bytes received in the last update
+    written: usize,
+}
+
+impl<'a, F: NorFlash> UpdateServer<'a, F> {
+    /// Accept images of any of `cells`
+    pub fn new(flash: &'a mut F, cells: &'a [CellDefMeta], device: DeviceConfigMeta) -> Self {
+        Self {
+            flash,
+            cells,
+            device,
+            decoder: FrameDecoder::new(),
+            session: None,
+            state: State::Idle,
+            written: 0,
+        }
+    }
+
+    /// Accept images of any cell from the cells definitions of `T`
+    pub fn for_cells_of<T: Cell>(flash: &'a mut F) -> Self {
+        Self::new(flash, T::CELLS_META, T::DEVICE_CONFIG)
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Process bytes received from the transport. `respond` is called with every encoded response frame
+    pub fn receive(&mut self, data: &[u8], mut respond: impl FnMut(&[u8])) {
+        for byte in data {
+            let Some(frame) = self.decoder.push(*byte) else {
+                continue;
+            };
+            let status = match frame {
+                Ok(frame) => self.handle(&frame),
+                Err(_) => self.status(0, ResultCode::BadFrame),
+            };
+
+            let mut out = [0; 8 + Status::SIZE];
+            let len = encode_frame(Kind::Status as u8, &status.to_bytes(), &mut out);
+            respond(&out[..len]);
+        }
+    }
+
+    fn status(&self, request: u8, result: ResultCode) -> Status {
+        Status {
+            request,
+            result,
+            state: self.state,
+            written: self.written as u32,
+        }
+    }
+
+    fn handle(&mut self, frame: &Frame) -> Status {
+        let payload = frame.payload();
+        let result = match Kind::from_u8(frame.kind) {
+            Some(Kind::Query) => ResultCode::Ok,
+            Some(Kind::Begin) => self.begin(payload),
+            Some(Kind::Data) => self.data(payload),
+            Some(Kind::End) => self.end(),
+            _ => ResultCode::BadRequest,
+        };
+        self.status(frame.kind, result)
+    }
+
+    fn begin(&mut self, payload: &[u8]) -> ResultCode {
+        if payload.len() < 5 {
+            return ResultCode::BadRequest;
+        }
+        let Some(slot) = Slot::from_u32(payload[0] as u32) else {
+            return ResultCode::NoSuchSlot;
+        };
+        let length = u32::from_le_bytes(payload[1..5].try_into().unwrap()) as usize;
+        let Some(cell) = self.cells.iter().find(|cell| cell.name.as_bytes() == &payload[5..]) else {
+            return ResultCode::UnknownCell;
+        };
+
+        self.session = None;
+        self.written = 0;
+        self.state = State::Failed;
+        match Writer::begin(self.flash, cell, &self.device, slot) {
+            Ok(writer) => {
+                self.session = Some(Session { writer, length });
+                self.state = State::Receiving;
+                ResultCode::Ok
+            }
+            Err(e) => ResultCode::from_update_error(&e),
+        }
+    }
+
+    fn data(&mut self, payload: &[u8]) -> ResultCode {
+        if payload.len() < 4 {
+            return ResultCode::BadRequest;
+        }
+        let Some(session) = &mut self.session else {
+            return ResultCode::NotStarted;
+        };
+        let offset = u32::from_le_bytes(payload[..4].try_into().unwrap()) as usize;
+        let chunk = &payload[4..];
+
+        // repeated last chunk, its response was lost
+        if offset + chunk.len() == self.written && offset < self.written {
+            return ResultCode::Ok;
+        }
+        if offset != self.written {
+            return ResultCode::OutOfOrder;
+        }
+        if offset + chunk.len() > session.length {
+            return ResultCode::OutOfRegion;
+        }
+
+        match session.writer.write(self.flash, chunk) {
+            Ok(()) => {
+                self.written = session.writer.written();
+                ResultCode::Ok
+            }
+            Err(e) => {
+                self.session = None;
+                self.state = State::Failed;
+                ResultCode::from_update_error(&e)
+            }
+        }
+    }
+
+    fn end(&mut self) -> ResultCode {
+        let Some(session) = &mut self.session else {
+            return match self.state {
+                State::Done => ResultCode::Ok,
+                _ => ResultCode::NotStarted,
+            };
+        };
+        if self.written != session.length {
+            return ResultCode::Incomplete;
+        }
+
+        let result = session.writer.finish(self.flash);
+        self.session = None;
+        match result {
+            Ok(()) => {
+                self.state = State::Done;
+                ResultCode::Ok
+            }
+            Err(e) => {
+                self.state = State::Failed;
+                ResultCode::from_update_error(&e)
+            }
+        }
+    }
+}
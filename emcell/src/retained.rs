@@ -0,0 +1,86 @@
+//! Statics, which survive resets and vector switches.
+//!
+//! `#[emcell::retained]` places a static into the `.emcell.noinit` section, which `build_rs` maps to
+//! `#[noinit_region(start, end)]` of `device!` in every cell, right after the [boot mailbox](crate::boot::BootMailbox).
+//! This region is not touched on startup and not included in any cell RAM region, so neither the runtime nor
+//! [`crate::device::init_memory`] initialize it:
+//!
+//! ```ignore
+//! #[emcell::retained]
+//! pub static RESET_REASON: u32 = 0;
+//!
+//! RESET_REASON.set(0xDEAD);
+//! // after reset
+//! let reason = RESET_REASON.get();
+//! ```
+//!
+//! Statics are laid out by name, so cells see the same statics at the same addresses if they declare the same set,
+//! e.g. in the cells definitions crate. Each static is tagged with a hash of its name and type: after power-on, or if
+//! another cell put something else at its address, the initial value is returned.
+
+use core::mem::MaybeUninit;
+
+/// Contents of a retained static in the no-init region
+#[doc(hidden)]
+#[repr(C)]
+pub struct Storage<T> {
+    tag: u32,
+    value: MaybeUninit<T>,
+}
+
+impl<T> Storage<T> {
+    /// Value in the object file only, the section is not loaded
+    pub const fn uninit() -> Self {
+        Self {
+            tag: 0,
+            value: MaybeUninit::uninit(),
+        }
+    }
+}
+
+/// Static, declared with `#[emcell::retained]`, see [module docs](self).
+///
+/// Accesses are volatile, but not atomic: a value, shared with interrupt handlers, must be accessed in a critical
+/// section.
+pub struct Retained<T: Copy + 'static> {
+    storage: *mut Storage<T>,
+    init: T,
+    tag: u32,
+}
+
+// single core, see the note about interrupts above
+unsafe impl<T: Copy + Send + 'static> Sync for Retained<T> {}
+
+impl<T: Copy + 'static> Retained<T> {
+    #[doc(hidden)]
+    /// # Safety
+    /// `storage` must be located in the no-init region and used only by this `Retained`
+    pub const unsafe fn _new(storage: *mut Storage<T>, init: T, tag: u32) -> Self {
+        Self { storage, init, tag }
+    }
+
+    /// Whether the value was stored by [`Retained::set`] before, i.e. survived the last reset
+    pub fn is_retained(&self) -> bool {
+        unsafe { core::ptr::addr_of!((*self.storage).tag).read_volatile() == self.tag }
+    }
+
+    /// Stored value, or the initial value, if nothing was stored
+    pub fn get(&self) -> T {
+        if !self.is_retained() {
+            return self.init;
+        }
+        unsafe { core::ptr::addr_of!((*self.storage).value).read_volatile().assume_init() }
+    }
+
+    pub fn set(&self, value: T) {
+        unsafe {
+            core::ptr::addr_of_mut!((*self.storage).value).write_volatile(MaybeUninit::new(value));
+            core::ptr::addr_of_mut!((*self.storage).tag).write_volatile(self.tag);
+        }
+    }
+
+    /// Forget the stored value, [`Retained::get`] returns the initial value again
+    pub fn reset(&self) {
+        unsafe { core::ptr::addr_of_mut!((*self.storage).tag).write_volatile(0) };
+    }
+}
@@ -0,0 +1,77 @@
+use crate::{Cell, CellError};
+
+/// Storage of the minimum accepted security counter of each cell.
+///
+/// Must survive reflashing of the cells, e.g. a dedicated flash page, OTP or backup registers.
+pub trait RollbackStore {
+    /// Minimum accepted security counter of the cell, 0 if it was never raised
+    fn min_counter(&self, cell: &str) -> u32;
+    /// Persist a new minimum. Called only with values larger than the current minimum
+    fn set_min_counter(&mut self, cell: &'static str, counter: u32) -> Result<(), RollbackStoreError>;
+}
+
+/// New minimum counter could not be stored
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RollbackStoreError;
+
+/// Reject the cell, if its security counter is lower than the minimum from `store`
+pub fn check<T: Cell + ?Sized>(header: &T, store: &dyn RollbackStore) -> Result<(), CellError> {
+    let counter = header.security_counter();
+    let min_counter = store.min_counter(T::CUR_META.name);
+    if counter < min_counter {
+        return Err(CellError::RolledBack { counter, min_counter });
+    }
+    Ok(())
+}
+
+/// Raise the minimum accepted counter to the one of the running cell, so older images are rejected from now on.
+///
+/// Call it once the new image is known to work, otherwise a faulty update cannot be rolled back.
+pub fn commit<T: Cell + ?Sized>(header: &T, store: &mut dyn RollbackStore) -> Result<(), RollbackStoreError> {
+    let counter = header.security_counter();
+    if counter > store.min_counter(T::CUR_META.name) {
+        store.set_min_counter(T::CUR_META.name, counter)?;
+    }
+    Ok(())
+}
+
+/// Volatile [`RollbackStore`] for up to `N` cells. Useful for tests and as a cache in front of a persistent store
+pub struct MemoryRollbackStore<const N: usize> {
+    entries: [(&'static str, u32); N],
+    len: usize,
+}
+
+impl<const N: usize> MemoryRollbackStore<N> {
+    pub const fn new() -> Self {
+        Self {
+            entries: [("", 0); N],
+            len: 0,
+        }
+    }
+}
+
+impl<const N: usize> Default for MemoryRollbackStore<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> RollbackStore for MemoryRollbackStore<N> {
+    fn min_counter(&self, cell: &str) -> u32 {
+        self.entries[..self.len].iter()
+            .find(|(name, _)| *name == cell)
+            .map_or(0, |(_, counter)| *counter)
+    }
+
+    fn set_min_counter(&mut self, cell: &'static str, counter: u32) -> Result<(), RollbackStoreError> {
+        if let Some(entry) = self.entries[..self.len].iter_mut().find(|(name, _)| *name == cell) {
+            entry.1 = counter;
+            return Ok(());
+        }
+        let entry = self.entries.get_mut(self.len).ok_or(RollbackStoreError)?;
+        *entry = (cell, counter);
+        self.len += 1;
+        Ok(())
+    }
+}
@@ -0,0 +1,433 @@
+//! In-field update of a cell through an [`embedded_storage`] NOR flash driver.
+//!
+//! The new image is streamed into one slot of the target cell, as produced by `emcell patch --bin` or
+//! `emcell sign --bin`: contents of the slot flash region, starting from its first byte. The first word of the header
+//! (its signature) is held back and written only after the whole image is verified, so an interrupted update leaves
+//! the slot rejected as [`CellError::NotFlashed`] instead of half-written.
+//!
+//! Flash offsets of the driver are counted from `flash_range_start` of the device configuration.
+
+use embedded_storage::nor_flash::{ErrorType, NorFlash, NorFlashErrorKind, ReadNorFlash};
+
+use crate::integrity::Crc32;
+use crate::meta::{header_layout, CellDefMeta, DeviceConfigMeta, PartitionedFlashRegion, SignatureTrailer};
+use crate::{CellError, Slot};
+
+/// Largest supported `READ_SIZE` and `WRITE_SIZE` of the flash driver
+pub const MAX_BLOCK_SIZE: usize = 64;
+
+const ERASED: u8 = 0xFF;
+
+/// Reason why an update was refused or failed
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UpdateError<E> {
+    /// Flash driver error
+    Flash(E),
+    /// Target slot contains the code, which performs the update
+    RunningCell,
+    /// Slot is not aligned to the erase sectors of the flash (or to [`MAX_BLOCK_SIZE`]), erasing it would destroy its
+    /// neighbours
+    SlotNotAligned,
+    /// Read or write size of the flash is larger than [`MAX_BLOCK_SIZE`] or does not divide it
+    UnsupportedBlockSize,
+    /// Written image does not fit into the slot or the flash
+    OutOfRegion {
+        offset: usize,
+        len: usize,
+    },
+    /// Written image is not a valid image of the target cell
+    Rejected(CellError),
+}
+
+impl<E> From<CellError> for UpdateError<E> {
+    fn from(e: CellError) -> Self {
+        UpdateError::Rejected(e)
+    }
+}
+
+impl<E: core::fmt::Debug> core::fmt::Display for UpdateError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            UpdateError::Flash(e) => write!(f, "flash error: {:?}", e),
+            UpdateError::RunningCell => write!(f, "cannot update the running cell"),
+            UpdateError::SlotNotAligned => write!(f, "slot is not aligned to flash erase sectors"),
+            UpdateError::UnsupportedBlockSize => write!(f, "unsupported flash read or write size"),
+            UpdateError::OutOfRegion { offset, len } => write!(f, "{} bytes at offset {} do not fit into the slot", len, offset),
+            UpdateError::Rejected(e) => write!(f, "image rejected: {}", e),
+        }
+    }
+}
+
+/// Update of one slot of a cell, in progress
+pub struct Update<'a, F: NorFlash> {
+    flash: &'a mut F,
+    writer: Writer<'a>,
+}
+
+impl<'a, F: NorFlash> Update<'a, F> {
+    /// Erase `slot` of `cell` and start writing a new image into it.
+    ///
+    /// Fails without touching the flash, if the slot contains the code of the caller or is not aligned to erase sectors.
+    pub fn begin(flash: &'a mut F, cell: &'a CellDefMeta, device: &DeviceConfigMeta, slot: Slot) -> Result<Self, UpdateError<F::Error>> {
+        let writer = Writer::begin(flash, cell, device, slot)?;
+        Ok(Self { flash, writer })
+    }
+
+    /// Number of image bytes written so far
+    pub fn written(&self) -> usize {
+        self.writer.written()
+    }
+
+    /// Append next chunk of the image. Chunks may have any size
+    pub fn write(&mut self, data: &[u8]) -> Result<(), UpdateError<F::Error>> {
+        self.writer.write(self.flash, data)
+    }
+
+    /// Verify the written image and make it bootable by writing its header signature.
+    ///
+    /// Checks header signature, header hash, slot, image CRC-32 (and SHA-256 with `sha256` feature) and the Ed25519
+    /// signature, if the cell requires one. On failure the slot stays unbootable.
+    pub fn finish(mut self) -> Result<(), UpdateError<F::Error>> {
+        self.writer.finish(self.flash)
+    }
+}
+
+/// State of an update, which does not own the flash driver
+pub(crate) struct Writer<'a> {
+    cell: &'a CellDefMeta,
+    slot: Slot,
+    /// Driver offset of the slot start
+    base: usize,
+    region: PartitionedFlashRegion,
+    /// Number of bytes of the image, passed to the flash
+    programmed: usize,
+    /// Image bytes, not forming a full write block yet
+    pending: [u8; MAX_BLOCK_SIZE],
+    pending_len: usize,
+    /// First write block of the header, with the header signature
+    held: [u8; MAX_BLOCK_SIZE],
+}
+
+impl<'a> Writer<'a> {
+    pub(crate) fn begin<F: NorFlash>(flash: &mut F, cell: &'a CellDefMeta, device: &DeviceConfigMeta, slot: Slot) -> Result<Self, UpdateError<F::Error>> {
+        let (start, end) = cell.slot_flash_range(slot, device).ok_or(CellError::NoSuchSlot)?;
+        let region = cell.partitioned_flash_in(slot, device).ok_or(CellError::NoSuchSlot)?;
+
+        if (start..end).contains(&running_code_address()) {
+            return Err(UpdateError::RunningCell);
+        }
+        if F::WRITE_SIZE > MAX_BLOCK_SIZE || !MAX_BLOCK_SIZE.is_multiple_of(F::WRITE_SIZE)
+            || F::READ_SIZE > MAX_BLOCK_SIZE || !MAX_BLOCK_SIZE.is_multiple_of(F::READ_SIZE) {
+            return Err(UpdateError::UnsupportedBlockSize);
+        }
+        let base = start - device.flash_range_start;
+        let size = end - start;
+        if base + size > flash.capacity() {
+            return Err(UpdateError::OutOfRegion { offset: 0, len: size });
+        }
+        let align = F::ERASE_SIZE.max(MAX_BLOCK_SIZE);
+        if !base.is_multiple_of(align) || !size.is_multiple_of(align) {
+            return Err(UpdateError::SlotNotAligned);
+        }
+
+        flash.erase(base as u32, (base + size) as u32).map_err(UpdateError::Flash)?;
+
+        Ok(Self {
+            cell,
+            slot,
+            base,
+            region,
+            programmed: 0,
+            pending: [ERASED; MAX_BLOCK_SIZE],
+            pending_len: 0,
+            held: [ERASED; MAX_BLOCK_SIZE],
+        })
+    }
+
+    pub(crate) fn written(&self) -> usize {
+        self.programmed + self.pending_len
+    }
+
+    pub(crate) fn write<F: NorFlash>(&mut self, flash: &mut F, mut data: &[u8]) -> Result<(), UpdateError<F::Error>> {
+        let offset = self.written();
+        if offset + data.len() > self.slot_size() {
+            return Err(UpdateError::OutOfRegion { offset, len: data.len() });
+        }
+
+        while !data.is_empty() {
+            if self.pending_len == 0 && data.len() >= F::WRITE_SIZE {
+                let len = data.len() - data.len() % F::WRITE_SIZE;
+                self.program(flash, &data[..len])?;
+                data = &data[len..];
+                continue;
+            }
+
+            let len = (F::WRITE_SIZE - self.pending_len).min(data.len());
+            self.pending[self.pending_len..self.pending_len + len].copy_from_slice(&data[..len]);
+            self.pending_len += len;
+            data = &data[len..];
+            if self.pending_len == F::WRITE_SIZE {
+                self.flush_pending(flash)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn finish<F: NorFlash>(&mut self, flash: &mut F) -> Result<(), UpdateError<F::Error>> {
+        if self.pending_len != 0 {
+            self.pending[self.pending_len..F::WRITE_SIZE].fill(ERASED);
+            self.pending_len = F::WRITE_SIZE;
+            self.flush_pending(flash)?;
+        }
+
+        self.verify(flash)?;
+
+        let held = self.held_range::<F>();
+        let data = self.held;
+        flash.write(held.start as u32, &data[..held.len()]).map_err(UpdateError::Flash)
+    }
+
+    fn slot_size(&self) -> usize {
+        self.region.end_flash.max(self.region.end_header) - self.slot_start()
+    }
+
+    fn slot_start(&self) -> usize {
+        self.region.start_flash.min(self.region.start_header)
+    }
+
+    /// Driver offset of an absolute flash address inside the slot
+    fn offset_of(&self, addr: usize) -> usize {
+        self.base + addr - self.slot_start()
+    }
+
+    /// Driver offsets of the held back header bytes: whole write blocks, covering the header signature
+    fn held_range<F: NorFlash>(&self) -> core::ops::Range<usize> {
+        let start = self.offset_of(self.region.start_header);
+        start..start + 4usize.div_ceil(F::WRITE_SIZE) * F::WRITE_SIZE
+    }
+
+    fn flush_pending<F: NorFlash>(&mut self, flash: &mut F) -> Result<(), UpdateError<F::Error>> {
+        let pending = self.pending;
+        self.pending_len = 0;
+        self.program(flash, &pending[..F::WRITE_SIZE])
+    }
+
+    /// Write whole blocks at the current position, keeping the held back bytes in memory
+    fn program<F: NorFlash>(&mut self, flash: &mut F, data: &[u8]) -> Result<(), UpdateError<F::Error>> {
+        let start = self.base + self.programmed;
+        let end = start + data.len();
+        let held = self.held_range::<F>();
+
+        let before = start..end.min(held.start).max(start);
+        let after = held.end.max(start).min(end)..end;
+        for part in [before, after] {
+            if !part.is_empty() {
+                flash.write(part.start as u32, &data[part.start - start..part.end - start]).map_err(UpdateError::Flash)?;
+            }
+        }
+        for offset in start.max(held.start)..end.min(held.end) {
+            self.held[offset - held.start] = data[offset - start];
+        }
+
+        self.programmed += data.len();
+        Ok(())
+    }
+
+    /// Read flash at absolute address `addr` of the slot. Held back header bytes are taken from the memory
+    fn read<F: NorFlash>(&mut self, flash: &mut F, addr: usize, out: &mut [u8]) -> Result<(), UpdateError<F::Error>> {
+        let held = self.held_range::<F>();
+        let mut buf = [0; MAX_BLOCK_SIZE];
+        let mut done = 0;
+        while done < out.len() {
+            let offset = self.offset_of(addr + done);
+            let block = offset - offset % MAX_BLOCK_SIZE;
+            flash.read(block as u32, &mut buf).map_err(UpdateError::Flash)?;
+            if block == held.start {
+                buf[..held.len()].copy_from_slice(&self.held[..held.len()]);
+            }
+
+            let at = offset - block;
+            let n = (out.len() - done).min(MAX_BLOCK_SIZE - at);
+            out[done..done + n].copy_from_slice(&buf[at..at + n]);
+            done += n;
+        }
+        Ok(())
+    }
+
+    fn header_u32<F: NorFlash>(&mut self, flash: &mut F, offset: usize) -> Result<u32, UpdateError<F::Error>> {
+        let mut word = [0; 4];
+        self.read(flash, self.region.start_header + offset, &mut word)?;
+        Ok(u32::from_le_bytes(word))
+    }
+
+    /// Feed `len` bytes at absolute address `addr` into `f`
+    fn for_each_block<F: NorFlash>(&mut self, flash: &mut F, addr: usize, len: usize, mut f: impl FnMut(&[u8])) -> Result<(), UpdateError<F::Error>> {
+        let mut buf = [0; MAX_BLOCK_SIZE];
+        let mut done = 0;
+        while done < len {
+            let n = (len - done).min(MAX_BLOCK_SIZE);
+            self.read(flash, addr + done, &mut buf[..n])?;
+            f(&buf[..n]);
+            done += n;
+        }
+        Ok(())
+    }
+
+    fn verify<F: NorFlash>(&mut self, flash: &mut F) -> Result<(), UpdateError<F::Error>> {
+        use header_layout::*;
+
+        let signature = self.header_u32(flash, SIGNATURE)?;
+        if signature == u32::from_le_bytes([ERASED; 4]) {
+            return Err(CellError::NotFlashed.into());
+        }
+        if signature != self.cell.signature {
+            return Err(CellError::BadSignature { found: signature }.into());
+        }
+
+        let mut struct_sha256 = [0; 32];
+        self.read(flash, self.region.start_header + ABI_STRUCT_SHA256, &mut struct_sha256)?;
+        if struct_sha256 != self.cell.struct_sha256 {
+            return Err(CellError::HashMismatch { first_differing_field: None }.into());
+        }
+
+        let linked = self.header_u32(flash, LINKED_SLOT)?;
+        if linked != self.slot as u32 {
+            return Err(CellError::WrongSlot { linked }.into());
+        }
+
+        let length = self.header_u32(flash, IMAGE_LENGTH)?;
+        if length == u32::from_le_bytes([ERASED; 4]) {
+            return Err(CellError::ImageNotPatched.into());
+        }
+        if length as usize > self.region.end_flash - self.region.start_flash {
+            return Err(CellError::ImageLengthInvalid { length }.into());
+        }
+
+        let mut crc = Crc32::new();
+        self.for_each_block(flash, self.region.start_flash, length as usize, |block| crc.update(block))?;
+        if crc.finalize() != self.header_u32(flash, IMAGE_CRC32)? {
+            return Err(CellError::ImageCorrupted.into());
+        }
+
+        #[cfg(feature = "sha256")]
+        {
+            use sha2::Digest;
+
+            let mut sha = sha2::Sha256::new();
+            self.for_each_block(flash, self.region.start_flash, length as usize, |block| sha.update(block))?;
+            let mut expected = [0; 32];
+            self.read(flash, self.region.start_header + IMAGE_SHA256, &mut expected)?;
+            if sha.finalize().as_slice() != expected {
+                return Err(CellError::ImageCorrupted.into());
+            }
+        }
+
+        if let Some(public_key) = self.cell.public_key {
+            self.verify_signature(flash, public_key, length as usize)?;
+        }
+        Ok(())
+    }
+
+    fn verify_signature<F: NorFlash>(&mut self, flash: &mut F, public_key: [u8; 32], length: usize) -> Result<(), UpdateError<F::Error>> {
+        let mut trailer = SignatureTrailer::UNSIGNED;
+        self.read(flash, self.region.start_header + header_layout::SIGNATURE_TRAILER, &mut trailer.signature)?;
+        if !trailer.is_signed() {
+            return Err(CellError::ImageNotSigned.into());
+        }
+
+        #[cfg(feature = "ed25519")]
+        {
+            use sha2::Digest;
+
+            let mut sha = sha2::Sha256::new();
+            self.for_each_block(flash, self.region.start_header, header_layout::SIGNATURE_TRAILER, |block| sha.update(block))?;
+            self.for_each_block(flash, self.region.start_flash, length, |block| sha.update(block))?;
+            let digest = sha.finalize();
+
+            let key = ed25519_dalek::VerifyingKey::from_bytes(&public_key).map_err(|_| CellError::ImageSignatureInvalid)?;
+            let signature = ed25519_dalek::Signature::from_bytes(&trailer.signature);
+            key.verify_strict(&digest, &signature).map_err(|_| CellError::ImageSignatureInvalid)?;
+            Ok(())
+        }
+        #[cfg(not(feature = "ed25519"))]
+        {
+            let _ = (public_key, length);
+            Err(CellError::ImageSignatureInvalid.into())
+        }
+    }
+}
+
+/// Address of the code, performing the update. It belongs to the running cell, as every cell links its own copy of emcell
+#[inline(never)]
+fn running_code_address() -> usize {
+    running_code_address as fn() -> usize as usize
+}
+
+/// RAM-backed NOR flash of `SIZE` bytes, e.g. for host tests of update code.
+///
+/// Like real NOR flash, writes can only clear bits, so writing without erasing first corrupts the data.
+pub struct MemoryFlash<const SIZE: usize, const WRITE_SIZE: usize = 4, const ERASE_SIZE: usize = 1024> {
+    pub data: [u8; SIZE],
+}
+
+impl<const SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> MemoryFlash<SIZE, WRITE_SIZE, ERASE_SIZE> {
+    /// Erased flash
+    pub const fn new() -> Self {
+        Self { data: [ERASED; SIZE] }
+    }
+
+    fn check(&self, offset: u32, len: usize, align: usize) -> Result<core::ops::Range<usize>, NorFlashErrorKind> {
+        let offset = offset as usize;
+        if !offset.is_multiple_of(align) || !len.is_multiple_of(align) {
+            return Err(NorFlashErrorKind::NotAligned);
+        }
+        if offset + len > SIZE {
+            return Err(NorFlashErrorKind::OutOfBounds);
+        }
+        Ok(offset..offset + len)
+    }
+}
+
+impl<const SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> Default for MemoryFlash<SIZE, WRITE_SIZE, ERASE_SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> ErrorType for MemoryFlash<SIZE, WRITE_SIZE, ERASE_SIZE> {
+    type Error = NorFlashErrorKind;
+}
+
+impl<const SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> ReadNorFlash for MemoryFlash<SIZE, WRITE_SIZE, ERASE_SIZE> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let range = self.check(offset, bytes.len(), Self::READ_SIZE)?;
+        bytes.copy_from_slice(&self.data[range]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        SIZE
+    }
+}
+
+impl<const SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> NorFlash for MemoryFlash<SIZE, WRITE_SIZE, ERASE_SIZE> {
+    const WRITE_SIZE: usize = WRITE_SIZE;
+    const ERASE_SIZE: usize = ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        let range = self.check(from, to.saturating_sub(from) as usize, ERASE_SIZE)?;
+        self.data[range].fill(ERASED);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let range = self.check(offset, bytes.len(), WRITE_SIZE)?;
+        for (cell, byte) in self.data[range].iter_mut().zip(bytes) {
+            *cell &= byte;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,65 @@
+//! `Retained` on heap storage instead of the no-init region
+
+use emcell::retained::{Retained, Storage};
+
+/// Retained value with the given tag, stored in `storage`
+fn retained<T: Copy>(storage: *mut Storage<T>, init: T, tag: u32) -> Retained<T> {
+    unsafe { Retained::_new(storage, init, tag) }
+}
+
+fn storage<T>() -> *mut Storage<T> {
+    Box::into_raw(Box::new(Storage::uninit()))
+}
+
+#[test]
+fn initial_value_before_set() {
+    let value = retained(storage(), 7u32, 0x1235);
+
+    assert!(!value.is_retained());
+    assert_eq!(value.get(), 7);
+}
+
+#[test]
+fn set_value_is_retained() {
+    let storage = storage();
+    let value = retained(storage, 7u32, 0x1235);
+    value.set(42);
+    assert!(value.is_retained());
+    assert_eq!(value.get(), 42);
+
+    // same static after a reset
+    let value = retained(storage, 7u32, 0x1235);
+    assert!(value.is_retained());
+    assert_eq!(value.get(), 42);
+}
+
+#[test]
+fn reset_returns_initial_value() {
+    let value = retained(storage(), (1u8, 2u16), 0x1235);
+    value.set((3, 4));
+    value.reset();
+
+    assert!(!value.is_retained());
+    assert_eq!(value.get(), (1, 2));
+}
+
+#[test]
+fn value_of_other_static_is_not_taken() {
+    let storage = storage();
+    retained(storage, 7u32, 0x1235).set(42);
+
+    let other = retained(storage, 9u32, 0x5679);
+    assert!(!other.is_retained());
+    assert_eq!(other.get(), 9);
+}
+
+#[emcell::retained]
+static COUNTER: u32 = 5;
+
+#[test]
+fn retained_attribute() {
+    assert_eq!(COUNTER.get(), 5);
+    COUNTER.set(COUNTER.get() + 1);
+    assert!(COUNTER.is_retained());
+    assert_eq!(COUNTER.get(), 6);
+}
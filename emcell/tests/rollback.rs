@@ -0,0 +1,129 @@
+#[macro_use]
+extern crate emcell_macro;
+
+use emcell::meta::{CellDefMeta, CellVersion, DeviceConfigMeta, ImageInfo};
+use emcell::rollback::{self, MemoryRollbackStore, RollbackStore};
+use emcell::{Cell, CellError, CheckPolicy, Slot, WithSignature};
+
+emcell_configuration! {
+    device!{
+        initial_stack_ptr: 0x2000_1000,
+        ram_range_start: 0x2000_0000,
+        ram_range_end: 0x2000_8000,
+        flash_range_start: 0x0800_0000,
+        flash_range_end: 0x0801_0000,
+    }
+
+    #[cell(primary)]
+    #[ram_region(0x1000, 0x2000)]
+    #[flash_region(0x0, 0x4000)]
+    pub struct Cell1 {
+    }
+
+    #[cell]
+    #[ram_region(0x2000, 0x3000)]
+    #[flash_region(0x4000, 0x8000)]
+    pub struct Cell2 {
+    }
+}
+
+/// Header of Cell2 with a valid signature and ABI, which is not located in flash
+struct FakeHeader {
+    version: CellVersion,
+    image: ImageInfo,
+    security_counter: u32,
+}
+
+impl FakeHeader {
+    fn new(security_counter: u32) -> Self {
+        Self {
+            version: CellVersion::new("cell2", "1.0.0", None, None, 1),
+            image: ImageInfo::UNPATCHED,
+            security_counter,
+        }
+    }
+}
+
+unsafe impl WithSignature for FakeHeader {
+    const VALID_SIGNATURE: u32 = <Cell2 as WithSignature>::VALID_SIGNATURE;
+}
+
+unsafe impl Cell for FakeHeader {
+    const CUR_META: CellDefMeta = <Cell2 as Cell>::CUR_META;
+    const CELLS_META: &'static [CellDefMeta] = <Cell2 as Cell>::CELLS_META;
+    const DEVICE_CONFIG: DeviceConfigMeta = <Cell2 as Cell>::DEVICE_CONFIG;
+    fn version(&self) -> &CellVersion {
+        &self.version
+    }
+    fn image_info(&self) -> &ImageInfo {
+        &self.image
+    }
+    fn security_counter(&self) -> u32 {
+        self.security_counter
+    }
+    fn linked_slot(&self) -> u32 {
+        Slot::A as u32
+    }
+    fn check_header(&self) -> Result<(), CellError> {
+        Ok(())
+    }
+    unsafe fn call_init(&self, _init_memory: bool) -> Result<(), CellError> {
+        Ok(())
+    }
+    fn boot_entry(&self) -> Option<fn() -> !> {
+        None
+    }
+    fn slot(&self) -> Result<Slot, CellError> {
+        Ok(Slot::A)
+    }
+}
+
+fn store_with_min(min_counter: u32) -> MemoryRollbackStore<2> {
+    let mut store = MemoryRollbackStore::new();
+    store.set_min_counter("Cell2", min_counter).unwrap();
+    store
+}
+
+#[test]
+fn check_compares_counter_with_minimum() {
+    let store = store_with_min(5);
+    assert_eq!(rollback::check(&FakeHeader::new(4), &store), Err(CellError::RolledBack { counter: 4, min_counter: 5 }));
+    assert_eq!(rollback::check(&FakeHeader::new(5), &store), Ok(()));
+    assert_eq!(rollback::check(&FakeHeader::new(6), &store), Ok(()));
+}
+
+#[test]
+fn check_accepts_any_counter_of_unknown_cell() {
+    let store = MemoryRollbackStore::<1>::new();
+    assert_eq!(rollback::check(&FakeHeader::new(0), &store), Ok(()));
+}
+
+#[test]
+fn commit_only_raises_minimum() {
+    let mut store = MemoryRollbackStore::<1>::new();
+    rollback::commit(&FakeHeader::new(3), &mut store).unwrap();
+    assert_eq!(store.min_counter("Cell2"), 3);
+
+    rollback::commit(&FakeHeader::new(2), &mut store).unwrap();
+    assert_eq!(store.min_counter("Cell2"), 3);
+
+    rollback::commit(&FakeHeader::new(7), &mut store).unwrap();
+    assert_eq!(store.min_counter("Cell2"), 7);
+    assert_eq!(rollback::check(&FakeHeader::new(3), &store), Err(CellError::RolledBack { counter: 3, min_counter: 7 }));
+}
+
+#[test]
+fn commit_fails_if_store_is_full() {
+    let mut store = MemoryRollbackStore::<0>::new();
+    assert!(rollback::commit(&FakeHeader::new(1), &mut store).is_err());
+}
+
+#[test]
+fn validate_rejects_rolled_back_cell_with_policy() {
+    let store = store_with_min(5);
+    let policy = CheckPolicy::new().with_rollback(&store);
+    assert_eq!(FakeHeader::new(4).validate(&policy), Err(CellError::RolledBack { counter: 4, min_counter: 5 }));
+    assert_eq!(FakeHeader::new(5).validate(&policy), Ok(()));
+    // without a store the counter is not checked
+    assert_eq!(FakeHeader::new(4).validate(&CheckPolicy::new()), Ok(()));
+}
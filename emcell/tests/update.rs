@@ -0,0 +1,224 @@
+#![cfg(feature = "update")]
+
+#[macro_use]
+extern crate emcell_macro;
+
+use emcell::integrity::crc32;
+use emcell::meta::{header_layout, HEADER_SIZE};
+use emcell::update::{MemoryFlash, Update, UpdateError};
+use emcell::{Cell, CellError, Slot};
+use embedded_storage::nor_flash::NorFlash;
+
+emcell_configuration! {
+    device!{
+        initial_stack_ptr: 0x2000_1000,
+        ram_range_start: 0x2000_0000,
+        ram_range_end: 0x2000_8000,
+        flash_range_start: 0x0800_0000,
+        flash_range_end: 0x0801_0000,
+    }
+
+    #[cell(primary)]
+    #[ram_region(0x1000, 0x2000)]
+    #[flash_region(0x0, 0x4000)]
+    pub struct Cell1 {
+    }
+
+    #[cell]
+    #[ram_region(0x2000, 0x3000)]
+    #[flash_region(0x4000, 0x8000)]
+    #[flash_region(0x8000, 0xC000)]
+    pub struct Cell2 {
+    }
+
+    // not aligned to the 1 KiB erase sectors of the test flash
+    #[cell]
+    #[ram_region(0x3000, 0x4000)]
+    #[flash_region(0xC000, 0xC600)]
+    pub struct Cell3 {
+    }
+}
+
+const FLASH_SIZE: usize = 0x1_0000;
+const SLOT_SIZE: usize = 0x4000;
+
+type Flash = MemoryFlash<FLASH_SIZE>;
+
+/// Driver offset of the slot start of Cell2
+fn slot_offset(slot: Slot) -> usize {
+    let (start, _) = Cell2::CUR_META.slot_flash_range(slot, &Cell2::DEVICE_CONFIG).unwrap();
+    start - Cell2::DEVICE_CONFIG.flash_range_start
+}
+
+/// Patched image of Cell2, linked for `slot`: header region followed by `payload`
+fn image(slot: Slot, payload: &[u8]) -> Vec<u8> {
+    use header_layout::*;
+
+    let mut image = vec![0; HEADER_SIZE];
+    let mut put = |offset: usize, bytes: &[u8]| image[offset..offset + bytes.len()].copy_from_slice(bytes);
+    put(SIGNATURE, &Cell2::CUR_META.signature.to_le_bytes());
+    put(ABI_STRUCT_SHA256, &Cell2::CUR_META.struct_sha256);
+    put(LINKED_SLOT, &(slot as u32).to_le_bytes());
+    put(IMAGE_LENGTH, &(payload.len() as u32).to_le_bytes());
+    put(IMAGE_CRC32, &crc32(payload).to_le_bytes());
+    #[cfg(feature = "sha256")]
+    {
+        use sha2::Digest;
+        put(IMAGE_SHA256, sha2::Sha256::digest(payload).as_slice());
+    }
+    image.extend_from_slice(payload);
+    image
+}
+
+fn payload() -> Vec<u8> {
+    (0..3000u32).map(|i| (i * 7 + 3) as u8).collect()
+}
+
+/// Header signature word of `slot`, erased if the slot is not bootable
+fn header_signature(flash: &[u8], slot: Slot) -> u32 {
+    let offset = slot_offset(slot) + header_layout::SIGNATURE;
+    u32::from_le_bytes(flash[offset..offset + 4].try_into().unwrap())
+}
+
+fn write_all<F: NorFlash>(update: &mut Update<F>, image: &[u8], chunk: usize) -> Result<(), UpdateError<F::Error>> {
+    for chunk in image.chunks(chunk) {
+        update.write(chunk)?;
+    }
+    Ok(())
+}
+
+fn update_in_blocks<const WRITE_SIZE: usize>() {
+    let mut flash = MemoryFlash::<FLASH_SIZE, WRITE_SIZE>::new();
+    let image = image(Slot::B, &payload());
+
+    // odd chunks do not line up with write blocks
+    let mut update = Update::begin(&mut flash, &Cell2::CUR_META, &Cell2::DEVICE_CONFIG, Slot::B).unwrap();
+    write_all(&mut update, &image, 7).unwrap();
+    assert_eq!(update.written(), image.len());
+    update.finish().unwrap();
+
+    let start = slot_offset(Slot::B);
+    assert_eq!(&flash.data[start..start + image.len()], &image[..]);
+    assert!(flash.data[start + image.len()..start + SLOT_SIZE].iter().all(|b| *b == 0xFF));
+}
+
+#[test]
+fn update_writes_whole_image() {
+    update_in_blocks::<1>();
+    update_in_blocks::<4>();
+    update_in_blocks::<16>();
+    update_in_blocks::<64>();
+}
+
+#[test]
+fn header_signature_is_written_in_finish() {
+    let mut flash = Flash::new();
+    let image = image(Slot::A, &payload());
+
+    let mut update = Update::begin(&mut flash, &Cell2::CUR_META, &Cell2::DEVICE_CONFIG, Slot::A).unwrap();
+    // update is abandoned before finish
+    write_all(&mut update, &image, 256).unwrap();
+    // held back block is the only part of the image missing in flash
+    let start = slot_offset(Slot::A);
+    assert_eq!(header_signature(&flash.data, Slot::A), u32::MAX);
+    assert_eq!(&flash.data[start + 4..start + image.len()], &image[4..]);
+
+    let mut update = Update::begin(&mut flash, &Cell2::CUR_META, &Cell2::DEVICE_CONFIG, Slot::A).unwrap();
+    write_all(&mut update, &image, 256).unwrap();
+    update.finish().unwrap();
+    assert_eq!(header_signature(&flash.data, Slot::A), Cell2::CUR_META.signature);
+}
+
+#[test]
+fn interrupted_update_leaves_header_not_flashed() {
+    let mut flash = Flash::new();
+    let image = image(Slot::A, &payload());
+
+    // previous image is erased by begin, the new one is cut off
+    let mut update = Update::begin(&mut flash, &Cell2::CUR_META, &Cell2::DEVICE_CONFIG, Slot::A).unwrap();
+    write_all(&mut update, &image, 256).unwrap();
+    update.finish().unwrap();
+    let mut update = Update::begin(&mut flash, &Cell2::CUR_META, &Cell2::DEVICE_CONFIG, Slot::A).unwrap();
+    write_all(&mut update, &image[..HEADER_SIZE + 100], 256).unwrap();
+    assert_eq!(header_signature(&flash.data, Slot::A), u32::MAX);
+
+    let update = Update::begin(&mut flash, &Cell2::CUR_META, &Cell2::DEVICE_CONFIG, Slot::A).unwrap();
+    assert_eq!(update.finish(), Err(UpdateError::Rejected(CellError::NotFlashed)));
+}
+
+#[test]
+fn image_for_other_slot_is_rejected() {
+    let mut flash = Flash::new();
+    let image = image(Slot::B, &payload());
+
+    let mut update = Update::begin(&mut flash, &Cell2::CUR_META, &Cell2::DEVICE_CONFIG, Slot::A).unwrap();
+    write_all(&mut update, &image, 256).unwrap();
+    assert_eq!(update.finish(), Err(UpdateError::Rejected(CellError::WrongSlot { linked: Slot::B as u32 })));
+    assert_eq!(header_signature(&flash.data, Slot::A), u32::MAX);
+}
+
+#[test]
+fn corrupted_image_is_rejected() {
+    let mut flash = Flash::new();
+    let mut image = image(Slot::A, &payload());
+    image[HEADER_SIZE + 1000] ^= 0x01;
+
+    let mut update = Update::begin(&mut flash, &Cell2::CUR_META, &Cell2::DEVICE_CONFIG, Slot::A).unwrap();
+    write_all(&mut update, &image, 256).unwrap();
+    assert_eq!(update.finish(), Err(UpdateError::Rejected(CellError::ImageCorrupted)));
+    assert_eq!(header_signature(&flash.data, Slot::A), u32::MAX);
+}
+
+#[test]
+fn image_of_other_cell_is_rejected() {
+    let mut flash = Flash::new();
+    let mut image = image(Slot::A, &payload());
+    image[..4].copy_from_slice(&Cell1::CUR_META.signature.to_le_bytes());
+
+    let mut update = Update::begin(&mut flash, &Cell2::CUR_META, &Cell2::DEVICE_CONFIG, Slot::A).unwrap();
+    write_all(&mut update, &image, 256).unwrap();
+    assert_eq!(update.finish(), Err(UpdateError::Rejected(CellError::BadSignature { found: Cell1::CUR_META.signature })));
+    assert_eq!(header_signature(&flash.data, Slot::A), u32::MAX);
+}
+
+#[test]
+fn write_past_slot_end_is_rejected() {
+    let mut flash = Flash::new();
+
+    let mut update = Update::begin(&mut flash, &Cell2::CUR_META, &Cell2::DEVICE_CONFIG, Slot::A).unwrap();
+    update.write(&[0; SLOT_SIZE - 10]).unwrap();
+    assert_eq!(update.write(&[0; 11]), Err(UpdateError::OutOfRegion { offset: SLOT_SIZE - 10, len: 11 }));
+    update.write(&[0; 10]).unwrap();
+}
+
+#[test]
+fn slot_outside_of_flash_is_rejected() {
+    let mut flash = MemoryFlash::<0x6000>::new();
+    let update = Update::begin(&mut flash, &Cell2::CUR_META, &Cell2::DEVICE_CONFIG, Slot::A);
+    assert_eq!(update.err(), Some(UpdateError::OutOfRegion { offset: 0, len: SLOT_SIZE }));
+}
+
+#[test]
+fn slot_not_aligned_to_erase_sectors_is_rejected() {
+    let mut flash = MemoryFlash::<FLASH_SIZE, 4, 0x8000>::new();
+    let update = Update::begin(&mut flash, &Cell2::CUR_META, &Cell2::DEVICE_CONFIG, Slot::A);
+    assert_eq!(update.err(), Some(UpdateError::SlotNotAligned));
+
+    let mut flash = Flash::new();
+    let update = Update::begin(&mut flash, &Cell3::CUR_META, &Cell3::DEVICE_CONFIG, Slot::A);
+    assert_eq!(update.err(), Some(UpdateError::SlotNotAligned));
+}
+
+#[test]
+fn large_write_size_is_rejected() {
+    let mut flash = MemoryFlash::<FLASH_SIZE, 128>::new();
+    let update = Update::begin(&mut flash, &Cell2::CUR_META, &Cell2::DEVICE_CONFIG, Slot::A);
+    assert_eq!(update.err(), Some(UpdateError::UnsupportedBlockSize));
+}
+
+#[test]
+fn missing_slot_is_rejected() {
+    let mut flash = Flash::new();
+    let update = Update::begin(&mut flash, &Cell3::CUR_META, &Cell3::DEVICE_CONFIG, Slot::B);
+    assert_eq!(update.err(), Some(UpdateError::Rejected(CellError::NoSuchSlot)));
+}
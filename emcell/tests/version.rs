@@ -0,0 +1,28 @@
+use core::cmp::Ordering;
+use emcell::meta::CellVersion;
+
+fn version(version: &str) -> CellVersion {
+    CellVersion::new("cell", version, None, None, 1)
+}
+
+#[test]
+fn release_is_newer_than_pre_release() {
+    assert_eq!(version("1.2.3").cmp_precedence(&version("1.2.3-rc.1")), Ordering::Greater);
+    assert_eq!(version("1.2.3-rc.1").cmp_precedence(&version("1.2.3")), Ordering::Less);
+    assert_eq!(version("1.2.4-alpha").cmp_precedence(&version("1.2.3")), Ordering::Greater);
+}
+
+#[test]
+fn pre_release_identifiers_follow_semver() {
+    // example from semver 2.0.0, 11.4
+    let ordered = ["1.0.0-alpha", "1.0.0-alpha.1", "1.0.0-alpha.beta", "1.0.0-beta", "1.0.0-beta.2", "1.0.0-beta.11",
+        "1.0.0-rc.1", "1.0.0"];
+    for pair in ordered.windows(2) {
+        assert_eq!(version(pair[0]).cmp_precedence(&version(pair[1])), Ordering::Less, "{} < {}", pair[0], pair[1]);
+    }
+}
+
+#[test]
+fn build_metadata_is_ignored() {
+    assert_eq!(version("1.0.0-rc.1+abc").cmp_precedence(&version("1.0.0-rc.1+def")), Ordering::Equal);
+}
@@ -5,20 +5,20 @@
 
 use at32f4xx_pac::at32f437::gpioa::cfgr::IOMC0_A;
 
-use emcell_macro::{define_primary_header, extern_header_forward};
-use cells_defs::{Cell1, Cell2};
+use emcell::boot::{BootDecision, BootManager, BootTarget};
+use emcell_macro::define_primary_header;
+use cells_defs::{Cell1, Cell2, BOOT_COUNT};
 use cortex_m::asm::delay;
 
 extern crate panic_halt;
 extern crate at32f4xx_pac;
 
 define_primary_header!{
+    #[pre_switch(led_off)]
     Cell1 {
     }
 }
 
-extern_header_forward!(Cell2Wrapper: Cell2);
-
 
 fn gpio_cfgr() {
 
@@ -43,14 +43,14 @@ fn led_off() {
 unsafe fn main() -> ! {
     gpio_cfgr();
     led_on();
+    BOOT_COUNT.set(BOOT_COUNT.get().wrapping_add(1));
 
-    if let Some(cell2) = Cell2Wrapper::new() {
-        cell2.switch_vectors_and_run()
-
-    }
-    else {
+    let mailbox = emcell::boot::mailbox::<Cell1>().unwrap();
+    let report = BootManager::new(&[BootTarget::of::<Cell2>(1)], mailbox).select();
+    if report.decision == BootDecision::NothingToBoot {
         loop {
             delay(1_000_000);
         }
     }
+    report.boot()
 }
\ No newline at end of file
@@ -117,6 +117,9 @@ pub fn run() -> ! {
 
 
     if let Some(cell3) = Cell3Wrapper::new() {
+        // cell2 works, keep booting this image
+        emcell::confirm_boot::<Cell2>();
+        info!("cell2: found {}", cell3.version());
         info!("cell2: b from cell3: {}", cell3.b);
         info!("cell2: Accessing static...");
         let v = (cell3.access_static)();
@@ -4,6 +4,7 @@
 extern crate emcell_macro;
 
 emcell_configuration! {
+    #[noinit_region(0x1_7C00, 0x1_8000)]
     device!{
         initial_stack_ptr: 0x2000_6000,
 
@@ -22,6 +23,7 @@ emcell_configuration! {
 
     #[cell]
     #[ram_region(0x6400, 0xA000)]
+    #[stack(0x1000)]
     #[flash_region(0x0_4000, 0xF_1000)]
     pub struct Cell2 {
         #[switch_vectors]
@@ -39,3 +41,7 @@ emcell_configuration! {
         pub access_static: fn() -> u32,
     }
 }
+
+/// Number of resets since power-on, counted by Cell1
+#[emcell::retained]
+pub static BOOT_COUNT: u32 = 0;
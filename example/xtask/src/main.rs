@@ -0,0 +1,6 @@
+//! `emcell` host tool with the cells definitions of the example, e.g.
+//! `cargo run -p xtask -- verify target/thumbv7em-none-eabihf/release/cell1 ...`
+
+fn main() {
+    emcell_cli::main(Some(emcell_cli::CellsDefs::of::<cells_defs::PrimaryCell>()));
+}